@@ -4,11 +4,61 @@ use crate::emulator as emulator;
 use eframe::{egui, epi};
 
 
-use emulator::cpu::{self, CPU6502};
-use emulator::disassembler::{htb_option};
+use emulator::cpu::CPU6502;
+use emulator::disassembler::{self, htb_option};
+use emulator::formats::{self, LoadImage, StartAction};
+#[cfg(not(target_arch = "wasm32"))]
+use emulator::label_files::{self, LabelFormat};
+use emulator::symbols::SymbolTable;
+
+mod coverage_window;
+mod disassembly_window;
+mod editor;
+mod heatmap_window;
+mod profiler_window;
+mod registers;
+mod runner;
+mod source;
+mod stack_view;
+mod symbols_window;
+mod trace;
+
+// instructions Step Over/Out and Run To give up after
+const STEP_LIMIT : u64 = 1_000_000;
+
+/// Format of the pasted object code
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ObjFormat {
+    /// Plain hex bytes, loaded at the Start Address
+    Hex,
+    IntelHex,
+    SRecord,
+}
+
+impl ObjFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            ObjFormat::Hex => "Hex bytes",
+            ObjFormat::IntelHex => "Intel HEX",
+            ObjFormat::SRecord => "S-record",
+        }
+    }
+}
+
+fn start_action_name(action: StartAction) -> &'static str {
+    match action {
+        StartAction::None => "Leave PC",
+        StartAction::SetPc => "Set PC",
+        StartAction::SetResetVector => "Set reset vector",
+    }
+}
 
 pub struct EmuDisplayApp {
     pc_change_str : String,
+    run_to_str : String,
+    step_status : String,
+    runner : runner::Runner,
+    register_panel: registers::RegisterPanel,
     load_start_str: String,
     read_addr: u16,
     read_addr_input: String,
@@ -23,13 +73,31 @@ pub struct EmuDisplayApp {
     from_2 : u16,
     to_2 : u16,
     pub obj_string: String,
+    obj_format: ObjFormat,
+    start_action: StartAction,
+    load_status: String,
+    file_path: String,
     pub cpu: CPU6502,
+    pub symbols: SymbolTable,
+    asm_editor: editor::AsmEditor,
+    source_view: source::SourceView,
+    symbols_window: symbols_window::SymbolsWindow,
+    trace_log: trace::TraceLog,
+    stack_view: stack_view::StackView,
+    profiler_window: profiler_window::ProfilerWindow,
+    coverage_window: coverage_window::CoverageWindow,
+    heatmap_window: heatmap_window::HeatmapWindow,
+    disassembly_window: disassembly_window::DisassemblyWindow,
 }
 
 impl Default for EmuDisplayApp {
     fn default() -> Self {
         Self {
             pc_change_str: "8000".to_owned(),
+            run_to_str: "8000".to_owned(),
+            step_status: String::new(),
+            runner: runner::Runner::default(),
+            register_panel: registers::RegisterPanel::default(),
             load_start_str: "8000".to_owned(),
             read_addr: 0x8000,
             read_addr_input: "8000".to_owned(),
@@ -44,7 +112,21 @@ impl Default for EmuDisplayApp {
             from_2: 0x0100,
             to_2: 0x01ff,
             obj_string: "9A039A2E".to_owned(),
+            obj_format: ObjFormat::Hex,
+            start_action: StartAction::SetPc,
+            load_status: String::new(),
+            file_path: String::new(),
             cpu: CPU6502::create_cpu_and_bus(0x8000),
+            symbols: SymbolTable::new(),
+            asm_editor: editor::AsmEditor::default(),
+            source_view: source::SourceView::default(),
+            symbols_window: symbols_window::SymbolsWindow::default(),
+            trace_log: trace::TraceLog::default(),
+            stack_view: stack_view::StackView::default(),
+            profiler_window: profiler_window::ProfilerWindow::default(),
+            coverage_window: coverage_window::CoverageWindow::default(),
+            heatmap_window: heatmap_window::HeatmapWindow::default(),
+            disassembly_window: disassembly_window::DisassemblyWindow::default(),
         }
     }
 }
@@ -73,6 +155,10 @@ impl epi::App for EmuDisplayApp {
     fn update(&mut self, ctx: &egui::CtxRef, frame: &mut epi::Frame<'_>) {
         let EmuDisplayApp {
             pc_change_str,
+            run_to_str,
+            step_status,
+            runner,
+            register_panel,
             load_start_str,
             read_addr,
             read_addr_input,
@@ -87,14 +173,33 @@ impl epi::App for EmuDisplayApp {
             from_2,
             to_2,
             obj_string,
+            obj_format,
+            start_action,
+            load_status,
+            file_path,
             cpu,
+            symbols,
+            asm_editor,
+            source_view,
+            symbols_window,
+            trace_log,
+            stack_view,
+            profiler_window,
+            coverage_window,
+            heatmap_window,
+            disassembly_window,
         } = self;
 
+        // a frame's share of Continuous Run before anything is drawn
+        runner.update(cpu, ctx.input().time);
+        if runner.running {
+            ctx.request_repaint();
+        }
+
         // Control Panel
         egui::SidePanel::left("side_panel", 230.0).show(ctx, |ui| {
             ui.heading("Control Panel");
             
-            let cpu_internal_label_color = egui::Color32::LIGHT_GRAY; 
             ui.separator();
             
             ui.horizontal(|ui| {
@@ -116,29 +221,149 @@ impl epi::App for EmuDisplayApp {
 
             });
 
+            ui.separator();
+
+            egui::combo_box_with_label(ui, "Format", obj_format.name(), |ui| {
+                for format in [ObjFormat::Hex, ObjFormat::IntelHex, ObjFormat::SRecord].iter() {
+                    ui.selectable_value(obj_format, *format, format.name());
+                }
+            });
+            // used by every format with a start address (not plain hex)
+            egui::combo_box_with_label(ui, "On load", start_action_name(*start_action), |ui| {
+                for action in [StartAction::None, StartAction::SetPc, StartAction::SetResetVector].iter() {
+                    ui.selectable_value(start_action, *action, start_action_name(*action));
+                }
+            });
+
             ui.separator();
             
             // Load Program
             ui.vertical_centered(|ui| {
                 if ui.button("Load object code").clicked() {
+                    let parsed = match obj_format {
+                        ObjFormat::Hex => None,
+                        ObjFormat::IntelHex => Some(formats::ihex::parse(obj_string)),
+                        ObjFormat::SRecord => Some(formats::srec::parse(obj_string)),
+                    };
+
+                    match parsed {
+                        Some(Ok(image)) => {
+                            image.load_into(cpu, *start_action);
+                            coverage_window.image = image.ranges();
+                            *load_status = format!("Loaded {} bytes", image.len());
+                        }
+                        // keeps the text, the error has a line number
+                        Some(Err(msg)) => *load_status = msg,
+
+                        None => match (htb_option(obj_string), htb_option(load_start_str)){
+                            // updates memory panel only if valid
+                            (Some(obj_code),Some(load_vec)) => {
+                                
+                                let load_addr = extract_from_hex(load_vec);
+                                let end = (load_addr as usize + obj_code.len().max(1)).min(0x10000) - 1;
+                                coverage_window.image = vec![(load_addr, end as u16)];
+                                match cpu.specific_load(obj_code, load_addr) {
+                                    Ok(_) => (),
+                                    Err(msg) => *obj_string = msg.into()
+                                }
+
+                            }
+                            _ => {
+                                *obj_string = "Invalid Input".into();
+                                *load_start_str = "8000".into();
+                            }
+                        },
+                    }
+                }
+
+                // first memory panel's range into the text box
+                if *obj_format != ObjFormat::Hex && ui.button("Export Memory Range 1").clicked() {
+                    let image = LoadImage::from_ranges(cpu, &[(*from_1, *to_1)], None);
+                    *obj_string = match obj_format {
+                        ObjFormat::IntelHex => formats::ihex::write(&image),
+                        _ => formats::srec::write(&image),
+                    };
+                    *load_status = format!("Exported ${:04X}-${:04X}", from_1, to_1);
+                }
+
+                // no file system on the web
+                #[cfg(not(target_arch = "wasm32"))]
+                ui.horizontal(|ui| {
+                    let load = ui.button("Load File:").clicked();
+                    ui.text_edit_singleline(file_path);
+
+                    let label_format = LabelFormat::from_extension(file_path.trim());
+
+                    if load && file_path.trim().ends_with(".dbg") {
+                        *load_status = source_view.load_debug_info(file_path.trim(), symbols).unwrap_or_else(|e| e);
+                    } else if let (true, Some(format)) = (load, label_format) {
+                        let imported = std::fs::read_to_string(file_path.trim()).map_err(|e| e.to_string())
+                            .and_then(|text| label_files::parse(format, &text, cpu.cartridge().map(|x| x.prg().len())));
 
-                    match (htb_option(obj_string), htb_option(load_start_str)){
-                        // updates memory panel only if valid
-                        (Some(obj_code),Some(load_vec)) => {
-                            
-                            let load_addr = extract_from_hex(load_vec);
-                            match cpu.specific_load(obj_code, load_addr) {
-                                Ok(_) => (),
-                                Err(msg) => *obj_string = msg.into()
+                        *load_status = match imported {
+                            Ok(table) => {
+                                symbols.merge(&table);
+                                format!("Imported {} {} labels", table.len(), format.name())
                             }
+                            Err(msg) => msg,
+                        };
+                    } else if load {
+                        let start = htb_option(load_start_str).map(extract_from_hex).unwrap_or(0x8000);
+                        let loaded = std::fs::read(file_path.trim()).map_err(|e| e.to_string())
+                            .and_then(|bytes| formats::load_file(file_path.trim(), &bytes, cpu, start, *start_action));
 
+                        if let Ok(loaded) = &loaded {
+                            coverage_window.image = loaded.ranges.clone();
                         }
-                        _ => {
-                            *obj_string = "Invalid Input".into();
-                            *load_start_str = "8000".into();
-                        }
-                    };
+                        *load_status = match loaded {
+                            Ok(loaded) if loaded.symbols.is_empty() => format!("Loaded {} bytes", loaded.len),
+                            Ok(loaded) => {
+                                symbols.merge(&loaded.symbols);
+                                format!("Loaded {} bytes, {} symbols", loaded.len, loaded.symbols.len())
+                            }
+                            Err(msg) => msg,
+                        };
+                    }
+                });
+
+                if !load_status.is_empty() {
+                    ui.small(load_status.as_str());
                 }
+
+                if ui.button("Assembly Editor").clicked() {
+                    asm_editor.open = !asm_editor.open;
+                }
+
+                if ui.button("Source & Breakpoints").clicked() {
+                    source_view.open = !source_view.open;
+                }
+
+                if ui.button("Disassembly").clicked() {
+                    disassembly_window.open = !disassembly_window.open;
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Symbols").clicked() {
+                        symbols_window.open = !symbols_window.open;
+                    }
+                    if ui.button("Trace Log").clicked() {
+                        trace_log.open = !trace_log.open;
+                    }
+                    if ui.button("Stack").clicked() {
+                        stack_view.open = !stack_view.open;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Profiler").clicked() {
+                        profiler_window.open = !profiler_window.open;
+                    }
+                    if ui.button("Coverage").clicked() {
+                        coverage_window.open = !coverage_window.open;
+                    }
+                    if ui.button("Heatmap").clicked() {
+                        heatmap_window.open = !heatmap_window.open;
+                    }
+                });
             });
 
             ui.add(egui::Separator::new().spacing(6.));
@@ -147,42 +372,7 @@ impl epi::App for EmuDisplayApp {
 
             // Internal Registers
             ui.vertical_centered(|ui|{
-                let pc = format!("PC:  ${:04X}", cpu.pc);
-                let a = format!("A:    ${:02X}", cpu.reg_a);
-                let x = format!("X:    ${:02X}", cpu.reg_x);
-                let y = format!("Y:    ${:02X}", cpu.reg_y);
-                let sp = format!("SP:  ${:04X}", cpu.stk_ptr as u16 + CPU6502::STACK_OFFSET);
-                let register_label = 
-                    |param : &str| {
-                        egui::Label::new(param).text_color(cpu_internal_label_color).heading()
-                    };
-                
-                let flag_label = 
-                    |letter : &str, exists: bool| {
-                        let color = match exists {
-                            true => egui::Color32::GREEN,
-                            false => egui::Color32::RED,
-                        };
-                        egui::Label::new(letter).text_color(color)
-                    };
-
-                // Status horizontal
-                ui.horizontal(|ui| {
-                    ui.add(register_label("   Status: "));
-                    ui.add(flag_label("N", cpu.status.contains(cpu::Flags::N)));
-                    ui.add(flag_label("V", cpu.status.contains(cpu::Flags::V)));
-                    ui.add(flag_label("_", cpu.status.contains(cpu::Flags::U)));
-                    ui.add(flag_label("B", cpu.status.contains(cpu::Flags::B)));
-                    ui.add(flag_label("D", cpu.status.contains(cpu::Flags::D)));
-                    ui.add(flag_label("I", cpu.status.contains(cpu::Flags::I)));
-                    ui.add(flag_label("Z", cpu.status.contains(cpu::Flags::Z)));
-                    ui.add(flag_label("C", cpu.status.contains(cpu::Flags::C)));
-                });
-                ui.add(register_label(&pc));
-                ui.add(register_label(&a));
-                ui.add(register_label(&x));
-                ui.add(register_label(&y));
-                ui.add(register_label(&sp));
+                register_panel.show(ui, cpu);
             });
 
             // Command Buttons
@@ -191,10 +381,37 @@ impl epi::App for EmuDisplayApp {
 
                 ui.label("");
                 if ui.button("Next Step").clicked() {
+                    trace_log.record(cpu, symbols);
                     cpu.execute_step();
+                    step_status.clear();
+                }
+                if ui.button("Step Over").clicked() {
+                    *step_status = cpu.step_over(STEP_LIMIT).err().map(|x| x.to_string()).unwrap_or_default();
+                }
+                if ui.button("Step Out").clicked() {
+                    *step_status = cpu.step_out(STEP_LIMIT).err().map(|x| x.to_string()).unwrap_or_default();
+                }
+                if ui.add(egui::Button::new("Continuous Run").enabled(!runner.running)).clicked() {
+                    runner.start();
+                    step_status.clear();
                 }
-                if ui.button("Continuous Run").clicked(){
-                    cpu.run_until_brk();
+                ui.horizontal(|ui| {
+                    if ui.add(egui::Button::new("Pause").enabled(runner.running)).clicked() {
+                        runner.pause();
+                    }
+                    if ui.add(egui::Button::new("Resume").enabled(runner.paused)).clicked() {
+                        runner.start();
+                    }
+                    egui::combo_box_with_label(ui, "Speed", runner.speed.name(), |ui| {
+                        for speed in runner::Speed::ALL.iter() {
+                            ui.selectable_value(&mut runner.speed, *speed, speed.name());
+                        }
+                    });
+                });
+                if runner.running {
+                    ui.small(format!("Running at {}", runner.speed.name()));
+                } else if !runner.status.is_empty() {
+                    ui.small(runner.status.as_str());
                 }
                 if ui.button("CPU Reset").clicked(){
                     cpu.reset();
@@ -222,6 +439,23 @@ impl epi::App for EmuDisplayApp {
                 }
 
             });
+            ui.horizontal(|ui| {
+                let run_button = ui.button("Run To:   ");
+                ui.text_edit_singleline(run_to_str);
+
+                // a symbol or a hex address
+                if run_button.clicked() {
+                    let addr = symbols.addr_of(run_to_str.trim())
+                        .or_else(|| htb_option(run_to_str).filter(|x| x.len() <= 2).map(extract_from_hex));
+                    *step_status = match addr {
+                        Some(addr) => cpu.run_to(addr, STEP_LIMIT).err().map(|x| x.to_string()).unwrap_or_default(),
+                        None => "Run To needs an address or symbol".to_owned(),
+                    };
+                }
+            });
+            if !step_status.is_empty() {
+                ui.small(step_status.as_str());
+            }
 
             ui.separator();
 
@@ -362,7 +596,8 @@ impl epi::App for EmuDisplayApp {
             // ==========================
             ui.heading("Next Instruction:");
             
-            let disassembled_str = emulator::disassembler::disassemble_next_instr(cpu);
+            let disassembled_str = disassembler::decode(cpu, cpu.pc)
+                .format_with(&disassembler::FormatOptions::default(), Some(symbols));
             ui.separator();
             ui.add(egui::Label::new(disassembled_str).heading().text_color(egui::Color32::LIGHT_GRAY).italics());
             ui.separator();
@@ -416,7 +651,7 @@ impl epi::App for EmuDisplayApp {
                 id_source("second_memory_area");
             // scrolling panel one
             scrolling_area.always_show_scroll(true).show(ui, |ui| {
-                draw_panel_rows_16wide(ui, cpu, *from_1, *to_1);
+                draw_panel_rows_16wide(ui, cpu, symbols, *from_1, *to_1);
             });
             ui.separator();
 
@@ -464,11 +699,34 @@ impl epi::App for EmuDisplayApp {
             ui.separator();
             let scrolling_area = egui::ScrollArea::from_max_height(MEM_SCROLL_HEIGHT);
             scrolling_area.always_show_scroll(true).show(ui, |ui| {
-                draw_panel_rows_16wide(ui, cpu, *from_2, *to_2);
+                draw_panel_rows_16wide(ui, cpu, symbols, *from_2, *to_2);
             });
             ui.separator();
         });
 
+        // assembles to the same start address as the object code box
+        let start_addr = htb_option(load_start_str).map(extract_from_hex).unwrap_or(0x8000);
+        asm_editor.show(ctx, cpu, symbols, start_addr);
+        if source_view.show(ctx, cpu, symbols) {
+            runner.start();
+        }
+        symbols_window.show(ctx, cpu, symbols);
+        trace_log.show(ctx);
+        stack_view.show(ctx, cpu, symbols);
+        disassembly_window.show(ctx, cpu, symbols);
+        profiler_window.show(ctx, cpu, symbols);
+        coverage_window.show(ctx, cpu, symbols, source_view.debug_info());
+
+        // same size range, starting at the clicked address's row,
+        // moved down so it ends before the last row
+        if let Some(addr) = heatmap_window.show(ctx, frame, cpu, symbols) {
+            let len = (*to_1 - *from_1).min(0xFFEF);
+            *from_1 = (addr & 0xFFF0).min((0xFFEF - len) & 0xFFF0);
+            *to_1 = *from_1 + len;
+            *from_input_1 = format!("{:04X}", from_1);
+            *to_input_1 = format!("{:04X}", to_1);
+        }
+
     }
 }
 
@@ -477,8 +735,8 @@ impl epi::App for EmuDisplayApp {
 fn extract_from_hex(hex_vec : Vec<u8>) -> u16{
     let mut load_addr: u16 = 0;
     let len = hex_vec.len();
-    for i in 0..len{
-        load_addr |= (hex_vec[i] as u16) << ((len-1-i)*8);
+    for (i, byte) in hex_vec.iter().enumerate() {
+        load_addr |= (*byte as u16) << ((len-1-i)*8);
     }   
 
     load_addr
@@ -503,7 +761,7 @@ fn ensure_input(input : &mut String, desired_len: usize) -> bool{
         *input = s;
     }
 
-    if htb_option(input) == None {
+    if htb_option(input).is_none() {
         *input = "0".repeat(desired_len);
         ret = false;
     }
@@ -512,7 +770,8 @@ fn ensure_input(input : &mut String, desired_len: usize) -> bool{
 } 
 
 /// draw panel rows as specified
-fn draw_panel_rows_16wide(ui : &mut egui::Ui, cpu: &CPU6502, from: u16, to: u16){
+/// Rows of memory, followed by the names of any symbols in the row
+fn draw_panel_rows_16wide(ui : &mut egui::Ui, cpu: &CPU6502, symbols: &SymbolTable, from: u16, to: u16){
     ui.vertical(|ui| {
         
         let mut i = 0;
@@ -525,7 +784,11 @@ fn draw_panel_rows_16wide(ui : &mut egui::Ui, cpu: &CPU6502, from: u16, to: u16)
             let mem_slice = cpu.index_memory(current_row, current_row+0xf).unwrap();
 
             let s : String = mem_slice.iter().take(15).map(|i| format!("{:02X}    ", i)).collect();
-            let row = format!("{:04X}:   {}{:02X} ", current_row, s, mem_slice.last().unwrap());
+            let mut row = format!("{:04X}:   {}{:02X} ", current_row, s, mem_slice.last().unwrap());
+
+            for (addr, name) in symbols.in_range(current_row, current_row + 0xf) {
+                row.push_str(&format!("  {}@{:X}", name, addr & 0xf));
+            }
      
             if i & 1 == 0 {
                 ui.add(egui::Label::new(&row[..])
@@ -535,7 +798,10 @@ fn draw_panel_rows_16wide(ui : &mut egui::Ui, cpu: &CPU6502, from: u16, to: u16)
             }
 
             i+=1;
-            current_row += 0x10;
+            current_row = match current_row.checked_add(0x10) {
+                Some(x) => x,
+                None => break,
+            };
         }
         
     });
@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use eframe::egui;
+
+use crate::emulator::coverage::Coverage;
+use crate::emulator::cpu::CPU6502;
+use crate::emulator::debug_info::DebugInfo;
+use crate::emulator::symbols::SymbolTable;
+
+/// Turns coverage on for the last loaded program and shows the results
+#[derive(Default)]
+pub struct CoverageWindow {
+    pub open: bool,
+    /// Ranges of the last program loaded, coverage is measured over these
+    pub image: Vec<(u16, u16)>,
+    text: String,
+}
+
+impl CoverageWindow {
+    pub fn show(&mut self, ctx: &egui::CtxRef, cpu: &mut CPU6502, symbols: &SymbolTable,
+        debug: Option<(&DebugInfo, &Path)>)
+    {
+        let mut open = self.open;
+
+        egui::Window::new("Coverage")
+            .open(&mut open)
+            .default_size(egui::vec2(520., 420.))
+            .show(ctx, |ui| {
+                let image = match self.image.is_empty() {
+                    true => "all of memory".to_string(),
+                    false => self.image.iter().map(|(from, to)| format!("${:04X}-${:04X}", from, to))
+                        .collect::<Vec<_>>().join(", "),
+                };
+                ui.label(format!("Program: {}", image));
+
+                ui.horizontal(|ui| {
+                    let mut on = cpu.coverage.is_some();
+                    ui.checkbox(&mut on, "Record coverage");
+                    if on != cpu.coverage.is_some() {
+                        cpu.coverage = on.then(|| Coverage::new(&self.image));
+                    }
+                    if ui.button("Clear").clicked() && cpu.coverage.is_some() {
+                        cpu.coverage = Some(Coverage::new(&self.image));
+                        self.text.clear();
+                    }
+                });
+
+                let coverage = match &cpu.coverage {
+                    Some(x) => x,
+                    None => {
+                        ui.label("Records which instructions ran and which ways branches went while turned on.");
+                        return;
+                    }
+                };
+
+                let (run, total) = coverage.bytes();
+                ui.label(format!("{}/{} bytes run, {} branches", run, total, coverage.branches().len()));
+
+                ui.horizontal(|ui| {
+                    if ui.button("Report").clicked() {
+                        self.text = coverage.report(Some(symbols), debug.map(|x| x.0), cpu);
+                    }
+                    if ui.button("LCOV").clicked() {
+                        let (info, base_dir) = match debug {
+                            Some((info, dir)) => (Some(info), dir),
+                            None => (None, Path::new("")),
+                        };
+                        self.text = coverage.lcov("program", info, base_dir, Some(symbols), cpu);
+                    }
+                });
+                ui.small("LCOV is by source line once a .dbg file is loaded, by address otherwise");
+
+                egui::ScrollArea::from_max_height(280.).show(ui, |ui| {
+                    ui.add(egui::TextEdit::multiline(&mut self.text)
+                        .text_style(egui::TextStyle::Monospace)
+                        .desired_rows(14));
+                });
+            });
+
+        self.open = open;
+    }
+}
@@ -0,0 +1,166 @@
+use eframe::egui;
+
+use crate::app::profiler_window::heat_color;
+use crate::emulator::cpu::CPU6502;
+use crate::emulator::disassembler::{self, DecodedInstruction, FormatOptions};
+use crate::emulator::symbols::SymbolTable;
+
+// instructions shown
+const LINES : usize = 32;
+// following puts the PC this many lines down
+const FOLLOW_CONTEXT : usize = 8;
+
+const PC_BACKGROUND : egui::Color32 = egui::Color32::from_rgb(40, 70, 110);
+const BREAKPOINT_COLOR : egui::Color32 = egui::Color32::from_rgb(230, 60, 60);
+
+/// Instructions around the PC, clicking one toggles a breakpoint there.
+/// While profiling each has a heat column like the Profiler's
+pub struct DisassemblyWindow {
+    pub open: bool,
+    follow: bool,
+    top: u16,
+    goto: String,
+    status: String,
+}
+
+impl Default for DisassemblyWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            follow: true,
+            top: 0x8000,
+            goto: String::new(),
+            status: String::new(),
+        }
+    }
+}
+
+fn listing(cpu: &CPU6502, top: u16) -> Vec<DecodedInstruction> {
+    let mut instrs: Vec<DecodedInstruction> = Vec::with_capacity(LINES);
+    let mut addr = top;
+    for _ in 0..LINES {
+        let instr = disassembler::decode(cpu, addr);
+        addr = instr.next_addr();
+        instrs.push(instr);
+    }
+    instrs
+}
+
+impl DisassemblyWindow {
+    pub fn show(&mut self, ctx: &egui::CtxRef, cpu: &mut CPU6502, symbols: &SymbolTable) {
+        let mut open = self.open;
+
+        egui::Window::new("Disassembly")
+            .open(&mut open)
+            .default_size(egui::vec2(480., 560.))
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.follow, "Follow PC");
+                    if ui.button("Go to").clicked() {
+                        let text = self.goto.trim();
+                        let addr = symbols.addr_of(text)
+                            .or_else(|| u16::from_str_radix(text.trim_start_matches('$'), 16).ok());
+                        match addr {
+                            Some(addr) => {
+                                self.top = addr;
+                                self.follow = false;
+                                self.status.clear();
+                            }
+                            None => self.status = format!("'{}' isn't an address or symbol", text),
+                        }
+                    }
+                    ui.add(egui::TextEdit::singleline(&mut self.goto).desired_width(120.));
+                });
+
+                // scrolling by hand stops following
+                let mut scroll = 0;
+                ui.horizontal(|ui| {
+                    if ui.button("Page Up").clicked() {
+                        scroll = -(LINES as i32);
+                    }
+                    if ui.button("Up").clicked() {
+                        scroll = -1;
+                    }
+                    if ui.button("Down").clicked() {
+                        scroll = 1;
+                    }
+                    if ui.button("Page Down").clicked() {
+                        scroll = LINES as i32;
+                    }
+                });
+                if !self.status.is_empty() {
+                    ui.small(self.status.as_str());
+                }
+                ui.separator();
+
+                let mut instrs = listing(cpu, self.top);
+                if self.follow && !instrs[..LINES - FOLLOW_CONTEXT].iter().any(|x| x.addr == cpu.pc) {
+                    self.top = disassembler::start_before(cpu, cpu.pc, FOLLOW_CONTEXT);
+                    instrs = listing(cpu, self.top);
+                }
+
+                let opts = FormatOptions { uppercase_hex: true, show_addr: true, show_bytes: true, ..FormatOptions::default() };
+                let hottest = cpu.profiler.as_ref().map(|x| (0..=0xFFFF).map(|addr| x.at(addr).1).max().unwrap_or(0).max(1));
+                let list = ui.vertical(|ui| {
+                    ui.spacing_mut().item_spacing = egui::vec2(4., 2.);
+                    for instr in &instrs {
+                        if let Some(name) = symbols.name_of(instr.addr) {
+                            ui.monospace(format!("{}:", name));
+                        }
+
+                        ui.horizontal(|ui| {
+                            let marker = match cpu.breakpoints.contains(&instr.addr) {
+                                true => "●",
+                                false => " ",
+                            };
+                            ui.add(egui::Label::new(marker).monospace().text_color(BREAKPOINT_COLOR));
+
+                            if let (Some(profiler), Some(hottest)) = (&cpu.profiler, hottest) {
+                                let (count, cycles) = profiler.at(instr.addr);
+                                let heat = match count {
+                                    0 => egui::Label::new("  ").monospace(),
+                                    _ => egui::Label::new("██").monospace().text_color(heat_color(cycles as f32 / hottest as f32)),
+                                };
+                                ui.add(heat).on_hover_text(format!("{} runs, {} cycles", count, cycles));
+                            }
+
+                            let cycles = match (instr.is_data(), instr.may_add_cycles()) {
+                                (true, _) => String::new(),
+                                (false, true) => format!("{}+", instr.cycles),
+                                (false, false) => instr.cycles.to_string(),
+                            };
+                            let text = format!("{:<36}{:>3}", instr.format_with(&opts, Some(symbols)), cycles);
+                            let mut line = egui::Label::new(text).monospace();
+                            if instr.addr == cpu.pc {
+                                line = line.background_color(PC_BACKGROUND);
+                            }
+
+                            if ui.add(line).on_hover_text("Click to toggle a breakpoint").clicked()
+                                && !cpu.breakpoints.remove(&instr.addr) {
+                                cpu.breakpoints.insert(instr.addr);
+                            }
+                        });
+                    }
+                }).response;
+
+                // the mouse wheel over the listing scrolls it
+                let (hover, delta) = {
+                    let input = ui.input();
+                    (input.pointer.tooltip_pos(), input.scroll_delta.y)
+                };
+                if delta != 0. && hover.is_some_and(|x| list.rect.contains(x)) {
+                    scroll = if delta > 0. { -3 } else { 3 };
+                }
+
+                if scroll != 0 {
+                    self.follow = false;
+                    self.top = match scroll > 0 {
+                        true => instrs[(scroll as usize).min(LINES - 1)].addr,
+                        false => disassembler::start_before(cpu, self.top, scroll.unsigned_abs() as usize),
+                    };
+                }
+            });
+
+        self.open = open;
+    }
+}
@@ -0,0 +1,162 @@
+use eframe::egui;
+
+use crate::emulator::assembler::{self, AsmError, Assembly, TokenKind};
+use crate::emulator::cpu::CPU6502;
+use crate::emulator::symbols::SymbolTable;
+
+const SAMPLE_SOURCE : &str = "; assembles to the Start Address
+        ldx #$05
+loop:   dex
+        bne loop
+        brk
+";
+
+/// Assembly editor window, opened from the Control Panel
+pub struct AsmEditor {
+    pub open: bool,
+    source: String,
+
+    // source as it was when last assembled, mappings are only
+    // shown while it matches the editor
+    assembled_source: String,
+    assembly: Option<Assembly>,
+    errors: Vec<AsmError>,
+    status: String,
+}
+
+impl Default for AsmEditor {
+    fn default() -> Self {
+        Self {
+            open: false,
+            source: SAMPLE_SOURCE.to_owned(),
+            assembled_source: String::new(),
+            assembly: None,
+            errors: Vec::new(),
+            status: String::new(),
+        }
+    }
+}
+
+fn token_color(kind: TokenKind) -> egui::Color32 {
+    match kind {
+        TokenKind::Label => egui::Color32::GOLD,
+        TokenKind::Mnemonic => egui::Color32::LIGHT_BLUE,
+        TokenKind::Directive => egui::Color32::from_rgb(200, 120, 255),
+        TokenKind::Number => egui::Color32::from_rgb(120, 220, 120),
+        TokenKind::Str => egui::Color32::from_rgb(230, 160, 100),
+        TokenKind::Identifier => egui::Color32::WHITE,
+        TokenKind::Comment => egui::Color32::GRAY,
+        TokenKind::Text => egui::Color32::LIGHT_GRAY,
+    }
+}
+
+impl AsmEditor {
+    /// Assembles the source and writes it into memory,
+    /// PC is set to the first address and labels are added to symbols on success
+    fn assemble_and_load(&mut self, cpu: &mut CPU6502, symbols: &mut SymbolTable, start_addr: u16) {
+        self.assembled_source = self.source.clone();
+
+        match assembler::assemble(&self.source, start_addr) {
+            Ok(assembly) => {
+                for (addr, bytes) in &assembly.chunks {
+                    for (i, byte) in bytes.iter().enumerate() {
+                        cpu.write(addr.wrapping_add(i as u16), *byte);
+                    }
+                }
+
+                if let Some((first, _)) = assembly.chunks.first() {
+                    cpu.pc = *first;
+                }
+
+                let mut names: Vec<_> = assembly.symbols.iter().collect();
+                names.sort();
+                symbols.merge(&names.into_iter().map(|(name, addr)| (name, *addr)).collect());
+
+                self.status = format!("Loaded {} bytes", assembly.len());
+                self.errors.clear();
+                self.assembly = Some(assembly);
+            }
+            Err(errors) => {
+                self.status = format!("{} error(s)", errors.len());
+                self.errors = errors;
+                self.assembly = None;
+            }
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::CtxRef, cpu: &mut CPU6502, symbols: &mut SymbolTable, start_addr: u16) {
+        let mut open = self.open;
+
+        egui::Window::new("Assembly Editor")
+            .open(&mut open)
+            .default_size(egui::vec2(620., 360.))
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Assemble & Load").clicked() {
+                        self.assemble_and_load(cpu, symbols, start_addr);
+                    }
+                    ui.label(format!("Start: ${:04X}", start_addr));
+                    ui.separator();
+                    ui.label(&self.status);
+                });
+
+                ui.separator();
+
+                ui.columns(2, |columns| {
+                    egui::ScrollArea::from_max_height(300.)
+                        .id_source("asm_source")
+                        .show(&mut columns[0], |ui| {
+                            ui.add(egui::TextEdit::multiline(&mut self.source)
+                                .text_style(egui::TextStyle::Monospace)
+                                .desired_rows(16));
+                        });
+
+                    egui::ScrollArea::from_max_height(300.)
+                        .id_source("asm_listing")
+                        .show(&mut columns[1], |ui| {
+                            self.draw_listing(ui, cpu.pc);
+                        });
+                });
+            });
+
+        self.open = open;
+    }
+
+    /// Highlighted copy of the source with addresses, errors
+    /// and the line at PC marked
+    fn draw_listing(&self, ui: &mut egui::Ui, pc: u16) {
+        let up_to_date = self.assembled_source == self.source;
+        let assembly = self.assembly.as_ref().filter(|_| up_to_date);
+        let pc_line = assembly.and_then(|a| a.line_of(pc));
+
+        ui.spacing_mut().item_spacing = egui::vec2(0., 2.);
+
+        for (n, line) in self.source.lines().enumerate() {
+            let background = match pc_line == Some(n) {
+                true => egui::Color32::from_rgb(70, 70, 20),
+                false => egui::Color32::TRANSPARENT,
+            };
+
+            ui.horizontal(|ui| {
+                let addr = match assembly.and_then(|a| a.addr_of(n)) {
+                    Some(addr) => format!("{:3} {:04X}  ", n + 1, addr),
+                    None => format!("{:3}       ", n + 1),
+                };
+                ui.add(egui::Label::new(addr).monospace().text_color(egui::Color32::GRAY)
+                    .background_color(background));
+
+                for (kind, text) in assembler::highlight_line(line) {
+                    ui.add(egui::Label::new(text).monospace().text_color(token_color(kind))
+                        .background_color(background));
+                }
+            });
+
+            if up_to_date {
+                for err in self.errors.iter().filter(|e| e.line == n) {
+                    ui.add(egui::Label::new(format!("          ^ {}", err.message))
+                        .monospace().text_color(egui::Color32::RED));
+                }
+            }
+        }
+    }
+}
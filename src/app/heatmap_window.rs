@@ -0,0 +1,100 @@
+use eframe::{egui, epi};
+
+use crate::emulator::cpu::CPU6502;
+use crate::emulator::heatmap::Heatmap;
+use crate::emulator::symbols::SymbolTable;
+
+// level lost per frame, about 4 seconds to fade out at 60fps
+const DECAY : u8 = 1;
+// screen pixels per address
+const SCALE : f32 = 2.;
+
+/// All 64K as 256x256 pixels, a row per page, lit up by recent accesses
+#[derive(Default)]
+pub struct HeatmapWindow {
+    pub open: bool,
+    texture: Option<egui::TextureId>,
+}
+
+impl HeatmapWindow {
+    /// Returns the address of a clicked pixel
+    pub fn show(&mut self, ctx: &egui::CtxRef, frame: &mut epi::Frame<'_>, cpu: &mut CPU6502, symbols: &SymbolTable)
+        -> Option<u16>
+    {
+        let mut open = self.open;
+        let mut clicked = None;
+
+        // the old frame's texture isn't needed once a new one is made or the window closes
+        if let (Some(id), Some(alloc)) = (self.texture.take(), frame.tex_allocator().as_mut()) {
+            alloc.free(id);
+        }
+
+        egui::Window::new("Memory Heatmap")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let mut on = cpu.heatmap.is_some();
+                    ui.checkbox(&mut on, "Record");
+                    if on != cpu.heatmap.is_some() {
+                        cpu.heatmap = on.then(Heatmap::default);
+                    }
+                    ui.add(egui::Label::new("write").text_color(egui::Color32::RED));
+                    ui.add(egui::Label::new("read").text_color(egui::Color32::GREEN));
+                    ui.add(egui::Label::new("execute").text_color(egui::Color32::LIGHT_BLUE));
+                });
+
+                let heatmap = match cpu.heatmap.as_mut() {
+                    Some(x) => x,
+                    None => {
+                        ui.label("Shows where programs read, write and run while turned on.");
+                        return;
+                    }
+                };
+
+                let pixels: Vec<egui::Color32> = heatmap.rgb()
+                    .map(|[r, g, b]| egui::Color32::from_rgb(r.max(16), g.max(16), b.max(24)))
+                    .collect();
+                heatmap.decay(DECAY);
+
+                let alloc = match frame.tex_allocator().as_mut() {
+                    Some(x) => x,
+                    None => return,
+                };
+                let id = alloc.alloc_srgba_premultiplied((256, 256), &pixels);
+                self.texture = Some(id);
+
+                let (rect, response) = ui.allocate_exact_size(egui::vec2(256. * SCALE, 256. * SCALE), egui::Sense::click());
+                egui::Image::new(id, rect.size()).paint_at(ui, rect);
+
+                let addr_at = |pos: egui::Pos2| {
+                    let x = ((pos.x - rect.min.x) / SCALE).clamp(0., 255.) as u16;
+                    let y = ((pos.y - rect.min.y) / SCALE).clamp(0., 255.) as u16;
+                    y << 8 | x
+                };
+
+                let hovered = ui.input().pointer.tooltip_pos().filter(|x| rect.contains(*x)).map(addr_at);
+                match hovered {
+                    Some(addr) => {
+                        let mut text = format!("${:04X}", addr);
+                        if let Some(name) = symbols.name_of(addr) {
+                            text.push_str(&format!(" {}", name));
+                        }
+                        ui.monospace(text);
+                    }
+                    None => {
+                        ui.label("Click to show an address in Memory Panel 1");
+                    }
+                }
+                if response.clicked() {
+                    clicked = response.interact_pointer_pos().map(addr_at);
+                }
+
+                // keeps fading while nothing else is happening
+                ctx.request_repaint();
+            });
+
+        self.open = open;
+        clicked
+    }
+}
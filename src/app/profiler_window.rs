@@ -0,0 +1,126 @@
+use eframe::egui;
+
+use crate::emulator::cpu::CPU6502;
+use crate::emulator::disassembler::{self, FormatOptions};
+use crate::emulator::profiler::Profiler;
+use crate::emulator::symbols::SymbolTable;
+
+// addresses in the report
+const REPORT_LIMIT : usize = 40;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Output {
+    Report,
+    Csv,
+    SubroutineCsv,
+    Collapsed,
+}
+
+/// Turns the CPU's profiler on and off and shows what it counted
+pub struct ProfilerWindow {
+    pub open: bool,
+    output: Output,
+    text: String,
+}
+
+impl Default for ProfilerWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            output: Output::Report,
+            text: String::new(),
+        }
+    }
+}
+
+/// Dark blue for cold to red for the hottest
+pub fn heat_color(heat: f32) -> egui::Color32 {
+    let heat = heat.clamp(0., 1.);
+    egui::Color32::from_rgb((40. + 215. * heat) as u8, (60. + 60. * (1. - heat)) as u8, (140. * (1. - heat)) as u8)
+}
+
+impl ProfilerWindow {
+    pub fn show(&mut self, ctx: &egui::CtxRef, cpu: &mut CPU6502, symbols: &SymbolTable) {
+        let mut open = self.open;
+
+        egui::Window::new("Profiler")
+            .open(&mut open)
+            .default_size(egui::vec2(560., 480.))
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let mut on = cpu.profiler.is_some();
+                    ui.checkbox(&mut on, "Profile");
+                    if on != cpu.profiler.is_some() {
+                        cpu.profiler = match on {
+                            true => Some(Profiler::new()),
+                            false => None,
+                        };
+                    }
+                    if ui.button("Clear").clicked() && cpu.profiler.is_some() {
+                        cpu.profiler = Some(Profiler::new());
+                        self.text.clear();
+                    }
+                });
+
+                let profiler = match &cpu.profiler {
+                    Some(x) => x,
+                    None => {
+                        ui.label("Counts cycles per address and subroutine while turned on.");
+                        return;
+                    }
+                };
+
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.output, Output::Report, "Report");
+                    ui.radio_value(&mut self.output, Output::Csv, "CSV");
+                    ui.radio_value(&mut self.output, Output::SubroutineCsv, "Subroutine CSV");
+                    ui.radio_value(&mut self.output, Output::Collapsed, "Collapsed stacks");
+                    if ui.button("Show").clicked() {
+                        self.text = match self.output {
+                            Output::Report => profiler.report(Some(symbols), REPORT_LIMIT),
+                            Output::Csv => profiler.csv(Some(symbols)),
+                            Output::SubroutineCsv => profiler.subroutine_csv(Some(symbols)),
+                            Output::Collapsed => profiler.collapsed(Some(symbols)),
+                        };
+                    }
+                });
+                egui::ScrollArea::from_max_height(140.)
+                    .id_source("profile_text")
+                    .show(ui, |ui| {
+                        ui.add(egui::TextEdit::multiline(&mut self.text)
+                            .text_style(egui::TextStyle::Monospace)
+                            .desired_rows(6));
+                    });
+
+                ui.separator();
+                ui.label(format!("{} cycles profiled", profiler.total_cycles()));
+
+                // every executed instruction in address order, heat beside it
+                let mut stats = profiler.addresses();
+                stats.sort_by_key(|x| x.addr);
+                let hottest = stats.iter().map(|x| x.cycles).max().unwrap_or(1) as f32;
+                let opts = FormatOptions { show_addr: true, ..FormatOptions::default() };
+
+                egui::ScrollArea::from_max_height(220.)
+                    .id_source("profile_heat")
+                    .show(ui, |ui| {
+                        ui.spacing_mut().item_spacing = egui::vec2(6., 2.);
+                        for stat in &stats {
+                            ui.horizontal(|ui| {
+                                let heat = stat.cycles as f32 / hottest;
+                                ui.add(egui::Label::new("████").monospace().text_color(heat_color(heat)));
+                                ui.monospace(format!("{:>9} {:>10}", stat.count, stat.cycles));
+
+                                let mut line = disassembler::decode(cpu, stat.addr).format_with(&opts, Some(symbols));
+                                if let Some(name) = symbols.name_of(stat.addr) {
+                                    line = format!("{}  ; {}", line, name);
+                                }
+                                ui.monospace(line);
+                            });
+                        }
+                    });
+            });
+
+        self.open = open;
+    }
+}
@@ -0,0 +1,171 @@
+use eframe::egui;
+
+use crate::emulator::cpu::{CPU6502, Flags};
+
+const LABEL_COLOR : egui::Color32 = egui::Color32::LIGHT_GRAY;
+const CHANGED_COLOR : egui::Color32 = egui::Color32::YELLOW;
+const CHANGED_BACKGROUND : egui::Color32 = egui::Color32::from_rgb(90, 80, 0);
+
+const FLAG_LETTERS : [(&str, Flags); 8] = [("N", Flags::N), ("V", Flags::V), ("_", Flags::U), ("B", Flags::B),
+    ("D", Flags::D), ("I", Flags::I), ("Z", Flags::Z), ("C", Flags::C)];
+
+#[derive(Copy, Clone, PartialEq)]
+enum Reg {
+    Pc,
+    A,
+    X,
+    Y,
+    Sp,
+}
+
+impl Reg {
+    const ALL : [Reg; 5] = [Reg::Pc, Reg::A, Reg::X, Reg::Y, Reg::Sp];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Reg::Pc => "PC:  $",
+            Reg::A => "A:    $",
+            Reg::X => "X:    $",
+            Reg::Y => "Y:    $",
+            // only the low byte is in the register
+            Reg::Sp => "SP:  $01",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Reg::Pc => "PC",
+            Reg::A => "A",
+            Reg::X => "X",
+            Reg::Y => "Y",
+            Reg::Sp => "SP",
+        }
+    }
+
+    fn digits(&self) -> usize {
+        match self {
+            Reg::Pc => 4,
+            _ => 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+struct Regs {
+    pc: u16,
+    a: u8,
+    x: u8,
+    y: u8,
+    sp: u8,
+    status: Flags,
+}
+
+impl Regs {
+    fn of(cpu: &CPU6502) -> Self {
+        Regs { pc: cpu.pc, a: cpu.reg_a, x: cpu.reg_x, y: cpu.reg_y, sp: cpu.stk_ptr, status: cpu.status }
+    }
+
+    fn get(&self, reg: Reg) -> u16 {
+        match reg {
+            Reg::Pc => self.pc,
+            Reg::A => self.a as u16,
+            Reg::X => self.x as u16,
+            Reg::Y => self.y as u16,
+            Reg::Sp => self.sp as u16,
+        }
+    }
+}
+
+fn set(cpu: &mut CPU6502, reg: Reg, val: u16) {
+    match reg {
+        Reg::Pc => cpu.pc = val,
+        Reg::A => cpu.reg_a = val as u8,
+        Reg::X => cpu.reg_x = val as u8,
+        Reg::Y => cpu.reg_y = val as u8,
+        Reg::Sp => cpu.stk_ptr = val as u8,
+    }
+}
+
+/// Internal Registers, each one editable and the flags toggled by clicking.
+/// Whatever the last step changed is highlighted
+#[derive(Default)]
+pub struct RegisterPanel {
+    text: [String; 5],
+    // before and after the last change the CPU made
+    before: Option<Regs>,
+    now: Option<Regs>,
+    error: String,
+}
+
+impl RegisterPanel {
+    pub fn show(&mut self, ui: &mut egui::Ui, cpu: &mut CPU6502) {
+        let regs = Regs::of(cpu);
+        if self.now != Some(regs) {
+            self.before = self.now;
+            self.now = Some(regs);
+        }
+        let before = self.before;
+        let changed = |reg: Reg| before.is_some_and(|x| x.get(reg) != regs.get(reg));
+        let flag_changed = |flag: Flags| before.is_some_and(|x| x.status.contains(flag) != regs.status.contains(flag));
+        let mut edited = false;
+
+        ui.horizontal(|ui| {
+            ui.add(egui::Label::new("   Status: ").text_color(LABEL_COLOR).heading());
+            for (letter, flag) in FLAG_LETTERS.iter() {
+                let color = match cpu.status.contains(*flag) {
+                    true => egui::Color32::GREEN,
+                    false => egui::Color32::RED,
+                };
+                let mut label = egui::Label::new(*letter).text_color(color);
+                if flag_changed(*flag) {
+                    label = label.background_color(CHANGED_BACKGROUND);
+                }
+                if ui.add(label).on_hover_text("Click to toggle").clicked() {
+                    cpu.status.toggle(*flag);
+                    edited = true;
+                }
+            }
+        });
+
+        for (i, reg) in Reg::ALL.iter().enumerate() {
+            ui.horizontal(|ui| {
+                let color = match changed(*reg) {
+                    true => CHANGED_COLOR,
+                    false => LABEL_COLOR,
+                };
+                ui.add(egui::Label::new(reg.label()).text_color(color).heading());
+
+                let text = &mut self.text[i];
+                let response = ui.add(egui::TextEdit::singleline(text)
+                    .text_style(egui::TextStyle::Heading)
+                    .text_color(color)
+                    .desired_width(14. * reg.digits() as f32));
+
+                if response.lost_kb_focus() {
+                    let digits = text.trim().trim_start_matches('$');
+                    match u16::from_str_radix(digits, 16) {
+                        Ok(val) if digits.len() <= reg.digits() => {
+                            set(cpu, *reg, val);
+                            self.error.clear();
+                            edited = true;
+                        }
+                        _ => self.error = format!("{} needs up to {} hex digits", reg.name(), reg.digits()),
+                    }
+                }
+                if !ui.memory().has_kb_focus(response.id) {
+                    *text = format!("{:0width$X}", Regs::of(cpu).get(*reg), width = reg.digits());
+                }
+            });
+        }
+
+        if !self.error.is_empty() {
+            ui.small(self.error.as_str());
+        }
+
+        // edits aren't what the last step changed
+        if edited {
+            self.before = None;
+            self.now = Some(Regs::of(cpu));
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use crate::emulator::cpu::{CPU6502, Limit, StopReason};
+
+// what Unlimited runs each frame, keeps the UI responsive
+const UNLIMITED_CYCLES : u64 = 1_000_000;
+// at most this many seconds are caught up after a slow frame
+const MAX_FRAME_TIME : f64 = 0.25;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Speed {
+    Hz1,
+    Khz1,
+    /// NTSC NES clock
+    Mhz179,
+    Unlimited,
+}
+
+impl Speed {
+    pub const ALL : [Speed; 4] = [Speed::Hz1, Speed::Khz1, Speed::Mhz179, Speed::Unlimited];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Speed::Hz1 => "1 Hz",
+            Speed::Khz1 => "1 kHz",
+            Speed::Mhz179 => "1.79 MHz",
+            Speed::Unlimited => "Unlimited",
+        }
+    }
+
+    /// Cycles a second, None for as fast as possible
+    fn hz(&self) -> Option<f64> {
+        match self {
+            Speed::Hz1 => Some(1.),
+            Speed::Khz1 => Some(1_000.),
+            Speed::Mhz179 => Some(1_789_773.),
+            Speed::Unlimited => None,
+        }
+    }
+}
+
+/// Continuous Run, a frame's worth of cycles at a time so the UI keeps going
+pub struct Runner {
+    pub running: bool,
+    pub speed: Speed,
+    pub status: String,
+    pub paused: bool,
+    // cycles that can run, negative while paying off an instruction
+    owed: f64,
+    last_time: Option<f64>,
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Self {
+            running: false,
+            speed: Speed::Mhz179,
+            status: String::new(),
+            paused: false,
+            owed: 0.,
+            last_time: None,
+        }
+    }
+}
+
+impl Runner {
+    pub fn start(&mut self) {
+        self.running = true;
+        self.paused = false;
+        self.last_time = None;
+        // the first instruction doesn't wait a frame
+        self.owed = 1.;
+        self.status.clear();
+    }
+
+    pub fn pause(&mut self) {
+        self.running = false;
+        self.paused = true;
+        self.status = "Paused".to_string();
+    }
+
+    fn stop(&mut self, status: String) {
+        self.running = false;
+        self.status = status;
+    }
+
+    /// Runs what's owed since the last frame, time is in seconds
+    pub fn update(&mut self, cpu: &mut CPU6502, time: f64) {
+        if !self.running {
+            return;
+        }
+
+        let elapsed = self.last_time.map(|x| (time - x).clamp(0., MAX_FRAME_TIME)).unwrap_or(0.);
+        self.last_time = Some(time);
+        let budget = match self.speed.hz() {
+            Some(hz) => {
+                self.owed += elapsed * hz;
+                if self.owed < 1. {
+                    return;
+                }
+                self.owed as u64
+            }
+            None => UNLIMITED_CYCLES,
+        };
+
+        // a frame never ends at a breakpoint, so the next doesn't skip one
+        let before = cpu.get_total_cycles();
+        let stop = cpu.run(Limit::Cycles(budget));
+        if self.speed.hz().is_some() {
+            self.owed -= cpu.get_total_cycles().wrapping_sub(before) as f64;
+        }
+        if stop != StopReason::BudgetExhausted {
+            self.stop(stop.to_string());
+        }
+    }
+}
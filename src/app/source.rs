@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use eframe::egui;
+
+use crate::emulator::cpu::CPU6502;
+use crate::emulator::debug_info::{self, DebugInfo};
+use crate::emulator::symbols::SymbolTable;
+
+// lines shown above and below the current one
+const CONTEXT_LINES : usize = 8;
+const STEP_LINE_LIMIT : u64 = 1_000_000;
+
+/// Source level debugging window, needs a cc65 .dbg file
+#[derive(Default)]
+pub struct SourceView {
+    pub open: bool,
+    debug: Option<DebugInfo>,
+    // source paths in the .dbg are relative to it
+    base_dir: PathBuf,
+    // None if the file couldn't be read
+    sources: HashMap<String, Option<Vec<String>>>,
+    breakpoint_input: String,
+    status: String,
+}
+
+impl SourceView {
+    /// Reads a .dbg file, its labels are added to symbols
+    pub fn load_debug_info(&mut self, path: &str, symbols: &mut SymbolTable) -> Result<String, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let info = debug_info::parse(&text)?;
+
+        symbols.merge(&info.symbols);
+        let msg = format!("Loaded {} files, {} labels", info.files().len(), info.symbols.len());
+
+        self.base_dir = Path::new(path).parent().map(|x| x.to_path_buf()).unwrap_or_default();
+        self.sources.clear();
+        self.debug = Some(info);
+        self.open = true;
+        Ok(msg)
+    }
+
+    /// The loaded .dbg and the directory its source paths start from
+    pub fn debug_info(&self) -> Option<(&DebugInfo, &Path)> {
+        self.debug.as_ref().map(|x| (x, self.base_dir.as_path()))
+    }
+
+    fn source(&mut self, file: &str) -> Option<&Vec<String>> {
+        let base_dir = &self.base_dir;
+        self.sources.entry(file.to_string()).or_insert_with(|| {
+            std::fs::read_to_string(base_dir.join(file))
+                .or_else(|_| std::fs::read_to_string(file))
+                .ok()
+                .map(|x| x.lines().map(|l| l.to_string()).collect())
+        }).as_ref()
+    }
+
+    /// True when Run was clicked, the app's runner does the running
+    pub fn show(&mut self, ctx: &egui::CtxRef, cpu: &mut CPU6502, symbols: &SymbolTable) -> bool {
+        let mut open = self.open;
+        let mut run = false;
+
+        egui::Window::new("Source")
+            .open(&mut open)
+            .default_size(egui::vec2(520., 380.))
+            .show(ctx, |ui| {
+                let debug = match self.debug.take() {
+                    Some(x) => x,
+                    None => {
+                        ui.label("Load a cc65 .dbg file (ld65 --dbgfile) with Load File to see source here.");
+                        ui.separator();
+                        self.draw_breakpoints(ui, cpu, symbols, None);
+                        ui.label(&self.status);
+                        return;
+                    }
+                };
+
+                ui.horizontal(|ui| {
+                    if ui.button("Step Line").clicked() {
+                        let steps = debug.step_line(cpu, STEP_LINE_LIMIT);
+                        self.status = format!("{} instruction(s)", steps);
+                    }
+                    if ui.button("Run").clicked() {
+                        run = true;
+                        self.status.clear();
+                    }
+                    ui.label(&self.status);
+                });
+
+                ui.separator();
+                self.draw_breakpoints(ui, cpu, symbols, Some(&debug));
+                ui.separator();
+
+                match debug.line_at(cpu.pc) {
+                    Some((file, line)) => {
+                        ui.label(format!("{}:{}  (PC ${:04X})", file, line, cpu.pc));
+                        self.draw_source(ui, &debug, cpu, file, line);
+                    }
+                    None => {
+                        ui.label(format!("No source for PC ${:04X}", cpu.pc));
+                    }
+                }
+
+                self.debug = Some(debug);
+            });
+
+        self.open = open;
+        run
+    }
+
+    fn draw_source(&mut self, ui: &mut egui::Ui, debug: &DebugInfo, cpu: &CPU6502, file: &str, line: u32) {
+        let lines = match self.source(file) {
+            Some(x) => x.clone(),
+            None => {
+                ui.label(format!("Couldn't read {}", file));
+                return;
+            }
+        };
+
+        let current = line as usize;
+        let first = current.saturating_sub(CONTEXT_LINES).max(1);
+        let last = (current + CONTEXT_LINES).min(lines.len());
+
+        ui.spacing_mut().item_spacing = egui::vec2(0., 2.);
+        for n in first..=last {
+            let has_bp = debug.addrs_of(file, n as u32).iter().any(|x| cpu.breakpoints.contains(x));
+            let text = format!("{} {:4}  {}", if has_bp { "●" } else { " " }, n, lines.get(n - 1).map(|x| &x[..]).unwrap_or(""));
+
+            let mut label = egui::Label::new(text).monospace();
+            if n == current {
+                label = label.background_color(egui::Color32::from_rgb(70, 70, 20)).text_color(egui::Color32::WHITE);
+            }
+            ui.add(label);
+        }
+    }
+
+    fn draw_breakpoints(&mut self, ui: &mut egui::Ui, cpu: &mut CPU6502, symbols: &SymbolTable, debug: Option<&DebugInfo>) {
+        ui.horizontal(|ui| {
+            ui.label("Breakpoint:");
+            ui.text_edit_singleline(&mut self.breakpoint_input);
+
+            if ui.button("Add").clicked() {
+                match debug_info::resolve_location(&self.breakpoint_input, symbols, debug) {
+                    Ok(addrs) => {
+                        cpu.breakpoints.extend(addrs);
+                        self.breakpoint_input.clear();
+                    }
+                    Err(msg) => self.status = msg,
+                }
+            }
+        });
+        ui.small("$8000, a symbol, or file:line");
+
+        let mut remove = None;
+        for addr in cpu.breakpoints.iter() {
+            ui.horizontal(|ui| {
+                if ui.small_button("x").clicked() {
+                    remove = Some(*addr);
+                }
+
+                let mut text = format!("${:04X}", addr);
+                if let Some(name) = symbols.name_of(*addr) {
+                    text.push_str(&format!("  {}", name));
+                }
+                if let Some((file, line)) = debug.and_then(|x| x.line_at(*addr)) {
+                    text.push_str(&format!("  {}:{}", file, line));
+                }
+                ui.monospace(text);
+            });
+        }
+        if let Some(addr) = remove {
+            cpu.breakpoints.remove(&addr);
+        }
+    }
+}
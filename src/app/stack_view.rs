@@ -0,0 +1,82 @@
+use eframe::egui;
+
+use crate::emulator::call_stack::{FrameKind, Slot};
+use crate::emulator::cpu::CPU6502;
+use crate::emulator::symbols::SymbolTable;
+
+const RETURN_COLOR : egui::Color32 = egui::Color32::from_rgb(230, 200, 90);
+const PUSH_COLOR : egui::Color32 = egui::Color32::from_rgb(120, 180, 240);
+
+/// Call stack and the bytes on the stack, from the CPU's shadow stack
+#[derive(Default)]
+pub struct StackView {
+    pub open: bool,
+}
+
+fn addr_name(addr: u16, symbols: &SymbolTable) -> String {
+    match symbols.name_of(addr) {
+        Some(name) => format!("${:04X} {}", addr, name),
+        None => format!("${:04X}", addr),
+    }
+}
+
+impl StackView {
+    pub fn show(&mut self, ctx: &egui::CtxRef, cpu: &CPU6502, symbols: &SymbolTable) {
+        let mut open = self.open;
+
+        egui::Window::new("Call Stack")
+            .open(&mut open)
+            .default_size(egui::vec2(440., 400.))
+            .show(ctx, |ui| {
+                let stack = &cpu.call_stack;
+
+                ui.label("Calls, innermost first");
+                if stack.frames().is_empty() {
+                    ui.small("none");
+                }
+                for frame in stack.frames().iter().rev() {
+                    let kind = match frame.kind {
+                        FrameKind::Jsr => "JSR",
+                        FrameKind::Brk => "BRK",
+                        FrameKind::Irq => "IRQ",
+                        FrameKind::Nmi => "NMI",
+                    };
+                    ui.monospace(format!("{} {}  from ${:04X}, returns to {}",
+                        kind, addr_name(frame.target, symbols), frame.from, addr_name(frame.return_to, symbols)));
+                }
+
+                ui.separator();
+                ui.label(format!("Stack, SP ${:02X}", cpu.stk_ptr));
+                egui::ScrollArea::from_max_height(200.).show(ui, |ui| {
+                    ui.spacing_mut().item_spacing = egui::vec2(0., 2.);
+
+                    // nothing is on the stack when SP is $FF
+                    for slot in (cpu.stk_ptr as u16 + 1)..=0xFF {
+                        let addr = CPU6502::STACK_OFFSET + slot;
+                        let (text, color) = match stack.slot(slot as u8) {
+                            Slot::Return(i) => {
+                                let frame = &stack.frames()[i];
+                                (format!("return address of {}", addr_name(frame.target, symbols)), RETURN_COLOR)
+                            }
+                            Slot::Status(_) => ("status pushed by the interrupt".to_string(), RETURN_COLOR),
+                            Slot::Pha(from) => (format!("PHA at ${:04X}", from), PUSH_COLOR),
+                            Slot::Php(from) => (format!("PHP at ${:04X}", from), PUSH_COLOR),
+                            Slot::Unknown => (String::new(), egui::Color32::GRAY),
+                        };
+                        let line = format!("${:04X}  ${:02X}  {}", addr, cpu.read(addr), text);
+                        ui.add(egui::Label::new(line).monospace().text_color(color));
+                    }
+                });
+
+                if stack.notes().next().is_some() {
+                    ui.separator();
+                    ui.label("Stack tricks");
+                    for note in stack.notes() {
+                        ui.add(egui::Label::new(note).small().text_color(egui::Color32::from_rgb(240, 150, 60)));
+                    }
+                }
+            });
+
+        self.open = open;
+    }
+}
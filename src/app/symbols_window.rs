@@ -0,0 +1,85 @@
+use eframe::egui;
+
+use crate::emulator::cpu::CPU6502;
+use crate::emulator::label_files::{self, LabelFormat};
+use crate::emulator::symbols::SymbolTable;
+
+/// Imports and exports the symbol table as other emulators' label files
+pub struct SymbolsWindow {
+    pub open: bool,
+    format: LabelFormat,
+    text: String,
+    status: String,
+}
+
+impl Default for SymbolsWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            format: LabelFormat::Vice,
+            text: String::new(),
+            status: String::new(),
+        }
+    }
+}
+
+impl SymbolsWindow {
+    pub fn show(&mut self, ctx: &egui::CtxRef, cpu: &CPU6502, symbols: &mut SymbolTable) {
+        let mut open = self.open;
+        // Mesen's PRG ROM offsets depend on the cartridge
+        let prg_size = cpu.cartridge().map(|x| x.prg().len());
+
+        egui::Window::new("Symbols")
+            .open(&mut open)
+            .default_size(egui::vec2(420., 360.))
+            .show(ctx, |ui| {
+                let format = &mut self.format;
+                egui::combo_box_with_label(ui, "Label file format", format.name(), |ui| {
+                    for f in LabelFormat::ALL.iter() {
+                        ui.selectable_value(format, *f, f.name());
+                    }
+                });
+
+                egui::ScrollArea::from_max_height(120.)
+                    .id_source("label_file_text")
+                    .show(ui, |ui| {
+                        ui.add(egui::TextEdit::multiline(&mut self.text)
+                            .text_style(egui::TextStyle::Monospace)
+                            .desired_rows(6));
+                    });
+
+                ui.horizontal(|ui| {
+                    if ui.button("Import").clicked() {
+                        self.status = match label_files::parse(self.format, &self.text, prg_size) {
+                            Ok(table) => {
+                                symbols.merge(&table);
+                                format!("Imported {} labels", table.len())
+                            }
+                            Err(msg) => msg,
+                        };
+                    }
+                    if ui.button("Export").clicked() {
+                        self.text = label_files::write(self.format, symbols, prg_size);
+                        self.status = format!("Exported {} labels", symbols.len());
+                    }
+                    if ui.button("Clear Symbols").clicked() {
+                        symbols.clear();
+                        self.status.clear();
+                    }
+                    ui.label(&self.status);
+                });
+
+                ui.separator();
+
+                egui::ScrollArea::from_max_height(160.)
+                    .id_source("symbol_list")
+                    .show(ui, |ui| {
+                        for (name, addr) in symbols.iter() {
+                            ui.monospace(format!("${:04X}  {}", addr, name));
+                        }
+                    });
+            });
+
+        self.open = open;
+    }
+}
@@ -0,0 +1,48 @@
+use std::collections::VecDeque;
+
+use eframe::egui;
+
+use crate::emulator::cpu::CPU6502;
+use crate::emulator::disassembler;
+use crate::emulator::symbols::SymbolTable;
+
+const MAX_LINES : usize = 500;
+
+/// Instructions run with Next Step, newest last
+#[derive(Default)]
+pub struct TraceLog {
+    pub open: bool,
+    lines: VecDeque<String>,
+}
+
+impl TraceLog {
+    /// Call before the instruction at PC runs
+    pub fn record(&mut self, cpu: &CPU6502, symbols: &SymbolTable) {
+        if self.lines.len() == MAX_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(disassembler::trace_line(cpu, Some(symbols)));
+    }
+
+    pub fn show(&mut self, ctx: &egui::CtxRef) {
+        let mut open = self.open;
+
+        egui::Window::new("Trace Log")
+            .open(&mut open)
+            .default_size(egui::vec2(640., 300.))
+            .show(ctx, |ui| {
+                if ui.button("Clear").clicked() {
+                    self.lines.clear();
+                }
+                ui.separator();
+
+                egui::ScrollArea::from_max_height(260.).show(ui, |ui| {
+                    for line in &self.lines {
+                        ui.monospace(line);
+                    }
+                });
+            });
+
+        self.open = open;
+    }
+}
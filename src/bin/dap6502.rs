@@ -0,0 +1,65 @@
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::process;
+
+use simple_6502rs::emulator::dap::{self, DapServer};
+
+/*
+*   Debug Adapter Protocol server, `dap6502 [--port N]`
+*
+*   Talks DAP over stdin/stdout, or with --port to one client at a time on
+*   127.0.0.1:N. The program and everything else comes from the launch request
+*/
+
+const USAGE : &str = "usage: dap6502 [--port N]
+
+Debug adapter for DAP clients (VS Code, nvim-dap, ...). Without --port it
+talks over stdin/stdout, which is how editors start adapters.";
+
+fn start() -> Result<(), String> {
+    let mut port = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--port" => {
+                let n = args.next().and_then(|x| x.parse::<u16>().ok());
+                port = Some(n.ok_or("--port needs a number")?);
+            }
+            "--help" => return Err(String::new()),
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+
+    let port = match port {
+        Some(port) => port,
+        None => {
+            let messages = dap::spawn_reader(BufReader::new(std::io::stdin()));
+            return DapServer::new(std::io::stdout()).serve(messages).map_err(|e| e.to_string());
+        }
+    };
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("port {}: {}", port, e))?;
+    eprintln!("waiting for a DAP client on 127.0.0.1:{}", port);
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| {
+            stream.set_nodelay(true)?;
+            let messages = dap::spawn_reader(BufReader::new(stream.try_clone()?));
+            DapServer::new(stream).serve(messages)
+        });
+        if let Err(e) = result {
+            eprintln!("dap6502: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = start() {
+        if !e.is_empty() {
+            eprintln!("dap6502: {}\n", e);
+        }
+        eprintln!("{}", USAGE);
+        process::exit(1);
+    }
+}
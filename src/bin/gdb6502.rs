@@ -0,0 +1,81 @@
+use std::net::TcpListener;
+use std::process;
+
+use simple_6502rs::emulator::cpu::CPU6502;
+use simple_6502rs::emulator::formats::{self, StartAction};
+use simple_6502rs::emulator::gdb_stub::GdbStub;
+
+/*
+*   Runs a program under the GDB stub, one gdb at a time
+*
+*   gdb6502 [--port N] [--load ADDR] [--pc ADDR] <program>
+*/
+
+const USAGE : &str = "usage: gdb6502 [--port N] [--load ADDR] [--pc ADDR] <program>
+
+Loads <program> like run6502 does and waits for gdb on 127.0.0.1:N (default 3333),
+then: gdb-multiarch -ex \"target remote :3333\"";
+
+fn parse_addr(s: &str) -> Result<u16, String> {
+    let hex = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("'{}' isn't a hex address", s))
+}
+
+fn start() -> Result<(), String> {
+    let mut port = 3333;
+    let mut load_addr = 0x8000;
+    let mut pc = None;
+    let mut program = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match &arg[..] {
+            "--port" => port = value()?.parse().map_err(|_| "--port needs a number".to_string())?,
+            "--load" => load_addr = parse_addr(&value()?)?,
+            "--pc" => pc = Some(parse_addr(&value()?)?),
+            "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => program = Some(arg),
+        }
+    }
+    let program = program.ok_or("no program given")?;
+
+    let bytes = std::fs::read(&program).map_err(|e| format!("{}: {}", program, e))?;
+    let mut cpu = CPU6502::create_cpu_and_bus(load_addr);
+    formats::load_file(&program, &bytes, &mut cpu, load_addr, StartAction::SetPc)
+        .map_err(|e| format!("{}: {}", program, e))?;
+    if let Some(pc) = pc {
+        cpu.pc = pc;
+    }
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("port {}: {}", port, e))?;
+    let mut stub = GdbStub::new(cpu);
+    println!("{} loaded, PC ${:04X}, waiting for gdb on 127.0.0.1:{}", program, stub.cpu.pc, port);
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("gdb6502: {}", e);
+                continue;
+            }
+        };
+        println!("gdb connected");
+        match stub.serve(&mut stream) {
+            Ok(()) => println!("gdb left"),
+            Err(e) => println!("gdb connection lost: {}", e),
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = start() {
+        if !e.is_empty() {
+            eprintln!("gdb6502: {}\n", e);
+        }
+        eprintln!("{}", USAGE);
+        process::exit(1);
+    }
+}
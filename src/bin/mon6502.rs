@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+use std::process;
+
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+
+use simple_6502rs::emulator::cpu::CPU6502;
+use simple_6502rs::emulator::monitor::Monitor;
+
+/*
+*   Terminal front end for the monitor, `mon6502 [program [addr]]`
+*
+*   Line editing and history (kept in ~/.mon6502_history) come from rustyline
+*/
+
+fn main() {
+    let mut monitor = Monitor::new(CPU6502::create_cpu_and_bus(0x8000));
+
+    // same as typing l
+    let args: Vec<String> = std::env::args().skip(1).map(|x| format!("\"{}\"", x)).collect();
+    if !args.is_empty() {
+        match monitor.command(&format!("l {}", args.join(" "))) {
+            Ok(out) => println!("{}", out),
+            Err(e) => {
+                eprintln!("mon6502: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let history = std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".mon6502_history"));
+    let mut editor = Editor::<()>::new();
+    if let Some(path) = &history {
+        // first run won't have one
+        let _ = editor.load_history(path);
+    }
+
+    println!("simple_6502rs monitor, help lists the commands, x exits");
+    loop {
+        let line = match editor.readline(&monitor.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("mon6502: {}", e);
+                break;
+            }
+        };
+
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str());
+        }
+        if matches!(line.trim(), "x" | "q" | "quit" | "exit") {
+            break;
+        }
+
+        match monitor.command(&line) {
+            Ok(out) if out.is_empty() => (),
+            Ok(out) => println!("{}", out),
+            Err(e) => println!("error: {}", e),
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("mon6502: couldn't save history: {}", e);
+        }
+    }
+}
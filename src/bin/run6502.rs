@@ -0,0 +1,404 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use simple_6502rs::emulator::coverage::Coverage;
+use simple_6502rs::emulator::cpu::{CPU6502, Limit, StopReason};
+use simple_6502rs::emulator::debug_info::{self, DebugInfo};
+use simple_6502rs::emulator::code_map;
+use simple_6502rs::emulator::disassembler::{trace_line, ObjCode};
+use simple_6502rs::emulator::export::{self, Dialect};
+use simple_6502rs::emulator::formats::{self, StartAction};
+use simple_6502rs::emulator::label_files::{self, LabelFormat};
+use simple_6502rs::emulator::profiler::Profiler;
+use simple_6502rs::emulator::symbols::SymbolTable;
+
+/*
+*   Headless runner for scripts and CI, no window needed
+*
+*   Loads a program like the GUI's Load File does, runs it and prints
+*   the registers. The exit code says why it stopped.
+*/
+
+const USAGE : &str = "usage: run6502 [options] <program>
+
+Loads <program> by extension (.hex/.ihx, .s19/.srec, .prg, .xex, .nes, ELF)
+or as raw binary, then runs until BRK, a trap, a breakpoint, a jam or illegal
+opcode, or the cycle budget.
+Addresses are hex ($c000, 0xc000 or c000). LOC is $c000/0xc000, a symbol or file:line.
+
+options:
+  --load ADDR         where raw binaries go (default $8000)
+  --pc ADDR           start here instead of the program's start address
+  --reset ADDR        set the reset vector and start from it (unless --pc)
+  --irq ADDR          set the IRQ/BRK vector
+  --nmi ADDR          set the NMI vector
+  --max-cycles N      cycle budget, 0 for none (default 100000000)
+  --break LOC         stop before running LOC, can be repeated
+  --success LOC       trapping or stopping at LOC exits with 0 (test suites)
+  --labels FILE       VICE/Mesen/FCEUX labels or a cc65 .dbg file, can be repeated
+  --dump FROM:TO      print memory afterwards, can be repeated
+  --trace FILE        write a trace line per instruction ('-' for stdout)
+  --profile FILE      write cycle counts afterwards: FILE.csv per address (and
+                      FILE_subroutines.csv), FILE.folded as collapsed stacks
+                      (flamegraph.pl), anything else a readable report
+  --coverage FILE     write code coverage of the program afterwards: FILE.info or
+                      FILE.lcov as LCOV (by source line with a .dbg), else a report
+  --export FILE       write the program as source for ca65 (FILE.s), ACME (FILE.a)
+                      or 64tass (FILE.asm) instead of running it
+  --quiet             only print dumps
+
+exit codes:
+  0  BRK (or stopped at --success, or --export written)
+  1  bad arguments or the program couldn't be loaded
+  2  cycle budget used up
+  3  trapped, an instruction that jumps to itself (JMP *, BNE *)
+  4  breakpoint
+  5  a JAM or illegal opcode";
+
+const EXIT_BRK : i32 = 0;
+const EXIT_ERROR : i32 = 1;
+const EXIT_BUDGET : i32 = 2;
+const EXIT_TRAP : i32 = 3;
+const EXIT_BREAKPOINT : i32 = 4;
+const EXIT_ILLEGAL : i32 = 5;
+
+// cycles run at a time, get_total_cycles() is a u32
+const CHUNK_CYCLES : u64 = 1 << 30;
+
+struct Options {
+    program: String,
+    load_addr: u16,
+    pc: Option<u16>,
+    reset: Option<u16>,
+    irq: Option<u16>,
+    nmi: Option<u16>,
+    max_cycles: u64,
+    breaks: Vec<String>,
+    success: Option<String>,
+    labels: Vec<String>,
+    dumps: Vec<(u16, u16)>,
+    trace: Option<String>,
+    profile: Option<String>,
+    coverage: Option<String>,
+    export: Option<String>,
+    quiet: bool,
+}
+
+fn parse_addr(s: &str) -> Result<u16, String> {
+    let hex = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("'{}' isn't a hex address", s))
+}
+
+fn parse_range(s: &str) -> Result<(u16, u16), String> {
+    let (from, to) = s.split_once(':').or_else(|| s.split_once('-'))
+        .ok_or_else(|| format!("'{}' isn't a range, use FROM:TO", s))?;
+    let (from, to) = (parse_addr(from)?, parse_addr(to)?);
+    match from <= to {
+        true => Ok((from, to)),
+        false => Err(format!("range '{}' ends before it starts", s)),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut opts = Options {
+        program: String::new(),
+        load_addr: 0x8000,
+        pc: None,
+        reset: None,
+        irq: None,
+        nmi: None,
+        max_cycles: 100_000_000,
+        breaks: Vec::new(),
+        success: None,
+        labels: Vec::new(),
+        dumps: Vec::new(),
+        trace: None,
+        profile: None,
+        coverage: None,
+        export: None,
+        quiet: false,
+    };
+
+    let mut program = None;
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if program.replace(arg).is_some() {
+                return Err("only one program can be run".to_string());
+            }
+            continue;
+        }
+
+        if arg == "--quiet" {
+            opts.quiet = true;
+            continue;
+        }
+        if arg == "--help" {
+            return Err(String::new());
+        }
+
+        const TAKE_VALUE : [&str; 14] = ["--load", "--pc", "--reset", "--irq", "--nmi", "--max-cycles",
+            "--break", "--success", "--labels", "--dump", "--trace", "--profile", "--coverage", "--export"];
+        if !TAKE_VALUE.contains(&&arg[..]) {
+            return Err(format!("unknown option {}", arg));
+        }
+
+        let val = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+        match &arg[..] {
+            "--load" => opts.load_addr = parse_addr(&val)?,
+            "--pc" => opts.pc = Some(parse_addr(&val)?),
+            "--reset" => opts.reset = Some(parse_addr(&val)?),
+            "--irq" => opts.irq = Some(parse_addr(&val)?),
+            "--nmi" => opts.nmi = Some(parse_addr(&val)?),
+            "--max-cycles" => opts.max_cycles = val.parse()
+                .map_err(|_| format!("'{}' isn't a number of cycles", val))?,
+            "--break" => opts.breaks.push(val),
+            "--success" => opts.success = Some(val),
+            "--labels" => opts.labels.push(val),
+            "--dump" => opts.dumps.push(parse_range(&val)?),
+            "--trace" => opts.trace = Some(val),
+            "--profile" => opts.profile = Some(val),
+            "--coverage" => opts.coverage = Some(val),
+            "--export" => opts.export = Some(val),
+            _ => unreachable!(),
+        }
+    }
+
+    opts.program = program.ok_or("no program given")?;
+    Ok(opts)
+}
+
+/// Label files and .dbg files given with --labels, for the
+/// program in cpu (a cartridge changes Mesen's addresses)
+fn load_labels(paths: &[String], cpu: &CPU6502) -> Result<(SymbolTable, Option<DebugInfo>), String> {
+    let mut symbols = SymbolTable::new();
+    let mut debug = None;
+
+    for path in paths {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        if path.to_ascii_lowercase().ends_with(".dbg") {
+            let info = debug_info::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+            symbols.merge(&info.symbols);
+            debug = Some(info);
+        } else {
+            let format = LabelFormat::from_extension(path)
+                .ok_or_else(|| format!("{}: unknown label file type", path))?;
+            let labels = label_files::parse(format, &text, cpu.cartridge().map(|x| x.prg().len())).map_err(|e| format!("{}: {}", path, e))?;
+            symbols.merge(&labels);
+        }
+    }
+    Ok((symbols, debug))
+}
+
+/// Directory of the .dbg file, its source paths are relative to it
+fn debug_dir(labels: &[String]) -> PathBuf {
+    labels.iter()
+        .find(|x| x.to_ascii_lowercase().ends_with(".dbg"))
+        .and_then(|x| Path::new(x).parent())
+        .map(|x| x.to_path_buf())
+        .unwrap_or_default()
+}
+
+/// Source for everything the program loaded, traced from the PC
+/// (and the vectors if it covers them)
+fn export_program(cpu: &CPU6502, ranges: &[(u16, u16)], dialect: Dialect, symbols: &SymbolTable) -> Result<String, String> {
+    let from = ranges.iter().map(|x| x.0).min().ok_or("nothing was loaded to export")?;
+    let to = ranges.iter().map(|x| x.1).max().unwrap_or(from);
+    let bytes: Vec<u8> = (from..=to).map(|x| cpu.read(x)).collect();
+
+    let map = code_map::trace(&ObjCode::new(&bytes, from), &[cpu.pc]);
+    Ok(export::export_source(&map, dialect, Some(symbols)))
+}
+
+fn run(cpu: &mut CPU6502, max_cycles: u64, symbols: &SymbolTable, trace: &mut Option<Box<dyn Write>>)
+-> std::io::Result<(StopReason, u64)> {
+    let mut cycles = 0;
+    let mut error = None;
+
+    loop {
+        let chunk = match max_cycles {
+            0 => CHUNK_CYCLES,
+            max => (max - cycles).min(CHUNK_CYCLES),
+        };
+        let before = cpu.get_total_cycles();
+        let stop = cpu.run_until(Limit::Cycles(chunk), |cpu| match trace.as_mut().map(|out| writeln!(out, "{}", trace_line(cpu, Some(symbols)))) {
+            Some(Err(e)) => {
+                error = Some(e);
+                true
+            }
+            _ => false,
+        });
+        cycles += cpu.get_total_cycles().wrapping_sub(before) as u64;
+
+        if let Some(e) = error {
+            return Err(e);
+        }
+        // a chunk never ends on a breakpoint, so the next can't skip one
+        match stop {
+            StopReason::BudgetExhausted if max_cycles == 0 || cycles < max_cycles => (),
+            stop => return Ok((stop, cycles)),
+        }
+    }
+}
+
+fn print_registers(cpu: &CPU6502) {
+    let names = "NV-BDIZC";
+    let flags: String = names.chars().enumerate()
+        .map(|(i, c)| match cpu.status.bits() & (0x80 >> i) != 0 {
+            true => c,
+            false => '.',
+        })
+        .collect();
+
+    println!("PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} {}",
+        cpu.pc, cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.stk_ptr, cpu.status.bits(), flags);
+}
+
+fn dump(cpu: &CPU6502, from: u16, to: u16) {
+    let mut addr = from as u32;
+    while addr <= to as u32 {
+        let end = (addr + 15).min(to as u32);
+        let bytes: Vec<String> = (addr..=end).map(|x| format!("{:02X}", cpu.read(x as u16))).collect();
+        println!("{:04X}: {}", addr, bytes.join(" "));
+        addr = end + 1;
+    }
+}
+
+fn main() {
+    let opts = match parse_args(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("run6502: {}\n", e);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(EXIT_ERROR);
+        }
+    };
+
+    process::exit(match start(&opts) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("run6502: {}", e);
+            EXIT_ERROR
+        }
+    });
+}
+
+fn start(opts: &Options) -> Result<i32, String> {
+    let bytes = std::fs::read(&opts.program).map_err(|e| format!("{}: {}", opts.program, e))?;
+    let mut cpu = CPU6502::create_cpu_and_bus(opts.load_addr);
+
+    let loaded = formats::load_file(&opts.program, &bytes, &mut cpu, opts.load_addr, StartAction::SetPc)
+        .map_err(|e| format!("{}: {}", opts.program, e))?;
+    let (mut symbols, debug) = load_labels(&opts.labels, &cpu)?;
+    symbols.merge(&loaded.symbols);
+
+    // cartridges ignore these since their ROM is mapped there
+    for (vector, addr) in [(0xFFFA, opts.nmi), (0xFFFC, opts.reset), (0xFFFE, opts.irq)] {
+        if let Some(addr) = addr {
+            cpu.write(vector, addr as u8);
+            cpu.write(vector + 1, (addr >> 8) as u8);
+        }
+    }
+    match (opts.pc, opts.reset) {
+        (Some(pc), _) => cpu.pc = pc,
+        (None, Some(_)) => cpu.reset(),
+        (None, None) => (),
+    }
+
+    if let Some(path) = &opts.export {
+        let dialect = Dialect::from_extension(path)
+            .ok_or_else(|| format!("{}: --export needs a .s, .a or .asm file", path))?;
+        let text = export_program(&cpu, &loaded.ranges, dialect, &symbols)?;
+        std::fs::write(path, text).map_err(|e| format!("{}: {}", path, e))?;
+        if !opts.quiet {
+            println!("wrote {} as {} source", path, dialect.name());
+        }
+        return Ok(EXIT_BRK);
+    }
+
+    for spec in &opts.breaks {
+        cpu.breakpoints.extend(debug_info::resolve_location(spec, &symbols, debug.as_ref())?);
+    }
+    let success = match &opts.success {
+        Some(spec) => debug_info::resolve_location(spec, &symbols, debug.as_ref())?,
+        None => Vec::new(),
+    };
+
+    let mut trace: Option<Box<dyn Write>> = match opts.trace.as_deref() {
+        None => None,
+        Some("-") => Some(Box::new(std::io::stdout())),
+        Some(path) => Some(Box::new(BufWriter::new(
+            File::create(path).map_err(|e| format!("{}: {}", path, e))?))),
+    };
+
+    if opts.profile.is_some() {
+        cpu.profiler = Some(Profiler::new());
+    }
+    if opts.coverage.is_some() {
+        cpu.coverage = Some(Coverage::new(&loaded.ranges));
+    }
+
+    if !opts.quiet {
+        println!("loaded {} bytes from {}, starting at ${:04X}", loaded.len, opts.program, cpu.pc);
+    }
+
+    let (stop, cycles) = run(&mut cpu, opts.max_cycles, &symbols, &mut trace)
+        .map_err(|e| format!("writing trace: {}", e))?;
+    if let Some(out) = trace.as_mut() {
+        out.flush().map_err(|e| format!("writing trace: {}", e))?;
+    }
+
+    let at = match symbols.name_of(cpu.pc) {
+        Some(name) => format!("${:04X} ({})", cpu.pc, name),
+        None => format!("${:04X}", cpu.pc),
+    };
+    let (code, reason) = match stop {
+        _ if stop != StopReason::BudgetExhausted && success.contains(&cpu.pc) => (EXIT_BRK, format!("reached {}", at)),
+        StopReason::Brk(_) => (EXIT_BRK, format!("BRK at {}", at)),
+        StopReason::BudgetExhausted => (EXIT_BUDGET, format!("cycle budget used up at {}", at)),
+        StopReason::Trap(_) => (EXIT_TRAP, format!("trapped at {}", at)),
+        StopReason::Breakpoint(_) => (EXIT_BREAKPOINT, format!("breakpoint at {}", at)),
+        StopReason::Jam(_) => (EXIT_ILLEGAL, format!("jammed at {}", at)),
+        StopReason::IllegalOpcode(_, op) => (EXIT_ILLEGAL, format!("illegal opcode ${:02X} at {}", op, at)),
+        // nothing sets watchpoints or stops the hook
+        StopReason::Watchpoint(..) | StopReason::Done => unreachable!(),
+    };
+
+    if !opts.quiet {
+        println!("{} after {} cycles", reason, cycles);
+        print_registers(&cpu);
+    }
+    for (from, to) in &opts.dumps {
+        dump(&cpu, *from, *to);
+    }
+
+    if let (Some(path), Some(profiler)) = (&opts.profile, &cpu.profiler) {
+        let text = match path.rsplit('.').next() {
+            Some("csv") => {
+                let subs_path = format!("{}_subroutines.csv", path.trim_end_matches(".csv"));
+                std::fs::write(&subs_path, profiler.subroutine_csv(Some(&symbols)))
+                    .map_err(|e| format!("{}: {}", subs_path, e))?;
+                profiler.csv(Some(&symbols))
+            }
+            Some("folded") | Some("collapsed") => profiler.collapsed(Some(&symbols)),
+            _ => profiler.report(Some(&symbols), 50),
+        };
+        std::fs::write(path, text).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    if let (Some(path), Some(coverage)) = (&opts.coverage, &cpu.coverage) {
+        let text = match path.rsplit('.').next() {
+            Some("info") | Some("lcov") => {
+                let base_dir = debug_dir(&opts.labels);
+                coverage.lcov(&opts.program, debug.as_ref(), &base_dir, Some(&symbols), &cpu)
+            }
+            _ => coverage.report(Some(&symbols), debug.as_ref(), &cpu),
+        };
+        std::fs::write(path, text).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    Ok(code)
+}
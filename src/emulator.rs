@@ -1,5 +1,22 @@
 pub mod cpu;
 pub mod disassembler;
+pub mod assembler;
+pub mod symbols;
+pub mod code_map;
+pub mod export;
+pub mod cfg;
+pub mod formats;
+pub mod cartridge;
+pub mod debug_info;
+pub mod label_files;
+pub mod monitor;
+pub mod gdb_stub;
+pub mod call_stack;
+pub mod profiler;
+pub mod coverage;
+pub mod heatmap;
+#[cfg(feature = "dap")]
+pub mod dap;
 
 mod bus;
 mod instruction;
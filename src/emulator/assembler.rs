@@ -0,0 +1,881 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::emulator::instruction::{CPU_INSTRUCTIONS, find_instruction, AddressingMode::{self, *}};
+
+/*
+*   Small two pass assembler, mostly for the GUI's editor.
+*
+*   Supports:
+*       labels          -> `loop:` (or `loop` in column 0)
+*       constants       -> `SCREEN = $0200`
+*       origin          -> `.org $8000` or `*= $8000`
+*       data            -> `.byte 1, $02, "hi"` `.word label` `.res 4, $ff`
+*       expressions     -> `$ff` `%1010` `10` `'a'` `label+1` `<label` `>label` `*`
+*       comments        -> `; anything`
+*       ignored         -> `.setcpu` `.cpu` `!cpu` `.segment`
+*
+*   ZP vs ABS: if an operand's value is known in the first pass
+*   and fits in a byte, the zero page form is used.
+*/
+
+/// Error found while assembling, line is 0 indexed
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Object code produced by assemble()
+pub struct Assembly {
+    /// Contiguous blocks of object code as (start address, bytes)
+    pub chunks: Vec<(u16, Vec<u8>)>,
+
+    /// Labels and constants defined in the source
+    pub symbols: HashMap<String, u16>,
+
+    // address of every line that emitted bytes -> line
+    addr_to_line: BTreeMap<u16, usize>,
+}
+
+impl Assembly {
+    /// Source line (0 indexed) that emitted the byte at addr
+    pub fn line_of(&self, addr: u16) -> Option<usize> {
+        self.addr_to_line.get(&addr).copied()
+    }
+
+    /// First address emitted by a source line
+    pub fn addr_of(&self, line: usize) -> Option<u16> {
+        self.addr_to_line.iter().find(|(_, l)| **l == line).map(|(a, _)| *a)
+    }
+
+    /// Total amount of bytes emitted
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|(_, c)| c.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Kind of token, used for syntax highlighting
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Label,
+    Mnemonic,
+    Directive,
+    Number,
+    Str,
+    Identifier,
+    Comment,
+    Text,
+}
+
+lazy_static! {
+    static ref MNEMONICS : HashSet<&'static str> = {
+        CPU_INSTRUCTIONS.iter().map(|x| x.name).filter(|name| *name != "IDK").collect()
+    };
+}
+
+fn is_mnemonic(word: &str) -> bool {
+    MNEMONICS.contains(&word.to_ascii_uppercase()[..])
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Splits a line into (code, comment), ignoring ';' inside quotes
+fn split_comment(line: &str) -> (&str, &str) {
+    let mut quote = None;
+
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ';' => return (&line[..i], &line[i..]),
+            None => (),
+        }
+    }
+    (line, "")
+}
+
+/// Splits a single line into highlighted tokens. Concatenating the
+/// token text gives back the original line.
+pub fn highlight_line(line: &str) -> Vec<(TokenKind, &str)> {
+    let (code, comment) = split_comment(line);
+    let mut ret = Vec::new();
+
+    let bytes = code.as_bytes();
+    let mut i = 0;
+    // first word of a statement (mnemonic or directive)
+    let mut expect_op = true;
+
+    while i < bytes.len() {
+        let c = code[i..].chars().next().unwrap();
+        let start = i;
+
+        if c.is_whitespace() || c == ',' || c == '#' || c == '(' || c == ')' || c == '+' || c == '-' || c == '<' || c == '>' {
+            i += c.len_utf8();
+            ret.push((TokenKind::Text, &code[start..i]));
+
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            while i < bytes.len() && bytes[i] as char != c {
+                i += 1;
+            }
+            i = (i + 1).min(bytes.len());
+            ret.push((TokenKind::Str, &code[start..i]));
+
+        } else if c == '$' || c == '%' || c.is_ascii_digit() {
+            i += 1;
+            while i < bytes.len() && (bytes[i] as char).is_ascii_alphanumeric() {
+                i += 1;
+            }
+            ret.push((TokenKind::Number, &code[start..i]));
+
+        } else if c == '*' && code[i..].trim_start_matches('*').trim_start().starts_with('=') && expect_op {
+            i += 1;
+            ret.push((TokenKind::Directive, &code[start..i]));
+            expect_op = false;
+
+        } else if c == '.' || c == '!' || is_ident_start(c) {
+            i += 1;
+            while i < bytes.len() && is_ident_char(bytes[i] as char) {
+                i += 1;
+            }
+            let word = &code[start..i];
+
+            let kind = if c == '.' || c == '!' {
+                expect_op = false;
+                TokenKind::Directive
+            } else if bytes.get(i) == Some(&b':') {
+                i += 1;
+                TokenKind::Label
+            } else if start == 0 && !is_mnemonic(word) {
+                TokenKind::Label
+            } else if expect_op && is_mnemonic(word) {
+                expect_op = false;
+                TokenKind::Mnemonic
+            } else {
+                TokenKind::Identifier
+            };
+
+            ret.push((kind, &code[start..i]));
+
+        } else {
+            i += c.len_utf8();
+            ret.push((TokenKind::Text, &code[start..i]));
+        }
+    }
+
+    if !comment.is_empty() {
+        ret.push((TokenKind::Comment, comment));
+    }
+
+    ret
+}
+
+// =============================
+/* Expressions */
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Num(i32),
+    Sym(String),
+    Pc,
+    Lo(Box<Expr>),
+    Hi(Box<Expr>),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Err holds the name of an undefined symbol
+    fn eval(&self, symbols: &HashMap<String, u16>, pc: u16) -> Result<i32, String> {
+        Ok(match self {
+            Expr::Num(n) => *n,
+            Expr::Pc => pc as i32,
+            Expr::Sym(name) => match symbols.get(name) {
+                Some(v) => *v as i32,
+                None => return Err(name.clone()),
+            },
+            Expr::Lo(e) => e.eval(symbols, pc)? & 0xFF,
+            Expr::Hi(e) => (e.eval(symbols, pc)? >> 8) & 0xFF,
+            Expr::Neg(e) => -e.eval(symbols, pc)?,
+            Expr::Add(a, b) => a.eval(symbols, pc)? + b.eval(symbols, pc)?,
+            Expr::Sub(a, b) => a.eval(symbols, pc)? - b.eval(symbols, pc)?,
+        })
+    }
+}
+
+struct ExprParser<'a> {
+    s: &'a str,
+    i: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.s[self.i..].chars().next()
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.i += c.len_utf8();
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.i;
+        while let Some(c) = self.peek() {
+            if !f(c) {
+                break;
+            }
+            self.i += c.len_utf8();
+        }
+        &self.s[start..self.i]
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+
+        loop {
+            self.skip_ws();
+            match self.peek() {
+                Some('+') => {
+                    self.i += 1;
+                    lhs = Expr::Add(Box::new(lhs), Box::new(self.term()?));
+                }
+                Some('-') => {
+                    self.i += 1;
+                    lhs = Expr::Sub(Box::new(lhs), Box::new(self.term()?));
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        self.skip_ws();
+
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err("expected a value".into()),
+        };
+
+        let radix_num = |digits: &str, radix: u32| {
+            i32::from_str_radix(digits, radix)
+                .map(Expr::Num)
+                .map_err(|_| format!("invalid number '{}'", digits))
+        };
+
+        match c {
+            '<' => { self.i += 1; Ok(Expr::Lo(Box::new(self.term()?))) }
+            '>' => { self.i += 1; Ok(Expr::Hi(Box::new(self.term()?))) }
+            '-' => { self.i += 1; Ok(Expr::Neg(Box::new(self.term()?))) }
+            '*' => { self.i += 1; Ok(Expr::Pc) }
+            '$' => {
+                self.i += 1;
+                let digits = self.take_while(|c| c.is_ascii_hexdigit());
+                radix_num(digits, 16)
+            }
+            '%' => {
+                self.i += 1;
+                let digits = self.take_while(|c| c == '0' || c == '1');
+                radix_num(digits, 2)
+            }
+            '\'' => {
+                let mut chars = self.s[self.i + 1..].chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), Some('\'')) if ch.is_ascii() => {
+                        self.i += 3;
+                        Ok(Expr::Num(ch as i32))
+                    }
+                    _ => Err("invalid character literal".into())
+                }
+            }
+            _ if self.s[self.i..].starts_with("0x") || self.s[self.i..].starts_with("0X") => {
+                self.i += 2;
+                let digits = self.take_while(|c| c.is_ascii_hexdigit());
+                radix_num(digits, 16)
+            }
+            _ if c.is_ascii_digit() => {
+                let digits = self.take_while(|c| c.is_ascii_digit());
+                radix_num(digits, 10)
+            }
+            _ if is_ident_start(c) => {
+                let name = self.take_while(is_ident_char);
+                Ok(Expr::Sym(name.to_string()))
+            }
+            _ => Err(format!("unexpected '{}'", c)),
+        }
+    }
+}
+
+fn parse_expr(s: &str) -> Result<Expr, String> {
+    let mut p = ExprParser { s, i: 0 };
+    let e = p.expr()?;
+    p.skip_ws();
+
+    match p.peek() {
+        None => Ok(e),
+        Some(c) => Err(format!("unexpected '{}'", c)),
+    }
+}
+
+/// Splits on commas that aren't inside quotes
+fn split_args(s: &str) -> Vec<&str> {
+    let mut ret = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ',' => {
+                ret.push(s[start..i].trim());
+                start = i + 1;
+            }
+            None => (),
+        }
+    }
+    ret.push(s[start..].trim());
+    ret
+}
+
+// =============================
+/* Statements */
+
+#[derive(Clone, Debug)]
+enum Operand {
+    None,
+    Acc,
+    Imm(Expr),
+    Plain(Expr),
+    IndexX(Expr),
+    IndexY(Expr),
+    Ind(Expr),
+    IndX(Expr),
+    IndY(Expr),
+}
+
+#[derive(Clone, Debug)]
+enum DataItem {
+    Value(Expr),
+    Text(String),
+}
+
+#[derive(Clone, Debug)]
+enum Stmt {
+    Empty,
+    Org(Expr),
+    Bytes(Vec<DataItem>),
+    Words(Vec<Expr>),
+    Res(Expr, Option<Expr>),
+    Instr(String, Operand),
+}
+
+struct Line {
+    label: Option<String>,
+    constant: Option<(String, Expr)>,
+    stmt: Stmt,
+}
+
+fn ends_with_reg(s: &str, reg: char) -> Option<&str> {
+    let t = s.trim_end();
+    let mut it = t.chars().rev();
+    match (it.next(), it.next()) {
+        (Some(r), Some(',')) if r.eq_ignore_ascii_case(&reg) => Some(&t[..t.len() - 2]),
+        _ => None,
+    }
+}
+
+fn parse_operand(s: &str) -> Result<Operand, String> {
+    let s = s.trim();
+
+    if s.is_empty() {
+        return Ok(Operand::None);
+    }
+    if s.eq_ignore_ascii_case("a") {
+        return Ok(Operand::Acc);
+    }
+    if let Some(rest) = s.strip_prefix('#') {
+        return Ok(Operand::Imm(parse_expr(rest)?));
+    }
+
+    if let Some(inner) = s.strip_prefix('(') {
+        // (zp),Y
+        if let Some(before) = ends_with_reg(inner, 'y') {
+            let before = before.trim_end();
+            return match before.strip_suffix(')') {
+                Some(e) => Ok(Operand::IndY(parse_expr(e)?)),
+                None => Err("expected ')' before ',Y'".into()),
+            };
+        }
+
+        let inner = match inner.trim_end().strip_suffix(')') {
+            Some(x) => x,
+            None => return Err("missing ')'".into()),
+        };
+
+        // (zp,X)
+        if let Some(e) = ends_with_reg(inner, 'x') {
+            return Ok(Operand::IndX(parse_expr(e)?));
+        }
+        return Ok(Operand::Ind(parse_expr(inner)?));
+    }
+
+    if let Some(e) = ends_with_reg(s, 'x') {
+        return Ok(Operand::IndexX(parse_expr(e)?));
+    }
+    if let Some(e) = ends_with_reg(s, 'y') {
+        return Ok(Operand::IndexY(parse_expr(e)?));
+    }
+
+    Ok(Operand::Plain(parse_expr(s)?))
+}
+
+fn parse_data(args: &str) -> Result<Vec<DataItem>, String> {
+    split_args(args).into_iter().map(|arg| {
+        if arg.len() >= 2 && arg.starts_with('"') && arg.ends_with('"') {
+            Ok(DataItem::Text(arg[1..arg.len() - 1].to_string()))
+        } else {
+            parse_expr(arg).map(DataItem::Value)
+        }
+    }).collect()
+}
+
+fn parse_directive(name: &str, args: &str) -> Result<Stmt, String> {
+    let lower = name.to_ascii_lowercase();
+
+    match &lower[..] {
+        ".org" => Ok(Stmt::Org(parse_expr(args)?)),
+
+        ".byte" | ".byt" | ".db" | "!byte" | "!by" | "!8" => Ok(Stmt::Bytes(parse_data(args)?)),
+
+        ".word" | ".dw" | "!word" | "!wo" | "!16" => {
+            let words = split_args(args).into_iter().map(parse_expr).collect::<Result<_, _>>()?;
+            Ok(Stmt::Words(words))
+        }
+
+        ".res" | ".fill" | "!fill" => {
+            let parts = split_args(args);
+            let fill = match parts.get(1) {
+                Some(x) => Some(parse_expr(x)?),
+                None => None,
+            };
+            Ok(Stmt::Res(parse_expr(parts[0])?, fill))
+        }
+
+        // other assemblers' headers, only 6502 is supported anyway
+        ".setcpu" | ".cpu" | "!cpu" | ".segment" => Ok(Stmt::Empty),
+
+        _ => Err(format!("unknown directive '{}'", name)),
+    }
+}
+
+fn parse_line(line: &str) -> Result<Line, String> {
+    let (code, _) = split_comment(line);
+
+    let mut ret = Line { label: None, constant: None, stmt: Stmt::Empty };
+    let mut rest = code.trim_end();
+
+    // `*= expr`
+    if let Some(x) = rest.trim_start().strip_prefix('*') {
+        if let Some(e) = x.trim_start().strip_prefix('=') {
+            ret.stmt = Stmt::Org(parse_expr(e)?);
+            return Ok(ret);
+        }
+    }
+
+    // leading identifier might be a label or a constant
+    let trimmed = rest.trim_start();
+    let at_col_0 = trimmed.len() == rest.len();
+    let ident_len = trimmed.find(|c: char| !is_ident_char(c)).unwrap_or(trimmed.len());
+
+    if ident_len > 0 && trimmed.starts_with(is_ident_start) {
+        let word = &trimmed[..ident_len];
+        let after = trimmed[ident_len..].trim_start();
+
+        if let Some(e) = after.strip_prefix('=') {
+            ret.constant = Some((word.to_string(), parse_expr(e)?));
+            return Ok(ret);
+        } else if let Some(x) = trimmed[ident_len..].strip_prefix(':') {
+            ret.label = Some(word.to_string());
+            rest = x;
+        } else if at_col_0 && !is_mnemonic(word) {
+            ret.label = Some(word.to_string());
+            rest = &trimmed[ident_len..];
+        }
+    }
+
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return Ok(ret);
+    }
+
+    let split = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let (word, args) = (&rest[..split], rest[split..].trim());
+
+    ret.stmt = if word.starts_with('.') || word.starts_with('!') {
+        parse_directive(word, args)?
+    } else if is_mnemonic(word) {
+        Stmt::Instr(word.to_ascii_uppercase(), parse_operand(args)?)
+    } else {
+        return Err(format!("unknown instruction '{}'", word));
+    };
+
+    Ok(ret)
+}
+
+fn has_mode(name: &str, mode: AddressingMode) -> bool {
+    find_instruction(name, mode).is_some()
+}
+
+/// Picks the addressing mode during the first pass,
+/// which also determines the instruction's size
+fn select_mode(name: &str, operand: &Operand, symbols: &HashMap<String, u16>, pc: u16)
+-> Result<AddressingMode, String> {
+    // known and fits in a byte
+    let is_zp = |e: &Expr| matches!(e.eval(symbols, pc), Ok(v) if (0..=0xFF).contains(&v));
+
+    let mode = match operand {
+        Operand::None => if has_mode(name, IMP) { IMP } else { ACC },
+        Operand::Acc => ACC,
+        Operand::Imm(_) => IMM,
+        Operand::Ind(_) => IND,
+        Operand::IndX(_) => IDX,
+        Operand::IndY(_) => IDY,
+        Operand::Plain(e) => {
+            if has_mode(name, REL) {
+                REL
+            } else if is_zp(e) && has_mode(name, ZP0) {
+                ZP0
+            } else {
+                ABS
+            }
+        }
+        Operand::IndexX(e) => if is_zp(e) && has_mode(name, ZPX) { ZPX } else { ABX },
+        Operand::IndexY(e) => if is_zp(e) && has_mode(name, ZPY) { ZPY } else { ABY },
+    };
+
+    if has_mode(name, mode) {
+        Ok(mode)
+    } else {
+        Err(format!("{} does not support {:?} addressing", name, mode))
+    }
+}
+
+fn instr_size(mode: AddressingMode) -> u16 {
+    match mode {
+        IMP | ACC => 1,
+        IMM | REL | ZP0 | ZPX | ZPY | IDX | IDY => 2,
+        ABS | ABX | ABY | IND => 3,
+    }
+}
+
+fn data_size(items: &[DataItem]) -> u16 {
+    items.iter().map(|x| match x {
+        DataItem::Value(_) => 1,
+        DataItem::Text(s) => s.len() as u16,
+    }).sum()
+}
+
+/// Assembles source, with `origin` used until the first `.org`
+///
+/// Returns every error found, not just the first
+pub fn assemble(source: &str, origin: u16) -> Result<Assembly, Vec<AsmError>> {
+    let mut errors = Vec::new();
+    let mut symbols: HashMap<String, u16> = HashMap::new();
+
+    // parsed line, address and chosen mode
+    let mut parsed: Vec<Option<(Line, u16, Option<AddressingMode>)>> = Vec::new();
+
+    /* first pass: parse, define labels and size everything */
+    let mut pc: u32 = origin as u32;
+    for (n, text) in source.lines().enumerate() {
+        let mut err = |message: String| errors.push(AsmError { line: n, message });
+
+        let line = match parse_line(text) {
+            Ok(l) => l,
+            Err(msg) => {
+                err(msg);
+                parsed.push(None);
+                continue;
+            }
+        };
+
+        if let Some(label) = &line.label {
+            if symbols.insert(label.clone(), pc as u16).is_some() {
+                err(format!("'{}' defined twice", label));
+            }
+        }
+
+        if let Some((name, e)) = &line.constant {
+            match e.eval(&symbols, pc as u16) {
+                Ok(v) => { symbols.insert(name.clone(), v as u16); }
+                Err(undef) => err(format!("'{}' must be defined before '{}'", undef, name)),
+            }
+        }
+
+        let addr = pc as u16;
+        let mut mode = None;
+
+        let size = match &line.stmt {
+            Stmt::Empty => 0,
+            Stmt::Org(e) => match e.eval(&symbols, pc as u16) {
+                Ok(v) => { pc = (v as u32) & 0xFFFF; 0 }
+                Err(undef) => { err(format!("'{}' must be defined before .org", undef)); 0 }
+            },
+            Stmt::Bytes(items) => data_size(items),
+            Stmt::Words(words) => 2 * words.len() as u16,
+            Stmt::Res(count, _) => match count.eval(&symbols, pc as u16) {
+                Ok(v) if (0..=0xFFFF).contains(&v) => v as u16,
+                Ok(v) if v < 0 => { err("negative .res size".into()); 0 }
+                Ok(v) => { err(format!(".res size ${:X} is past $FFFF", v)); 0 }
+                Err(undef) => { err(format!("'{}' must be defined before .res", undef)); 0 }
+            },
+            Stmt::Instr(name, operand) => match select_mode(name, operand, &symbols, pc as u16) {
+                Ok(m) => { mode = Some(m); instr_size(m) }
+                Err(msg) => { err(msg); 0 }
+            },
+        };
+
+        let addr = if let Stmt::Org(_) = line.stmt { pc as u16 } else { addr };
+        // only the line that crosses it, not every one after
+        let before = pc;
+        pc += size as u32;
+        if pc > 0x10000 && before <= 0x10000 {
+            err("program runs past $FFFF".into());
+        }
+
+        parsed.push(Some((line, addr, mode)));
+    }
+
+    /* second pass: emit bytes */
+    let mut chunks: Vec<(u16, Vec<u8>)> = Vec::new();
+    let mut addr_to_line = BTreeMap::new();
+
+    for (n, entry) in parsed.iter().enumerate() {
+        let (line, addr, mode) = match entry {
+            Some(x) => x,
+            None => continue,
+        };
+        let addr = *addr;
+
+        let mut err = |message: String| errors.push(AsmError { line: n, message });
+        let mut eval = |e: &Expr| match e.eval(&symbols, addr) {
+            Ok(v) => Some(v),
+            Err(undef) => { err(format!("undefined symbol '{}'", undef)); None }
+        };
+
+        let mut out: Vec<u8> = Vec::new();
+        let mut misfits = Vec::new();
+        // value, or 0 after noting it doesn't fit
+        let mut fit = |v: i32, range: std::ops::RangeInclusive<i32>, what: &str| match range.contains(&v) {
+            true => v,
+            false => {
+                misfits.push(format!("value ${:X} does not fit in a {}", v, what));
+                0
+            }
+        };
+
+        match &line.stmt {
+            Stmt::Empty | Stmt::Org(_) => (),
+            Stmt::Bytes(items) => for item in items {
+                match item {
+                    DataItem::Text(s) => out.extend(s.bytes()),
+                    DataItem::Value(e) => out.push(fit(eval(e).unwrap_or(0), -128..=0xFF, "byte") as u8),
+                }
+            },
+            Stmt::Words(words) => for w in words {
+                let v = fit(eval(w).unwrap_or(0), -0x8000..=0xFFFF, "word") as u16;
+                out.push(v as u8);
+                out.push((v >> 8) as u8);
+            },
+            Stmt::Res(count, fill) => {
+                // a bad size was reported by the first pass
+                let count = eval(count).unwrap_or(0).clamp(0, 0xFFFF) as usize;
+                let fill = fit(fill.as_ref().and_then(&mut eval).unwrap_or(0), -128..=0xFF, "byte") as u8;
+                out.resize(count, fill);
+            }
+            Stmt::Instr(name, operand) => {
+                let mode = match mode {
+                    Some(m) => *m,
+                    None => continue,
+                };
+                let instr = find_instruction(name, mode).unwrap();
+                out.push(instr.opcode);
+
+                let value = match operand {
+                    Operand::None | Operand::Acc => None,
+                    Operand::Imm(e) | Operand::Plain(e) | Operand::IndexX(e) | Operand::IndexY(e) |
+                    Operand::Ind(e) | Operand::IndX(e) | Operand::IndY(e) => eval(e),
+                };
+
+                match (mode, value) {
+                    // error already reported, keep the size consistent
+                    (_, None) => out.resize(instr_size(mode) as usize, 0),
+                    (REL, Some(target)) => {
+                        let delta = target - (addr as i32 + 2);
+                        if !(-128..=127).contains(&delta) {
+                            errors.push(AsmError { line: n, message: format!("branch out of range ({} bytes)", delta) });
+                        }
+                        out.push(delta as u8);
+                    }
+                    (_, Some(v)) if instr_size(mode) == 2 => {
+                        if !(-128..=0xFF).contains(&v) {
+                            errors.push(AsmError { line: n, message: format!("value ${:X} does not fit in a byte", v) });
+                        }
+                        out.push(v as u8);
+                    }
+                    (_, Some(v)) => {
+                        let v = fit(v, -0x8000..=0xFFFF, "word");
+                        out.push(v as u8);
+                        out.push((v >> 8) as u8);
+                    }
+                }
+            }
+        }
+        errors.extend(misfits.into_iter().map(|message| AsmError { line: n, message }));
+
+        if out.is_empty() {
+            continue;
+        }
+
+        addr_to_line.insert(addr, n);
+
+        // extend the last chunk if contiguous
+        match chunks.last_mut() {
+            Some((start, bytes)) if *start as usize + bytes.len() == addr as usize => {
+                bytes.extend(out);
+            }
+            _ => chunks.push((addr, out)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Assembly { chunks, symbols, addr_to_line })
+    } else {
+        errors.sort_by_key(|e| e.line);
+        Err(errors)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes_of(src: &str, origin: u16) -> Vec<u8> {
+        let asm = assemble(src, origin).unwrap_or_else(|e| panic!("{:?}", e));
+        assert_eq!(asm.chunks.len(), 1);
+        asm.chunks[0].1.clone()
+    }
+
+    #[test]
+    fn simple_program() {
+        let src = "
+            LDA #$01
+            STA $0200
+            ldx #5
+            stx $10
+            ASL A
+            lsr
+            jmp ($1234)
+            lda ($20,x)
+            sta ($20),Y
+            rts";
+
+        assert_eq!(bytes_of(src, 0x8000), vec![
+            0xa9, 0x01, 0x8d, 0x00, 0x02, 0xa2, 0x05, 0x86, 0x10, 0x0a, 0x4a,
+            0x6c, 0x34, 0x12, 0xa1, 0x20, 0x91, 0x20, 0x60
+        ]);
+    }
+
+    #[test]
+    fn labels_and_branches() {
+        let src = "
+        start:  ldx #$03
+        loop:   dex
+                bne loop
+                beq done
+                jsr start
+        done:   brk";
+
+        assert_eq!(bytes_of(src, 0x8000), vec![
+            0xa2, 0x03, 0xca, 0xd0, 0xfd, 0xf0, 0x03, 0x20, 0x00, 0x80, 0x00
+        ]);
+    }
+
+    #[test]
+    fn directives_and_expressions() {
+        let src = "
+SCREEN = $0200
+        *= $c000
+table   .byte 1, %11, 'a', \"hi\"
+        .word table, SCREEN+1
+        lda #<table
+        ldy #>table
+        .res 2, $ea";
+
+        let asm = assemble(src, 0x8000).unwrap();
+        assert_eq!(asm.chunks[0].0, 0xc000);
+        assert_eq!(asm.chunks[0].1, vec![
+            1, 3, 0x61, 0x68, 0x69, 0x00, 0xc0, 0x01, 0x02, 0xa9, 0x00, 0xa0, 0xc0, 0xea, 0xea
+        ]);
+        assert_eq!(asm.symbols["table"], 0xc000);
+    }
+
+    #[test]
+    fn line_mapping() {
+        let src = "; comment\n  lda #1\n\n  sta $0200\n";
+        let asm = assemble(src, 0x0600).unwrap();
+
+        assert_eq!(asm.line_of(0x0600), Some(1));
+        assert_eq!(asm.line_of(0x0602), Some(3));
+        assert_eq!(asm.line_of(0x0601), None);
+        assert_eq!(asm.addr_of(3), Some(0x0602));
+    }
+
+    #[test]
+    fn reports_errors_with_lines() {
+        let src = "  lda #1\n  foo $10\n  bne far\n  sta (1)\n";
+        let errors = assemble(src, 0x8000).err().unwrap();
+
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![1, 2, 3]);
+
+        // values that don't fit, and running past $FFFF once
+        let src = "  .byte $1234\n  .word $10000\n  .res $10000\n  .org $fffe\n  nop\n  nop\n  nop\n  nop\n";
+        let errors = assemble(src, 0x8000).err().unwrap();
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![0, 1, 2, 6]);
+        assert!(errors[0].message.contains("byte"));
+    }
+
+    #[test]
+    fn highlighting_round_trips() {
+        let line = "loop:  lda #$10 ; load";
+        let tokens = highlight_line(line);
+
+        let joined: String = tokens.iter().map(|(_, t)| *t).collect();
+        assert_eq!(joined, line);
+
+        assert_eq!(tokens[0], (TokenKind::Label, "loop:"));
+        assert!(tokens.contains(&(TokenKind::Mnemonic, "lda")));
+        assert!(tokens.contains(&(TokenKind::Number, "$10")));
+        assert_eq!(tokens.last(), Some(&(TokenKind::Comment, "; load")));
+    }
+}
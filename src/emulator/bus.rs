@@ -1,4 +1,5 @@
 use crate::emulator::memory::{CPU_RAM, Memory};
+use crate::emulator::cartridge::Cartridge;
 
 /*
 *   Will handle memory mirroring here
@@ -10,6 +11,9 @@ pub struct Bus{
 
     cpu_ram : CPU_RAM, // 64KB for now
 
+    // maps over cpu_ram where it has something
+    cartridge : Option<Cartridge>,
+
 }
 
 
@@ -17,9 +21,28 @@ impl Bus{
     pub fn new() -> Bus{
         Bus{
             cpu_ram : CPU_RAM::new(),
+            cartridge : None,
         }
     }
 
+    /// ROM is also copied into RAM so memory views (index_memory) show it
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        for addr in 0x8000..=0xFFFF {
+            if let Some(val) = cartridge.cpu_read(addr) {
+                self.cpu_ram.write(addr, val);
+            }
+        }
+        self.cartridge = Some(cartridge);
+    }
+
+    pub fn remove_cartridge(&mut self) -> Option<Cartridge> {
+        self.cartridge.take()
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
     /// Indexes from start..=end
     pub fn index_memory(&self, start: u16, end: u16) -> Option<&[u8]> {
         self.cpu_ram.index_memory(start, end)
@@ -28,6 +51,12 @@ impl Bus{
 
     pub fn write(&mut self, addr : u16, val : u8) {
 
+        if let Some(cart) = &mut self.cartridge {
+            if cart.cpu_write(addr, val) {
+                return;
+            }
+        }
+
         match addr {
             0x0000..=0xFFFF => {
                 self.cpu_ram.write(addr, val);
@@ -36,7 +65,19 @@ impl Bus{
 
     } 
 
+    /// Reads two bytes, following little endian
+    pub fn read_u16(&self, addr: u16) -> u16 {
+        match self.cartridge {
+            Some(_) => (self.read(addr.wrapping_add(1)) as u16) << 8 | self.read(addr) as u16,
+            None => self.cpu_ram.read_u16(addr),
+        }
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
+        if let Some(val) = self.cartridge.as_ref().and_then(|x| x.cpu_read(addr)) {
+            return val;
+        }
+
         match addr{
             0x0000..=0xFFFF => {
                 self.cpu_ram.read(addr)
@@ -0,0 +1,219 @@
+use std::collections::{BTreeMap, VecDeque};
+
+/*
+*   Shadow call stack, kept by the CPU as instructions run
+*
+*   JSR, BRK and interrupts push a frame, and frames are dropped once the
+*   stack pointer moves above their return address, so it follows the real
+*   stack through TXS and friends too. Returns that don't match the frame
+*   they pop (RTS jump tables, changed return addresses) are noted instead
+*   of guessed at.
+*/
+
+// notes kept, oldest are dropped
+const MAX_NOTES : usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    Jsr,
+    Brk,
+    Irq,
+    Nmi,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// The JSR/BRK, or where the interrupt came in
+    pub from: u16,
+    /// Subroutine or handler
+    pub target: u16,
+    /// Where the RTS/RTI should go
+    pub return_to: u16,
+    /// Stack pointer after the push, the frame's bytes are just above it
+    pub sp: u8,
+}
+
+impl Frame {
+    /// Bytes it takes on the stack
+    pub fn size(&self) -> u8 {
+        match self.kind {
+            FrameKind::Jsr => 2,
+            _ => 3,
+        }
+    }
+}
+
+/// What a byte on the stack is, as far as the call stack knows
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Slot {
+    /// Return address byte of frames()[n]
+    Return(usize),
+    /// Status pushed by an interrupt, frames()[n]
+    Status(usize),
+    /// Pushed by PHA/PHP at the address
+    Pha(u16),
+    Php(u16),
+    Unknown,
+}
+
+#[derive(Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    // stack slot (SP when pushed) -> (PHP?, address of the push)
+    pushes: BTreeMap<u8, (bool, u16)>,
+    notes: VecDeque<String>,
+}
+
+impl CallStack {
+    /// Innermost last
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Things the stack tracking couldn't follow normally, newest last
+    pub fn notes(&self) -> impl Iterator<Item = &str> {
+        self.notes.iter().map(|x| &x[..])
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.pushes.clear();
+        self.notes.clear();
+    }
+
+    fn note(&mut self, text: String) {
+        if self.notes.len() == MAX_NOTES {
+            self.notes.pop_front();
+        }
+        self.notes.push_back(text);
+    }
+
+    /// Byte at $0100 + slot
+    pub fn slot(&self, slot: u8) -> Slot {
+        for (i, frame) in self.frames.iter().enumerate() {
+            let offset = slot.wrapping_sub(frame.sp);
+            if offset >= 1 && offset <= frame.size() {
+                return match frame.kind != FrameKind::Jsr && offset == 1 {
+                    true => Slot::Status(i),
+                    false => Slot::Return(i),
+                };
+            }
+        }
+        match self.pushes.get(&slot) {
+            Some((false, addr)) => Slot::Pha(*addr),
+            Some((true, addr)) => Slot::Php(*addr),
+            None => Slot::Unknown,
+        }
+    }
+
+    /// After the instruction at addr ran, sp_before is the stack pointer
+    /// it started with, pc and sp are the ones it left
+    pub fn instruction(&mut self, addr: u16, name: &str, sp_before: u8, pc: u16, sp: u8) {
+        match name {
+            "JSR" => self.frames.push(Frame { kind: FrameKind::Jsr, from: addr, target: pc,
+                return_to: addr.wrapping_add(3), sp }),
+            "BRK" => self.frames.push(Frame { kind: FrameKind::Brk, from: addr, target: pc,
+                return_to: addr.wrapping_add(2), sp }),
+            "PHA" | "PHP" => {
+                self.pushes.insert(sp_before, (name == "PHP", addr));
+            }
+            "RTS" | "RTI" => self.returned(addr, name, sp_before, pc),
+            _ => (),
+        }
+
+        let before = self.frames.len();
+        self.resync(sp);
+
+        let dropped = before - self.frames.len();
+        if dropped > 0 && !matches!(name, "RTS" | "RTI") {
+            self.note(format!("${:04X}: {} dropped {} frame(s) off the stack", addr, name, dropped));
+        }
+    }
+
+    /// IRQ or NMI, from is where it came in, sp the stack pointer after the push
+    pub fn interrupt(&mut self, kind: FrameKind, from: u16, handler: u16, sp: u8) {
+        self.frames.push(Frame { kind, from, target: handler, return_to: from, sp });
+        self.resync(sp);
+    }
+
+    /// Drops frames and pushes above the stack pointer
+    pub fn resync(&mut self, sp: u8) {
+        self.frames.retain(|x| x.sp >= sp);
+        self.pushes.retain(|slot, _| *slot > sp);
+    }
+
+    fn returned(&mut self, addr: u16, name: &str, sp_before: u8, pc: u16) {
+        let rts = name == "RTS";
+        let frame = match self.frames.last() {
+            Some(x) if x.sp == sp_before => x.clone(),
+            _ => {
+                let how = match rts {
+                    true => "an address pushed by code (jump table?)",
+                    false => "a frame pushed by code",
+                };
+                self.note(format!("${:04X}: {} to ${:04X} uses {}", addr, name, pc, how));
+                return;
+            }
+        };
+
+        if rts != (frame.kind == FrameKind::Jsr) {
+            self.note(format!("${:04X}: {} out of a {:?} frame from ${:04X}", addr, name, frame.kind, frame.from));
+        } else if pc != frame.return_to {
+            self.note(format!("${:04X}: {} to ${:04X}, the return address was changed (expected ${:04X})",
+                addr, name, pc, frame.return_to));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::emulator::cpu::{CPU6502, Limit, StopReason};
+    use super::*;
+
+    fn load(cpu: &mut CPU6502, addr: u16, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            cpu.write(addr + i as u16, *byte);
+        }
+    }
+
+    #[test]
+    fn calls_and_returns() {
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+
+        // JSR $8010, BRK / $8010: PHA, JSR $8020, PLA, RTS / $8020: RTS
+        load(&mut cpu, 0x8000, &[0x20, 0x10, 0x80, 0x00]);
+        load(&mut cpu, 0x8010, &[0x48, 0x20, 0x20, 0x80, 0x68, 0x60]);
+        load(&mut cpu, 0x8020, &[0x60]);
+
+        for _ in 0..3 {
+            cpu.execute_step();
+        }
+        let stack = &cpu.call_stack;
+        let targets: Vec<u16> = stack.frames().iter().map(|x| x.target).collect();
+        assert_eq!(targets, vec![0x8010, 0x8020]);
+        assert_eq!(stack.frames()[1].return_to, 0x8014);
+
+        let sp = cpu.stk_ptr;
+        assert_eq!(stack.slot(sp.wrapping_add(1)), Slot::Return(1));
+        assert_eq!(stack.slot(sp.wrapping_add(3)), Slot::Pha(0x8010));
+        assert_eq!(stack.slot(sp.wrapping_add(4)), Slot::Return(0));
+
+        assert_eq!(cpu.run(Limit::Instructions(100)), StopReason::Brk(0x8003));
+        assert!(cpu.call_stack.frames().is_empty());
+        assert_eq!(cpu.call_stack.notes().count(), 0);
+    }
+
+    #[test]
+    fn rts_jump_is_noted() {
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+
+        // LDA #$80, PHA, LDA #$0f, PHA, RTS -> $8010
+        load(&mut cpu, 0x8000, &[0xa9, 0x80, 0x48, 0xa9, 0x0f, 0x48, 0x60]);
+        load(&mut cpu, 0x8010, &[0x00]);
+
+        assert_eq!(cpu.run(Limit::Instructions(100)), StopReason::Brk(0x8010));
+        assert!(cpu.call_stack.notes().next().unwrap().contains("jump table"));
+    }
+}
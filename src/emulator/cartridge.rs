@@ -0,0 +1,115 @@
+/*
+*   NES cartridge
+*
+*   Only NROM (mapper 0) for now: 16K or 32K of PRG ROM at $8000-$FFFF
+*   (16K is mirrored into $C000) and 8K of CHR ROM, or CHR RAM if the
+*   ROM has none. CHR isn't used by the CPU, it's kept for video/tile viewers.
+*/
+
+pub const PRG_BANK_SIZE : usize = 16 * 1024;
+pub const CHR_BANK_SIZE : usize = 8 * 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+}
+
+/// Name of the common mappers, for error messages
+pub fn mapper_name(mapper: u16) -> &'static str {
+    match mapper {
+        0 => "NROM",
+        1 => "MMC1",
+        2 => "UxROM",
+        3 => "CNROM",
+        4 => "MMC3",
+        5 => "MMC5",
+        7 => "AxROM",
+        9 => "MMC2",
+        10 => "MMC4",
+        66 => "GxROM",
+        _ => "unknown",
+    }
+}
+
+pub struct Cartridge {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    pub mapper: u16,
+    pub mirroring: Mirroring,
+    /// Battery backed PRG RAM at $6000
+    pub battery: bool,
+}
+
+impl Cartridge {
+    /// Errors for mappers that aren't supported or PRG sizes NROM can't have
+    pub fn new(mapper: u16, prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring, battery: bool)
+    -> Result<Cartridge, String> {
+        if mapper != 0 {
+            return Err(format!("Mapper {} ({}) isn't supported, only NROM (mapper 0) is", mapper, mapper_name(mapper)));
+        }
+        if prg.len() != PRG_BANK_SIZE && prg.len() != 2 * PRG_BANK_SIZE {
+            return Err(format!("NROM needs 16K or 32K of PRG ROM, not {} bytes", prg.len()));
+        }
+
+        let chr_is_ram = chr.is_empty();
+        let chr = match chr_is_ram {
+            true => vec![0; CHR_BANK_SIZE],
+            false => chr,
+        };
+
+        Ok(Cartridge { prg, chr, chr_is_ram, mapper, mirroring, battery })
+    }
+
+    /// Byte the CPU sees at addr, None outside of $8000-$FFFF
+    pub fn cpu_read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg[(addr as usize - 0x8000) % self.prg.len()]),
+            _ => None,
+        }
+    }
+
+    /// True if the cartridge handles writes to addr (ROM ignores them)
+    pub fn cpu_write(&mut self, addr: u16, _val: u8) -> bool {
+        addr >= 0x8000
+    }
+
+    pub fn prg(&self) -> &[u8] {
+        &self.prg
+    }
+
+    /// Pattern tables, $0000-$1FFF of the PPU
+    pub fn chr(&self) -> &[u8] {
+        &self.chr
+    }
+
+    pub fn chr_is_ram(&self) -> bool {
+        self.chr_is_ram
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nrom_mirroring() {
+        let mut prg = vec![0; PRG_BANK_SIZE];
+        prg[0] = 0x11;
+        prg[PRG_BANK_SIZE - 1] = 0x22;
+
+        let cart = Cartridge::new(0, prg, Vec::new(), Mirroring::Vertical, false).unwrap();
+        assert_eq!(cart.cpu_read(0x8000), Some(0x11));
+        assert_eq!(cart.cpu_read(0xC000), Some(0x11));
+        assert_eq!(cart.cpu_read(0xFFFF), Some(0x22));
+        assert_eq!(cart.cpu_read(0x6000), None);
+        assert!(cart.chr_is_ram());
+        assert_eq!(cart.chr().len(), CHR_BANK_SIZE);
+
+        let err = Cartridge::new(4, vec![0; PRG_BANK_SIZE], Vec::new(), Mirroring::Vertical, false);
+        assert!(err.err().unwrap().contains("MMC3"));
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::emulator::code_map::{successors, CodeMap};
+use crate::emulator::disassembler::{DecodedInstruction, FormatOptions};
+use crate::emulator::symbols::SymbolTable;
+
+/*
+*   Control flow graph of traced code
+*
+*   A basic block ends at anything that jumps (branches, JSR, JMP,
+*   RTS...) or right before an instruction something else jumps to.
+*   Graphs come out as Graphviz DOT, `dot -Tsvg prog.dot > prog.svg`
+*/
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// On to the next instruction (including after a JSR or untaken branch)
+    Fallthrough,
+    /// Branch taken
+    Branch,
+    /// JSR
+    Call,
+    /// JMP
+    Jump,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edge {
+    /// Start of the block the edge leaves
+    pub from: u16,
+    /// Might be outside the traced image (a JSR into the ROM for example)
+    pub to: u16,
+    pub kind: EdgeKind,
+}
+
+pub struct BasicBlock {
+    pub start: u16,
+    pub instrs: Vec<DecodedInstruction>,
+}
+
+impl BasicBlock {
+    /// Address of the last byte in the block
+    pub fn end(&self) -> u16 {
+        self.instrs.last().map(|x| x.next_addr().wrapping_sub(1)).unwrap_or(self.start)
+    }
+}
+
+pub struct ControlFlowGraph {
+    blocks: BTreeMap<u16, BasicBlock>,
+    edges: Vec<Edge>,
+    entries: BTreeSet<u16>,
+    labels: SymbolTable,
+}
+
+/// Splits the code found by trace() into basic blocks.
+/// Symbols are used to name blocks and operands
+pub fn build(map: &CodeMap, symbols: Option<&SymbolTable>) -> ControlFlowGraph {
+    let is_code = |addr: u16| map.instruction_at(addr).is_some();
+
+    // first instruction of every block
+    let mut leaders: BTreeSet<u16> = map.entry_points().collect();
+    let mut calls = BTreeSet::new();
+    for instr in map.instructions() {
+        let (target, falls_through) = successors(instr);
+        if let Some(target) = target.filter(|t| is_code(*t)) {
+            leaders.insert(target);
+            if instr.mnemonic == "JSR" {
+                calls.insert(target);
+            }
+        }
+        // anything after a jump
+        if target.is_some() || !falls_through {
+            leaders.insert(instr.next_addr());
+        }
+    }
+
+    let mut graph = ControlFlowGraph {
+        blocks: BTreeMap::new(),
+        edges: Vec::new(),
+        entries: map.entry_points().chain(calls).collect(),
+        labels: map.labels(symbols),
+    };
+    if let Some(symbols) = symbols {
+        // for call targets outside the image
+        graph.labels.merge(symbols);
+    }
+
+    for start in leaders.iter().copied().filter(|x| is_code(*x)) {
+        let mut block = BasicBlock { start, instrs: Vec::new() };
+        let mut addr = start;
+
+        let last = loop {
+            let instr = map.instruction_at(addr).unwrap().clone();
+            let next = instr.next_addr();
+            let ends = successors(&instr) != (None, true) || leaders.contains(&next) || !is_code(next);
+            block.instrs.push(instr);
+
+            if ends {
+                break block.instrs.last().unwrap();
+            }
+            addr = next;
+        };
+
+        let (target, falls_through) = successors(last);
+        if let Some(to) = target {
+            let kind = match last.mnemonic {
+                "JSR" => EdgeKind::Call,
+                "JMP" => EdgeKind::Jump,
+                _ => EdgeKind::Branch,
+            };
+            graph.edges.push(Edge { from: start, to, kind });
+        }
+        if falls_through && is_code(last.next_addr()) {
+            graph.edges.push(Edge { from: start, to: last.next_addr(), kind: EdgeKind::Fallthrough });
+        }
+
+        graph.blocks.insert(start, block);
+    }
+
+    graph
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", dot_escape(s))
+}
+
+/// Quoted DOT label, lines left justified
+fn dot_label(lines: &[String]) -> String {
+    let escaped: Vec<String> = lines.iter().map(|x| dot_escape(x)).collect();
+    format!("\"{}\\l\"", escaped.join("\\l"))
+}
+
+impl ControlFlowGraph {
+    pub fn blocks(&self) -> impl Iterator<Item = &BasicBlock> {
+        self.blocks.values()
+    }
+
+    /// Block starting at addr
+    pub fn block(&self, addr: u16) -> Option<&BasicBlock> {
+        self.blocks.get(&addr)
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// Entry points and JSR targets
+    pub fn subroutines(&self) -> impl Iterator<Item = u16> + '_ {
+        self.entries.iter().copied().filter(move |x| self.blocks.contains_key(x))
+    }
+
+    /// Blocks reachable from entry without following calls
+    pub fn subroutine_blocks(&self, entry: u16) -> BTreeSet<u16> {
+        let mut found = BTreeSet::new();
+        let mut work = vec![entry];
+
+        while let Some(addr) = work.pop() {
+            if !self.blocks.contains_key(&addr) || !found.insert(addr) {
+                continue;
+            }
+            for edge in self.edges.iter().filter(|e| e.from == addr && e.kind != EdgeKind::Call) {
+                work.push(edge.to);
+            }
+        }
+        found
+    }
+
+    /// Name of a subroutine, or its address
+    pub fn name(&self, addr: u16) -> String {
+        match self.labels.name_of(addr) {
+            Some(name) => name.to_string(),
+            None => format!("${:04x}", addr),
+        }
+    }
+
+    /// Whole program as one graph
+    pub fn to_dot(&self) -> String {
+        let all: BTreeSet<u16> = self.blocks.keys().copied().collect();
+        self.dot("program", &all)
+    }
+
+    /// Graph of one subroutine, calls out of it point at a node
+    /// with the callee's name
+    pub fn subroutine_dot(&self, entry: u16) -> String {
+        self.dot(&self.name(entry), &self.subroutine_blocks(entry))
+    }
+
+    fn dot(&self, title: &str, blocks: &BTreeSet<u16>) -> String {
+        let opts = FormatOptions::default();
+        let mut s = format!("digraph {} {{\n", dot_quote(title));
+        s.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+        for block in blocks.iter().filter_map(|x| self.blocks.get(x)) {
+            let mut lines = Vec::new();
+            if let Some(name) = self.labels.name_of(block.start) {
+                lines.push(format!("{}:", name));
+            }
+            lines.push(format!("${:04x}-${:04x}", block.start, block.end()));
+            for instr in &block.instrs {
+                lines.push(format!("  {}", instr.format_with(&opts, Some(&self.labels))));
+            }
+            s.push_str(&format!("    b{:04x} [label={}];\n", block.start, dot_label(&lines)));
+        }
+
+        // targets that aren't part of this graph
+        let outside: BTreeSet<u16> = self.edges.iter()
+            .filter(|e| blocks.contains(&e.from) && !blocks.contains(&e.to))
+            .map(|e| e.to)
+            .collect();
+        for addr in outside {
+            s.push_str(&format!("    b{:04x} [shape=ellipse, label={}];\n", addr,
+                dot_quote(&self.name(addr))));
+        }
+
+        for edge in self.edges.iter().filter(|e| blocks.contains(&e.from)) {
+            let style = match edge.kind {
+                EdgeKind::Fallthrough => "",
+                EdgeKind::Branch => " [label=\"taken\", color=\"darkgreen\"]",
+                EdgeKind::Call => " [label=\"jsr\", style=dashed]",
+                EdgeKind::Jump => " [label=\"jmp\", style=bold]",
+            };
+            s.push_str(&format!("    b{:04x} -> b{:04x}{};\n", edge.from, edge.to, style));
+        }
+
+        s.push_str("}\n");
+        s
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::code_map::trace;
+    use crate::emulator::disassembler::{hex_string_to_bytes, ObjCode};
+
+    #[test]
+    fn blocks_and_edges() {
+        /*
+            c000  LDX #$03
+            c002  JSR $c00b     ; sub
+            c005  DEX
+            c006  BNE $c002
+            c008  JMP $c00e
+            c00b  NOP           ; sub
+            c00c  RTS
+            c00d  BRK
+            c00e  JSR $ffd2     ; outside
+            c011  RTS           ; JSR ends a block
+        */
+        let code = hex_string_to_bytes("a2 03 20 0b c0 ca d0 fa 4c 0e c0 ea 60 00 20 d2 ff 60");
+        let map = trace(&ObjCode::new(&code, 0xc000), &[0xc000]);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("chrout", 0xffd2);
+        symbols.insert("main", 0xc000);
+        let graph = build(&map, Some(&symbols));
+
+        let starts: Vec<u16> = graph.blocks().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0xc000, 0xc002, 0xc005, 0xc008, 0xc00b, 0xc00e, 0xc011]);
+        assert_eq!(graph.block(0xc005).unwrap().end(), 0xc007);
+
+        let from = |addr: u16| -> Vec<(u16, EdgeKind)> {
+            graph.edges().iter().filter(|e| e.from == addr).map(|e| (e.to, e.kind)).collect()
+        };
+        assert_eq!(from(0xc000), vec![(0xc002, EdgeKind::Fallthrough)]);
+        assert_eq!(from(0xc002), vec![(0xc00b, EdgeKind::Call), (0xc005, EdgeKind::Fallthrough)]);
+        assert_eq!(from(0xc005), vec![(0xc002, EdgeKind::Branch), (0xc008, EdgeKind::Fallthrough)]);
+        assert_eq!(from(0xc008), vec![(0xc00e, EdgeKind::Jump)]);
+        assert_eq!(from(0xc00b), vec![]);
+        assert_eq!(from(0xc00e), vec![(0xffd2, EdgeKind::Call), (0xc011, EdgeKind::Fallthrough)]);
+
+        let subs: Vec<u16> = graph.subroutines().collect();
+        assert_eq!(subs, vec![0xc000, 0xc00b]);
+        assert_eq!(graph.subroutine_blocks(0xc00b).len(), 1);
+        assert_eq!(graph.subroutine_blocks(0xc000).len(), 6);
+
+        let dot = graph.subroutine_dot(0xc000);
+        assert!(dot.starts_with("digraph \"main\" {"));
+        assert!(dot.contains("bc00b [shape=ellipse, label=\"sub_C00B\"];"));
+        assert!(dot.contains("bffd2 [shape=ellipse, label=\"chrout\"];"));
+        assert!(dot.contains("bc005 -> bc002 [label=\"taken\""));
+        assert!(dot.contains("$c005-$c007\\l  DEX\\l  BNE LC002\\l"));
+    }
+}
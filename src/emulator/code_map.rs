@@ -0,0 +1,417 @@
+use std::collections::BTreeMap;
+
+use crate::emulator::disassembler::{self, ByteSource, DecodedInstruction, FormatOptions, ObjCode};
+use crate::emulator::instruction::{find_instruction, AddressingMode::{self, *}};
+use crate::emulator::symbols::SymbolTable;
+
+/*
+*   Recursive traversal disassembly
+*
+*   Starting from the vectors and any given entry points, instructions
+*   are decoded following every branch, JSR and JMP. Only bytes reached
+*   this way are code, so tables and strings mixed in with the code
+*   aren't turned into garbage instructions.
+*/
+
+pub const NMI_VECTOR : u16 = 0xFFFA;
+pub const RESET_VECTOR : u16 = 0xFFFC;
+pub const IRQ_VECTOR : u16 = 0xFFFE;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ByteKind {
+    /// Never reached or referenced
+    Unknown,
+    /// Part of a reachable instruction
+    Code,
+    /// Referenced by an instruction's operand, or a vector
+    Data,
+}
+
+/// Spelling of what listing_with() writes, which differs between assemblers
+#[derive(Clone, Debug)]
+pub struct Syntax {
+    /// Sets the address, followed by it (`.org` or `* =`)
+    pub org: &'static str,
+    pub byte: &'static str,
+    /// `name:` rather than just `name`
+    pub label_colon: bool,
+    /// `ASL A` rather than `ASL`
+    pub acc_operand: bool,
+}
+
+impl Default for Syntax {
+    /// What this crate's assembler expects
+    fn default() -> Self {
+        Syntax { org: ".org", byte: ".byte", label_colon: true, acc_operand: true }
+    }
+}
+
+/// Result of trace(), what every byte of an image is
+pub struct CodeMap {
+    origin: u16,
+    bytes: Vec<u8>,
+    kinds: Vec<ByteKind>,
+    instrs: BTreeMap<u16, DecodedInstruction>,
+    entries: BTreeMap<u16, &'static str>,
+}
+
+/// Where an instruction can continue to
+pub(crate) fn successors(instr: &DecodedInstruction) -> (Option<u16>, bool) {
+    // (jump target, falls through)
+    match (instr.mnemonic, instr.mode) {
+        (_, REL) => (instr.target, true),
+        ("JSR", _) => (instr.target, true),
+        ("JMP", ABS) => (instr.target, false),
+        // can't know (JMP (ind)) or comes from the stack/vector
+        ("JMP", _) | ("RTS", _) | ("RTI", _) | ("BRK", _) => (None, false),
+        _ => (None, true),
+    }
+}
+
+/// Operand is a data address (not a jump target)
+fn reads_data(instr: &DecodedInstruction) -> bool {
+    !matches!(instr.mode, IMP | ACC | IMM | REL) && instr.mnemonic != "JMP" && instr.mnemonic != "JSR"
+}
+
+/// Follows control flow through obj_code from the vectors (if obj_code
+/// holds them) and entries
+pub fn trace(obj_code: &ObjCode<'_>, entries: &[u16]) -> CodeMap {
+    let mut map = CodeMap {
+        origin: obj_code.origin,
+        bytes: obj_code.bytes.to_vec(),
+        kinds: vec![ByteKind::Unknown; obj_code.bytes.len()],
+        instrs: BTreeMap::new(),
+        entries: BTreeMap::new(),
+    };
+
+    for (vector, name) in [(NMI_VECTOR, "nmi"), (RESET_VECTOR, "reset"), (IRQ_VECTOR, "irq")].iter() {
+        if let (Some(lo), Some(hi)) = (obj_code.byte_at(*vector), obj_code.byte_at(vector + 1)) {
+            map.mark(*vector, 2, ByteKind::Data);
+            let addr = (hi as u16) << 8 | lo as u16;
+            if map.contains(addr) {
+                map.entries.entry(addr).or_insert(name);
+            }
+        }
+    }
+
+    for addr in entries {
+        if map.contains(*addr) {
+            map.entries.entry(*addr).or_insert("entry");
+        }
+    }
+
+    let mut work: Vec<u16> = map.entries.keys().copied().collect();
+
+    while let Some(mut addr) = work.pop() {
+        loop {
+            if !map.contains(addr) || map.instrs.contains_key(&addr) {
+                break;
+            }
+
+            let instr = disassembler::decode(obj_code, addr);
+
+            // unknown opcode, or overlaps code found along another path
+            let overlaps = (0..instr.len as u16).any(|i| map.kind(addr.wrapping_add(i)) == ByteKind::Code);
+            if instr.is_data() || overlaps {
+                break;
+            }
+
+            map.mark(addr, instr.len as u16, ByteKind::Code);
+
+            if reads_data(&instr) {
+                if let Some(target) = instr.target {
+                    if map.kind(target) == ByteKind::Unknown {
+                        map.mark(target, 1, ByteKind::Data);
+                    }
+                }
+            }
+
+            let (jump, falls_through) = successors(&instr);
+            let next = instr.next_addr();
+            map.instrs.insert(addr, instr);
+
+            if let Some(target) = jump {
+                work.push(target);
+            }
+            if !falls_through || next < addr {
+                break;
+            }
+            addr = next;
+        }
+    }
+
+    map
+}
+
+impl CodeMap {
+    fn index(&self, addr: u16) -> Option<usize> {
+        match addr >= self.origin {
+            true => Some((addr - self.origin) as usize).filter(|i| *i < self.bytes.len()),
+            false => None,
+        }
+    }
+
+    fn mark(&mut self, addr: u16, len: u16, kind: ByteKind) {
+        for i in 0..len {
+            if let Some(idx) = self.index(addr.wrapping_add(i)) {
+                self.kinds[idx] = kind;
+            }
+        }
+    }
+
+    /// True if addr is part of the traced image
+    pub fn contains(&self, addr: u16) -> bool {
+        self.index(addr).is_some()
+    }
+
+    /// Unknown for addresses outside the image
+    pub fn kind(&self, addr: u16) -> ByteKind {
+        match self.index(addr) {
+            Some(i) => self.kinds[i],
+            None => ByteKind::Unknown,
+        }
+    }
+
+    /// Every reachable instruction in address order
+    pub fn instructions(&self) -> impl Iterator<Item = &DecodedInstruction> {
+        self.instrs.values()
+    }
+
+    /// Instruction starting at addr, if it's code
+    pub fn instruction_at(&self, addr: u16) -> Option<&DecodedInstruction> {
+        self.instrs.get(&addr)
+    }
+
+    /// Addresses tracing started from
+    pub fn entry_points(&self) -> impl Iterator<Item = u16> + '_ {
+        self.entries.keys().copied()
+    }
+
+    pub fn origin(&self) -> u16 {
+        self.origin
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Labels for the image: names from symbols, then vector names,
+    /// then generated ones. Only addresses a label line can be put
+    /// in front of (instruction starts and non code bytes) are named
+    pub fn labels(&self, symbols: Option<&SymbolTable>) -> SymbolTable {
+        let placeable = |addr: u16| {
+            self.contains(addr) && (self.instrs.contains_key(&addr) || self.kind(addr) != ByteKind::Code)
+        };
+
+        let mut labels = SymbolTable::new();
+
+        if let Some(symbols) = symbols {
+            for (name, addr) in symbols.iter().filter(|(_, a)| placeable(*a)) {
+                labels.insert(name, addr);
+            }
+        }
+
+        for (addr, name) in &self.entries {
+            if *name != "entry" {
+                labels.insert(name, *addr);
+            }
+        }
+
+        let instrs: Vec<DecodedInstruction> = self.instrs.values().cloned().collect();
+        labels.merge(&disassembler::auto_labels(&instrs));
+
+        for instr in instrs.iter().filter(|x| reads_data(x)) {
+            if let Some(target) = instr.target {
+                if placeable(target) && self.kind(target) == ByteKind::Data {
+                    labels.insert(&format!("D{:04X}", target), target);
+                }
+            }
+        }
+
+        labels
+    }
+
+    /// Source listing of the whole image which an assembler
+    /// (including the one in this crate) turns back into the same bytes.
+    ///
+    /// Anything that isn't code is written with `.byte`
+    pub fn listing(&self, symbols: Option<&SymbolTable>) -> Vec<String> {
+        self.listing_with(&Syntax::default(), symbols)
+    }
+
+    /// listing() for another assembler's syntax
+    pub fn listing_with(&self, syntax: &Syntax, symbols: Option<&SymbolTable>) -> Vec<String> {
+        let labels = self.labels(symbols);
+
+        // symbols outside of the image become constants
+        let mut externals = SymbolTable::new();
+        if let Some(symbols) = symbols {
+            for instr in self.instrs.values() {
+                if let Some(target) = instr.target {
+                    if let (false, Some(name)) = (self.contains(target), symbols.name_of(target)) {
+                        externals.insert(name, target);
+                    }
+                }
+            }
+        }
+
+        let mut all = externals.clone();
+        all.merge(&labels);
+
+        let mut ret: Vec<String> = externals.iter().map(|(name, addr)| format!("{} = ${:04x}", name, addr)).collect();
+        ret.push(format!("    {} ${:04x}", syntax.org, self.origin));
+
+        let opts = FormatOptions::default();
+        let mut addr = self.origin as u32;
+        let end = self.origin as u32 + self.bytes.len() as u32;
+
+        while addr < end {
+            let a = addr as u16;
+            if let Some(name) = labels.name_of(a) {
+                ret.push(format!("{}{}", name, if syntax.label_colon { ":" } else { "" }));
+            }
+
+            if let Some(instr) = self.instrs.get(&a) {
+                ret.push(format!("    {}", self.format_instr(instr, syntax, &opts, &all, &externals)));
+                addr += instr.len as u32;
+                continue;
+            }
+
+            // data runs until code, a label, or 8 bytes
+            let mut run = vec![self.bytes[addr as usize - self.origin as usize]];
+            addr += 1;
+            while addr < end && run.len() < 8 {
+                let a = addr as u16;
+                if self.instrs.contains_key(&a) || labels.name_of(a).is_some() {
+                    break;
+                }
+                run.push(self.bytes[addr as usize - self.origin as usize]);
+                addr += 1;
+            }
+
+            let bytes: Vec<String> = run.iter().map(|b| format!("${:02x}", b)).collect();
+            ret.push(format!("    {} {}", syntax.byte, bytes.join(", ")));
+        }
+
+        ret
+    }
+
+    fn format_instr(&self, instr: &DecodedInstruction, syntax: &Syntax, opts: &FormatOptions, all: &SymbolTable,
+        externals: &SymbolTable) -> String {
+        let zp_form = |mode: AddressingMode| match mode {
+            ABS => Some(ZP0),
+            ABX => Some(ZPX),
+            ABY => Some(ZPY),
+            _ => None,
+        };
+
+        // an assembler would pick the zero page form for these
+        if let (Some(zp), Some(operand)) = (zp_form(instr.mode), instr.operand) {
+            if operand < 0x100 && find_instruction(instr.mnemonic, zp).is_some() {
+                let bytes: Vec<String> = instr.raw().iter().map(|b| format!("${:02x}", b)).collect();
+                return format!("{} {} ; {}", syntax.byte, bytes.join(", "), instr.format(opts));
+            }
+        }
+
+        if instr.mode == ACC && !syntax.acc_operand {
+            return instr.mnemonic.to_string();
+        }
+
+        // a zero page label defined later would be assembled as absolute
+        match instr.mode {
+            ZP0 | ZPX | ZPY | IDX | IDY => instr.format_with(opts, Some(externals)),
+            _ => instr.format_with(opts, Some(all)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::assembler;
+    use crate::emulator::disassembler::hex_string_to_bytes;
+
+    // code at $8000, then a table and string the code reads,
+    // then vectors at the end of a 32K image
+    fn image() -> Vec<u8> {
+        let mut rom = vec![0xff; 0x8000];
+        let code = hex_string_to_bytes("
+            a2 00       bd 10 80    f0 06   20 0d 80    e8  d0 f5
+            60
+            40
+            ea
+            48 49 00
+        ");
+        rom[..code.len()].copy_from_slice(&code);
+        // nmi, reset, irq
+        rom[0x7ffa..].copy_from_slice(&[0x0e, 0x80, 0x00, 0x80, 0x0e, 0x80]);
+        rom
+    }
+
+    #[test]
+    fn separates_code_and_data() {
+        let rom = image();
+        let map = trace(&ObjCode::new(&rom, 0x8000), &[]);
+
+        assert_eq!(map.kind(0x8000), ByteKind::Code);
+        assert_eq!(map.kind(0x800d), ByteKind::Code);
+        // never reached
+        assert_eq!(map.kind(0x800f), ByteKind::Unknown);
+        // table read by LDA $8010,X
+        assert_eq!(map.kind(0x8010), ByteKind::Data);
+        assert_eq!(map.kind(0x8011), ByteKind::Unknown);
+        assert_eq!(map.kind(0xfffc), ByteKind::Data);
+        assert_eq!(map.kind(0x9000), ByteKind::Unknown);
+
+        let names: Vec<&str> = map.instructions().map(|x| x.mnemonic).collect();
+        assert_eq!(names, vec!["LDX", "LDA", "BEQ", "JSR", "INX", "BNE", "RTS", "RTI"]);
+
+        let labels = map.labels(None);
+        assert_eq!(labels.name_of(0x8000), Some("reset"));
+        assert_eq!(labels.name_of(0x8002), Some("L8002"));
+        assert_eq!(labels.name_of(0x800d), Some("sub_800D"));
+        assert_eq!(labels.name_of(0x800e), Some("nmi"));
+        assert_eq!(labels.name_of(0x8010), Some("D8010"));
+    }
+
+    #[test]
+    fn entry_points_outside_vectors() {
+        // JMP ($0300) can't be followed, so give the target by hand
+        let code = hex_string_to_bytes("6c 00 03 ea 00");
+        let obj = ObjCode::new(&code, 0x0600);
+
+        assert_eq!(trace(&obj, &[0x0600]).kind(0x0603), ByteKind::Unknown);
+        assert_eq!(trace(&obj, &[0x0600, 0x0603]).kind(0x0603), ByteKind::Code);
+    }
+
+    #[test]
+    fn listing_reassembles() {
+        let rom = image();
+        let map = trace(&ObjCode::new(&rom, 0x8000), &[]);
+        let source = map.listing(None).join("\n");
+
+        let asm = assembler::assemble(&source, 0).unwrap_or_else(|e| panic!("{:?}", e));
+        assert_eq!(asm.chunks.len(), 1);
+        assert_eq!(asm.chunks[0].0, 0x8000);
+        assert_eq!(asm.chunks[0].1, rom);
+    }
+
+    #[test]
+    fn listing_keeps_absolute_zero_page() {
+        // LDA $0010 (absolute), STA $10 (zero page) with a symbol
+        let code = hex_string_to_bytes("ad 10 00 85 10 60");
+        let obj = ObjCode::new(&code, 0xc000);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("ptr", 0x10);
+
+        let map = trace(&obj, &[0xc000]);
+        let listing = map.listing(Some(&symbols));
+        assert_eq!(listing[0], "ptr = $0010");
+        assert!(listing.contains(&"    STA ptr".to_string()));
+
+        let asm = assembler::assemble(&listing.join("\n"), 0).unwrap();
+        assert_eq!(asm.chunks[0].1, code);
+    }
+}
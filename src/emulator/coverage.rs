@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::emulator::debug_info::DebugInfo;
+use crate::emulator::disassembler::{self, ByteSource};
+use crate::emulator::symbols::SymbolTable;
+
+/*
+*   Code coverage, set cpu.coverage to Some(Coverage::new(&loaded.ranges))
+*
+*   Records which bytes ran as part of an instruction, how often each
+*   instruction ran and which ways each conditional branch went. The byte
+*   count is over the whole image given to new(), so tables in it count as
+*   not run. Source lines (with debug info) only have code in them.
+*   lcov() writes the tracefile format genhtml and coverage dashboards read.
+*/
+
+const BRANCHES : [&str; 8] = ["BPL", "BMI", "BVC", "BVS", "BCC", "BCS", "BNE", "BEQ"];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BranchCount {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl BranchCount {
+    pub fn both_ways(&self) -> bool {
+        self.taken > 0 && self.not_taken > 0
+    }
+}
+
+/// One source line, hits is its most run instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineCoverage {
+    pub file: String,
+    pub line: u32,
+    pub hits: u64,
+    /// Every branch instruction on the line, run or not
+    pub branches: Vec<BranchCount>,
+}
+
+pub struct Coverage {
+    ranges: Vec<(u16, u16)>,
+    // per instruction start
+    hits: Vec<u64>,
+    // per byte, opcode and operand
+    executed: Vec<bool>,
+    branches: BTreeMap<u16, BranchCount>,
+}
+
+impl Coverage {
+    /// Coverage of the image at ranges (both ends included), all of memory if empty
+    pub fn new(ranges: &[(u16, u16)]) -> Self {
+        let ranges = match ranges.is_empty() {
+            true => vec![(0, 0xFFFF)],
+            false => ranges.to_vec(),
+        };
+        Coverage {
+            ranges,
+            hits: vec![0; 0x10000],
+            executed: vec![false; 0x10000],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// The len byte instruction at addr ran, branch_taken says which way if it's a branch
+    pub fn record(&mut self, addr: u16, len: u16, branch_taken: Option<bool>) {
+        self.hits[addr as usize] += 1;
+        for i in 0..len {
+            self.executed[addr.wrapping_add(i) as usize] = true;
+        }
+
+        if let Some(taken) = branch_taken {
+            let count = self.branches.entry(addr).or_default();
+            match taken {
+                true => count.taken += 1,
+                false => count.not_taken += 1,
+            }
+        }
+    }
+
+    /// Times the instruction at addr ran
+    pub fn hits(&self, addr: u16) -> u64 {
+        self.hits[addr as usize]
+    }
+
+    pub fn executed(&self, addr: u16) -> bool {
+        self.executed[addr as usize]
+    }
+
+    /// Branches that ran, by address
+    pub fn branches(&self) -> &BTreeMap<u16, BranchCount> {
+        &self.branches
+    }
+
+    fn image(&self) -> impl Iterator<Item = u16> + '_ {
+        self.ranges.iter().flat_map(|(from, to)| *from..=*to)
+    }
+
+    /// (bytes run, bytes in the image)
+    pub fn bytes(&self) -> (usize, usize) {
+        let run = self.image().filter(|x| self.executed(*x)).count();
+        (run, self.image().count())
+    }
+
+    /// Every line in the debug info, by file then line. src is memory,
+    /// for finding branches that never ran
+    pub fn lines(&self, debug: &DebugInfo, src: &impl ByteSource) -> Vec<LineCoverage> {
+        // each line's bytes, in runs of consecutive addresses
+        let mut spans: BTreeMap<(&str, u32), Vec<(u16, u16)>> = BTreeMap::new();
+        for (addr, file, line) in debug.lines() {
+            let line_spans = spans.entry((file, line)).or_default();
+            match line_spans.last_mut() {
+                Some((_, end)) if end.wrapping_add(1) == addr => *end = addr,
+                _ => line_spans.push((addr, addr)),
+            }
+        }
+
+        spans.into_iter().map(|((file, line), line_spans)| {
+            let mut hits = 0;
+            let mut branches = Vec::new();
+
+            for (from, to) in line_spans {
+                let mut addr = from as u32;
+                while addr <= to as u32 {
+                    let instr = disassembler::decode(src, addr as u16);
+                    hits = hits.max(self.hits(instr.addr));
+                    if BRANCHES.contains(&instr.mnemonic) {
+                        branches.push(self.branches.get(&instr.addr).copied().unwrap_or_default());
+                    }
+                    addr += instr.len.max(1) as u32;
+                }
+            }
+            LineCoverage { file: file.to_string(), line, hits, branches }
+        }).collect()
+    }
+
+    /// Totals, then each symbol in the image and branches that haven't gone both ways
+    pub fn report(&self, symbols: Option<&SymbolTable>, debug: Option<&DebugInfo>, src: &impl ByteSource) -> String {
+        let percent = |run: usize, total: usize| run as f64 * 100. / total.max(1) as f64;
+
+        let (run, total) = self.bytes();
+        let both = self.branches.values().filter(|x| x.both_ways()).count();
+        let mut out = format!("bytes run: {}/{} ({:.1}%)\nbranches run: {}, both ways: {}\n",
+            run, total, percent(run, total), self.branches.len(), both);
+
+        if let Some(debug) = debug {
+            let lines = self.lines(debug, src);
+            let hit = lines.iter().filter(|x| x.hits > 0).count();
+            out.push_str(&format!("source lines run: {}/{} ({:.1}%)\n", hit, lines.len(), percent(hit, lines.len())));
+        }
+
+        // a symbol covers up to the next one or the end of its range
+        if let Some(symbols) = symbols.filter(|x| self.ranges.iter().any(|(from, to)| x.in_range(*from, *to).next().is_some())) {
+            out.push_str("\nsymbols\n");
+            for (from, to) in &self.ranges {
+                let names: Vec<(u16, &str)> = symbols.in_range(*from, *to).collect();
+                for (i, (addr, name)) in names.iter().enumerate() {
+                    let end = names.get(i + 1).map(|x| x.0 - 1).unwrap_or(*to);
+                    let run = (*addr..=end).filter(|x| self.executed(*x)).count();
+                    let size = (end - addr) as usize + 1;
+                    out.push_str(&format!("  ${:04X}-${:04X} {:>6}/{:<6} {:5.1}%  {}\n",
+                        addr, end, run, size, percent(run, size), name));
+                }
+            }
+        }
+
+        let one_way: Vec<_> = self.branches.iter().filter(|(_, x)| !x.both_ways()).collect();
+        if !one_way.is_empty() {
+            out.push_str("\nbranches only gone one way\n");
+            for (addr, count) in one_way {
+                let way = match count.taken > 0 {
+                    true => "always taken",
+                    false => "never taken",
+                };
+                let instr = disassembler::decode(src, *addr);
+                out.push_str(&format!("  ${:04X}  {} {}\n", addr, instr.mnemonic, way));
+            }
+        }
+        out
+    }
+
+    /// LCOV tracefile. With debug info there's a record per source file
+    /// (paths joined onto base_dir), without one record called name
+    /// where every byte of the image is a line numbered by its address
+    pub fn lcov(&self, name: &str, debug: Option<&DebugInfo>, base_dir: &Path, symbols: Option<&SymbolTable>,
+        src: &impl ByteSource) -> String
+    {
+        let mut out = String::new();
+
+        let debug = match debug {
+            Some(x) => x,
+            None => {
+                out.push_str(&format!("TN:\nSF:{}\n", name));
+                let mut hit = 0;
+                for addr in self.image() {
+                    // operands count as run as often as their instruction
+                    let start = (0..3).map(|x| addr.wrapping_sub(x))
+                        .find(|x| self.hits(*x) > 0 && disassembler::decode(src, *x).len as u16 > addr.wrapping_sub(*x));
+                    let hits = start.map(|x| self.hits(x)).unwrap_or(0);
+                    hit += (hits > 0) as usize;
+                    out.push_str(&format!("DA:{},{}\n", addr, hits));
+                }
+                out.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", self.image().count(), hit));
+                return out;
+            }
+        };
+
+        let lines = self.lines(debug, src);
+        let mut files: Vec<&str> = lines.iter().map(|x| &x.file[..]).collect();
+        files.dedup();
+
+        for file in files {
+            out.push_str(&format!("TN:\nSF:{}\n", base_dir.join(file).display()));
+
+            let functions: Vec<(u32, &str, u64)> = symbols.into_iter().flat_map(|x| x.iter())
+                .filter_map(|(sym, addr)| match debug.line_at(addr) {
+                    Some((f, line)) if f == file => Some((line, sym, self.hits(addr))),
+                    _ => None,
+                })
+                .collect();
+            for (line, sym, _) in &functions {
+                out.push_str(&format!("FN:{},{}\n", line, sym));
+            }
+            for (_, sym, hits) in &functions {
+                out.push_str(&format!("FNDA:{},{}\n", hits, sym));
+            }
+            out.push_str(&format!("FNF:{}\nFNH:{}\n", functions.len(), functions.iter().filter(|x| x.2 > 0).count()));
+
+            let file_lines: Vec<&LineCoverage> = lines.iter().filter(|x| x.file == file).collect();
+            let (mut brf, mut brh) = (0, 0);
+            for line in &file_lines {
+                for (block, count) in line.branches.iter().enumerate() {
+                    for (branch, n) in [count.taken, count.not_taken].iter().enumerate() {
+                        // - is for a branch that never ran at all
+                        let taken = match line.hits {
+                            0 => "-".to_string(),
+                            _ => n.to_string(),
+                        };
+                        out.push_str(&format!("BRDA:{},{},{},{}\n", line.line, block, branch, taken));
+                        brf += 1;
+                        brh += (*n > 0) as usize;
+                    }
+                }
+            }
+            out.push_str(&format!("BRF:{}\nBRH:{}\n", brf, brh));
+
+            for line in &file_lines {
+                out.push_str(&format!("DA:{},{}\n", line.line, line.hits));
+            }
+            out.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", file_lines.len(),
+                file_lines.iter().filter(|x| x.hits > 0).count()));
+        }
+        out
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::emulator::cpu::{CPU6502, Limit, StopReason};
+    use crate::emulator::debug_info;
+    use super::*;
+
+    #[test]
+    fn branches_and_lines() {
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+
+        /*
+            8000  LDX #$02     line 1
+            8002  DEX          line 2
+            8003  BNE $8002    line 2
+            8005  BEQ $8009    line 3
+            8007  INY          line 4, never run
+            8008  .byte $ff
+            8009  BRK
+        */
+        for (i, byte) in [0xa2, 0x02, 0xca, 0xd0, 0xfd, 0xf0, 0x02, 0xc8, 0xff, 0x00].iter().enumerate() {
+            cpu.write(0x8000 + i as u16, *byte);
+        }
+        cpu.coverage = Some(Coverage::new(&[(0x8000, 0x8009)]));
+        assert_eq!(cpu.run(Limit::Instructions(100)), StopReason::Brk(0x8009));
+
+        let cov = cpu.coverage.take().unwrap();
+        assert_eq!(cov.bytes(), (7, 10));
+        assert_eq!(cov.hits(0x8002), 2);
+        assert_eq!(cov.branches()[&0x8003], BranchCount { taken: 1, not_taken: 1 });
+        assert_eq!(cov.branches()[&0x8005], BranchCount { taken: 1, not_taken: 0 });
+        assert!(cov.report(None, None, &cpu).contains("$8005  BEQ always taken"));
+
+        let debug = debug_info::parse("file\tid=0,name=\"t.s\",size=1,mtime=0,mod=0
+seg\tid=0,name=\"CODE\",start=0x008000,size=0x000A,addrsize=absolute,type=ro,oname=\"t\",ooffs=0
+span\tid=0,seg=0,start=0,size=2
+span\tid=1,seg=0,start=2,size=3
+span\tid=2,seg=0,start=5,size=2
+span\tid=3,seg=0,start=7,size=1
+line\tid=0,file=0,line=1,span=0
+line\tid=1,file=0,line=2,span=1
+line\tid=2,file=0,line=3,span=2
+line\tid=3,file=0,line=4,span=3").unwrap();
+        let lines = cov.lines(&debug, &cpu);
+        let hits: Vec<u64> = lines.iter().map(|x| x.hits).collect();
+        assert_eq!(hits, vec![1, 2, 1, 0]);
+
+        let lcov = cov.lcov("t", Some(&debug), Path::new(""), None, &cpu);
+        assert!(lcov.starts_with("TN:\nSF:t.s\n"));
+        assert!(lcov.contains("BRDA:2,0,0,1\nBRDA:2,0,1,1\nBRDA:3,0,0,1\nBRDA:3,0,1,0\n"));
+        assert!(lcov.contains("DA:4,0\nLF:4\nLH:3\nend_of_record\n"));
+    }
+}
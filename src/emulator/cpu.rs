@@ -1,10 +1,80 @@
+use std::collections::BTreeSet;
+
 use crate::emulator::bus::{self, Bus};
+use crate::emulator::call_stack::{CallStack, FrameKind};
+use crate::emulator::cartridge::Cartridge;
+use crate::emulator::coverage::Coverage;
+use crate::emulator::heatmap::Heatmap;
+use crate::emulator::instruction::Instruction;
+use crate::emulator::profiler::Profiler;
 
 pub use crate::emulator::instruction::OPCODE_MAP;
 pub use crate::emulator::instruction::AddressingMode;
 
 const BRK_OPCODE : u8 = 0x00;
 const NOP_OPCODE : u8 = 0xEA;
+const JSR_OPCODE : u8 = 0x20;
+const RTS_OPCODE : u8 = 0x60;
+const RTI_OPCODE : u8 = 0x40;
+
+// KIL/JAM, these lock up a real 6502 until reset
+const JAM_OPCODES : [u8; 12] = [0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2];
+
+/// Memory access made while running an instruction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// How long run() may go for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Cycles(u64),
+    Instructions(u64),
+}
+
+/// Why run() stopped, the PC is left at the instruction it stopped before
+/// (or after, for a watchpoint or trap)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// run_until()'s hook asked to stop
+    Done,
+    Brk(u16),
+    BudgetExhausted,
+    Breakpoint(u16),
+    /// First access that hit one
+    Watchpoint(u16, Access),
+    Jam(u16),
+    /// Address and opcode
+    IllegalOpcode(u16, u8),
+    /// An instruction that jumps to itself, like `JMP *` or a branch to itself
+    Trap(u16),
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Done => write!(f, "Done"),
+            StopReason::Brk(addr) => write!(f, "BRK at ${:04X}", addr),
+            StopReason::BudgetExhausted => write!(f, "Ran out of budget"),
+            StopReason::Breakpoint(addr) => write!(f, "Breakpoint at ${:04X}", addr),
+            StopReason::Watchpoint(addr, Access::Read) => write!(f, "Watchpoint, read from ${:04X}", addr),
+            StopReason::Watchpoint(addr, Access::Write) => write!(f, "Watchpoint, write to ${:04X}", addr),
+            StopReason::Jam(addr) => write!(f, "Jammed at ${:04X}", addr),
+            StopReason::IllegalOpcode(addr, op) => write!(f, "Illegal opcode ${:02X} at ${:04X}", op, addr),
+            StopReason::Trap(addr) => write!(f, "Trapped at ${:04X}, it jumps to itself", addr),
+        }
+    }
+}
+
+/// Ok when run_until()'s hook stopped it
+fn finished(stop: StopReason) -> Result<(), StopReason> {
+    match stop {
+        StopReason::Done => Ok(()),
+        stop => Err(stop),
+    }
+}
 
 
 bitflags! {
@@ -41,6 +111,25 @@ pub struct CPU6502{
     pub pc : u16,
     pub status: Flags,
 
+    /// Addresses run() stops at
+    pub breakpoints: BTreeSet<u16>,
+
+    /// run() stops after an instruction reads/writes one of these
+    pub read_watchpoints: BTreeSet<u16>,
+    pub write_watchpoints: BTreeSet<u16>,
+
+    /// Shadow of the calls on the stack, kept up to date as it runs
+    pub call_stack: CallStack,
+
+    /// Counts cycles per address and subroutine when set
+    pub profiler: Option<Profiler>,
+
+    /// Records which instructions and branch directions ran when set
+    pub coverage: Option<Coverage>,
+
+    /// Fading record of recent reads, writes and executes when set
+    pub heatmap: Option<Heatmap>,
+
     // =============================
     /* Private internals */
     bus : Box<bus::Bus>,
@@ -72,6 +161,9 @@ pub struct CPU6502{
     /// Set after calling run_addr_mode
     page_crossed : bool,
 
+    /// Data reads and writes of the last instruction (not opcode/operand fetches)
+    accesses : Vec<(u16, Access)>,
+
     // =============================
 }
 
@@ -91,6 +183,13 @@ impl CPU6502{
             stk_ptr: 0xFD,
             pc : 0x0000,
             status: Flags::U, // unused always set
+            breakpoints: BTreeSet::new(),
+            read_watchpoints: BTreeSet::new(),
+            write_watchpoints: BTreeSet::new(),
+            call_stack: CallStack::default(),
+            profiler: None,
+            coverage: None,
+            heatmap: None,
 
             bus,
             cycles : 0, 
@@ -100,7 +199,7 @@ impl CPU6502{
             addr_abs : None, 
             mode : AddressingMode::IMP,
             page_crossed : false,      
-            
+            accesses : Vec::new(),
         }
     }
 
@@ -113,8 +212,15 @@ impl CPU6502{
             reg_x: 0x00,
             reg_y: 0x00,
             stk_ptr: 0xFF,
-            pc : pc,
+            pc,
             status: Flags::U, // unused always set
+            breakpoints: BTreeSet::new(),
+            read_watchpoints: BTreeSet::new(),
+            write_watchpoints: BTreeSet::new(),
+            call_stack: CallStack::default(),
+            profiler: None,
+            coverage: None,
+            heatmap: None,
 
             bus: Box::new(Bus::new()),
             cycles : 0, 
@@ -124,7 +230,7 @@ impl CPU6502{
             addr_abs : None, 
             mode : AddressingMode::IMP,
             page_crossed : false,      
-            
+            accesses : Vec::new(),
         }
     }
 
@@ -148,6 +254,22 @@ impl CPU6502{
         self.bus.load_cpu(program, Some(start_addr))
     }
 
+    /// Maps the cartridge's PRG ROM into $8000-$FFFF and resets,
+    /// so PC comes from the cartridge's reset vector
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        self.bus.insert_cartridge(cartridge);
+        self.reset();
+    }
+
+    /// Leaves whatever was in RAM before the cartridge (the ROM copy)
+    pub fn remove_cartridge(&mut self) -> Option<Cartridge> {
+        self.bus.remove_cartridge()
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.bus.cartridge()
+    }
+
     /// Calls clock the specified number of times
     pub fn run_cycles(&mut self, cycles : u32){
         for _i in 1..=cycles {
@@ -159,7 +281,8 @@ impl CPU6502{
     /// Returns the number of total cycles ran on the cpu instance/
     /// 
     /// Affected by clock(), execute_step(),
-    /// , run_cycles(), and run_until_brk()
+    /// , run_cycles(), and run(). Wraps around after u32::MAX,
+    /// so take differences with wrapping_sub()
     pub fn get_total_cycles(&self) -> u32 {
         self.total_cycles
     }
@@ -171,10 +294,7 @@ impl CPU6502{
 
     /// Returns two bytes combined, following little endian
     pub fn read_u16(&self, addr: u16) -> u16{
-        let lo = self.bus.read(addr);
-        let hi = self.bus.read(addr.wrapping_add(1));
-
-        (hi as u16) << 8 | lo as u16
+        self.bus.read_u16(addr)
     }
 
     /// Writes to address, handled by bus implementation
@@ -203,32 +323,6 @@ impl CPU6502{
         self.bus.index_memory(start, end)
     }
 
-    /// Will treat unknown opcodes as NOPs
-    /// aka designed for GUI usage
-    pub fn run_until_brk(&mut self) {
-        let map = &*OPCODE_MAP;
-
-        let mut opcode = self.read_pc();
-        while opcode!= BRK_OPCODE {
-
-            // _or here allows CPU to run a NOP if opcode read doesn't match an instruction
-            let instr = map.get(&opcode).unwrap_or(map.get(&NOP_OPCODE).unwrap());
-
-            let mode = instr.mode; 
-
-            self.run_addr_mode(mode);
-            self.run_operation(instr.opcode, mode);
-
-            // set internal variables to none after operation is complete 
-            self.operand = None;
-            self.addr_abs = None; 
-
-            self.total_cycles += self.cycles;
-            opcode = self.read_pc();
-
-        }
-    }
-
     /// Ignores clock cycles and exectues
     /// the next instruction
     ///
@@ -245,17 +339,135 @@ impl CPU6502{
         // _or here allows CPU to run a NOP if opcode read doesn't match an instruction
         let instr = map.get(&opcode).unwrap_or(OPCODE_MAP.get(&NOP_OPCODE).unwrap());
 
-        let mode = instr.mode;
+        self.cycles = instr.min_cycles as u32;
+        self.run_instr(instr);
 
-        self.run_addr_mode(mode);
-        self.run_operation(instr.opcode, mode);
+        self.total_cycles = self.total_cycles.wrapping_add(self.cycles);
+        self.cycles
+    }
 
-        // set internal variables to none after operation is complete 
-        self.operand = None;
-        self.addr_abs = None; 
+    /// Runs the subroutine at addr as if called by JSR, returning once
+    /// its RTS comes back to the current PC.
+    /// Errors (with PC and SP put back) if that takes over max_steps instructions
+    pub fn call_subroutine(&mut self, addr: u16, max_steps: u32) -> Result<(), &'static str> {
+        let return_pc = self.pc;
+        let sp = self.stk_ptr;
 
-        self.total_cycles += self.cycles;
-        self.cycles
+        // RTS adds 1 to what it pulls
+        let pushed = return_pc.wrapping_sub(1);
+        self.stack_push((pushed >> 8) as u8);
+        self.stack_push(pushed as u8);
+        self.pc = addr;
+
+        for _ in 0..max_steps {
+            self.execute_step();
+            if self.pc == return_pc && self.stk_ptr == sp {
+                return Ok(());
+            }
+        }
+
+        self.pc = return_pc;
+        self.stk_ptr = sp;
+        Err("Subroutine didn't return")
+    }
+
+    /// Runs until a BRK, jam, illegal opcode, breakpoint, watchpoint,
+    /// trap or the limit, whichever comes first
+    pub fn run(&mut self, limit: Limit) -> StopReason {
+        self.run_until(limit, |_| false)
+    }
+
+    /// run() without a limit, from before run() could say why it stopped
+    #[deprecated(note = "use run(), which has a limit and says why it stopped")]
+    pub fn run_until_brk(&mut self) -> StopReason {
+        self.run(Limit::Cycles(u64::MAX))
+    }
+
+    /// run() that also calls done right before each instruction runs,
+    /// stopping with Done (without running it) when it says so.
+    ///
+    /// The first instruction can be at a breakpoint, so it continues from one
+    pub fn run_until(&mut self, limit: Limit, mut done: impl FnMut(&CPU6502) -> bool) -> StopReason {
+        let map = &*OPCODE_MAP;
+        let mut cycles : u64 = 0;
+        let mut count : u64 = 0;
+
+        loop {
+            let pc = self.pc;
+            if count > 0 && self.breakpoints.contains(&pc) {
+                return StopReason::Breakpoint(pc);
+            }
+            let used = match limit {
+                Limit::Cycles(max) => cycles >= max,
+                Limit::Instructions(max) => count >= max,
+            };
+            if used {
+                return StopReason::BudgetExhausted;
+            }
+            let opcode = self.read(pc);
+            if opcode == BRK_OPCODE {
+                return StopReason::Brk(pc);
+            }
+            if JAM_OPCODES.contains(&opcode) {
+                return StopReason::Jam(pc);
+            }
+            // the IDK placeholders are really 3 byte NOPs, running them as 1 byte desyncs
+            if map.get(&opcode).is_none_or(|x| x.name == "IDK") {
+                return StopReason::IllegalOpcode(pc, opcode);
+            }
+            if done(self) {
+                return StopReason::Done;
+            }
+
+            cycles += self.execute_step() as u64;
+            count += 1;
+            if let Some((addr, kind)) = self.watch_hit() {
+                return StopReason::Watchpoint(addr, kind);
+            }
+            if self.pc == pc {
+                return StopReason::Trap(pc);
+            }
+        }
+    }
+
+    /// One instruction, except it stops before one run() would stop
+    /// before (BRK, jam, illegal opcode) and reports a watchpoint or trap
+    pub fn step_instruction(&mut self) -> Result<(), StopReason> {
+        match self.run(Limit::Instructions(1)) {
+            StopReason::BudgetExhausted => Ok(()),
+            stop => Err(stop),
+        }
+    }
+
+    /// Same as step_instruction, except a JSR runs until its subroutine
+    /// comes back to the next instruction at the same stack depth
+    pub fn step_over(&mut self, max_steps: u64) -> Result<(), StopReason> {
+        if self.read(self.pc) != JSR_OPCODE {
+            return self.step_instruction();
+        }
+
+        let return_pc = self.pc.wrapping_add(3);
+        let sp = self.stk_ptr;
+        finished(self.run_until(Limit::Instructions(max_steps), |cpu| cpu.pc == return_pc && cpu.stk_ptr == sp))
+    }
+
+    /// Runs until the current subroutine (or interrupt handler) returns,
+    /// an RTS/RTI that pops above the stack pointer it started with
+    pub fn step_out(&mut self, max_steps: u64) -> Result<(), StopReason> {
+        let sp = self.stk_ptr;
+        // done sees the PC after an instruction ran, so it remembers the one before
+        let mut last_pc = None;
+        finished(self.run_until(Limit::Instructions(max_steps), |cpu| {
+            let op = last_pc.map(|x| cpu.read(x));
+            last_pc = Some(cpu.pc);
+            (op == Some(RTS_OPCODE) || op == Some(RTI_OPCODE)) && cpu.stk_ptr > sp
+        }))
+    }
+
+    /// Runs until PC gets to addr, at least one instruction
+    pub fn run_to(&mut self, addr: u16, max_steps: u64) -> Result<(), StopReason> {
+        let mut first = true;
+        finished(self.run_until(Limit::Instructions(max_steps), |cpu| !std::mem::take(&mut first) && cpu.pc == addr))
     }
 
     /// If clock cycle is 0, runs an instruction and appropriately sets internal cycles.
@@ -276,18 +488,11 @@ impl CPU6502{
                 );
 
             self.cycles = instr.min_cycles as u32;
-            let mode = instr.mode;
-
-            self.run_addr_mode(mode);
-            self.run_operation(opcode, mode);
-
-            // set internal variables to none after operation is complete 
-            self.operand = None;
-            self.addr_abs = None; 
+            self.run_instr(instr);
         }
 
         self.cycles -= 1;
-        self.total_cycles += 1;
+        self.total_cycles = self.total_cycles.wrapping_add(1);
     }
 
     /// Must run appropriate amoutn of cycles to allow cpu to continue after
@@ -300,6 +505,7 @@ impl CPU6502{
         self.reg_y = 0;
 
         self.stk_ptr = 0xFD;
+        self.call_stack.clear();
         
         // Unused bit set (and interupts enabled)
         self.status = Flags::U;
@@ -336,7 +542,9 @@ impl CPU6502{
             self.stack_push(self.status.bits());
 
             // Read vector according to 6502 specification 
+            let from = self.pc;
             self.pc = self.read_u16(0xFFFE);
+            self.call_stack.interrupt(FrameKind::Irq, from, self.pc, self.stk_ptr);
 
             self.cycles = CPU6502::IRQ_CYCLES;
 
@@ -366,7 +574,9 @@ impl CPU6502{
         self.stack_push(self.status.bits());
 
         // Read vector according to 6502 specification 
+        let from = self.pc;
         self.pc = self.read_u16(0xFFFA);
+        self.call_stack.interrupt(FrameKind::Nmi, from, self.pc, self.stk_ptr);
 
         self.cycles = CPU6502::NMI_CYCLES;
 
@@ -376,6 +586,78 @@ impl CPU6502{
         self.mode = AddressingMode::IMP;
     }
 
+    /// Data reads and writes made by the last instruction, in order
+    pub fn last_accesses(&self) -> &[(u16, Access)] {
+        &self.accesses
+    }
+
+    /// First access of the last instruction that hit a watchpoint
+    pub fn watch_hit(&self) -> Option<(u16, Access)> {
+        self.accesses.iter().copied().find(|(addr, kind)| match kind {
+            Access::Read => self.read_watchpoints.contains(addr),
+            Access::Write => self.write_watchpoints.contains(addr),
+        })
+    }
+
+    /// read() that's recorded for watchpoints
+    fn bus_read(&mut self, addr: u16) -> u8 {
+        self.accesses.push((addr, Access::Read));
+        self.bus.read(addr)
+    }
+
+    /// write() that's recorded for watchpoints
+    fn bus_write(&mut self, addr: u16, val: u8) {
+        self.accesses.push((addr, Access::Write));
+        self.bus.write(addr, val);
+    }
+
+    /// Runs instr, its opcode has already been read
+    fn run_instr(&mut self, instr: &Instruction) {
+        use AddressingMode::*;
+
+        // PC is already past the opcode
+        let addr = self.pc.wrapping_sub(1);
+        let sp = self.stk_ptr;
+
+        self.accesses.clear();
+        self.run_addr_mode(instr.mode);
+        let len = self.pc.wrapping_sub(addr);
+
+        // run_addr_mode fetches the operand of stores and jumps too,
+        // a real 6502 doesn't so it isn't recorded
+        let reads = !matches!(instr.name, "STA" | "STX" | "STY" | "JMP" | "JSR");
+        if reads && matches!(instr.mode, ZP0 | ZPX | ZPY | ABS | ABX | ABY | IDX | IDY) {
+            self.accesses.push((self.addr_abs.unwrap(), Access::Read));
+        }
+
+        self.run_operation(instr.opcode, instr.mode);
+
+        // cycles is final now, and the call stack is still the one it ran in
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(addr, self.cycles, self.call_stack.frames());
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            let taken = (instr.mode == REL).then_some(self.pc != addr.wrapping_add(2));
+            coverage.record(addr, len, taken);
+        }
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            for i in 0..len {
+                heatmap.execute(addr.wrapping_add(i));
+            }
+            for (at, kind) in &self.accesses {
+                match kind {
+                    Access::Read => heatmap.read(*at),
+                    Access::Write => heatmap.write(*at),
+                }
+            }
+        }
+        self.call_stack.instruction(addr, instr.name, sp, self.pc, self.stk_ptr);
+
+        // set internal variables to none after operation is complete 
+        self.operand = None;
+        self.addr_abs = None; 
+    }
+
     /// Sets up internals addr_abs, operand, and page_crossed.
     /// 
     /// Also incriments PC to point at next opcode
@@ -452,8 +734,8 @@ impl CPU6502{
                     _ => base_ptr + 1
                 };
 
-                let lo = self.read(base_ptr) as u16;
-                let hi = self.read(next_ptr) as u16;
+                let lo = self.bus_read(base_ptr) as u16;
+                let hi = self.bus_read(next_ptr) as u16;
                 
                 Some((hi << 8) | lo)
             }
@@ -466,8 +748,8 @@ impl CPU6502{
                 let ptr = self.read_pc().wrapping_add(self.reg_x);
                 let nxt = ptr.wrapping_add(1);
 
-                let lo = self.read(ptr as u16) as u16;
-                let hi = self.read(nxt as u16) as u16;
+                let lo = self.bus_read(ptr as u16) as u16;
+                let hi = self.bus_read(nxt as u16) as u16;
 
                 Some((hi << 8) | lo)
             }
@@ -481,8 +763,8 @@ impl CPU6502{
                 let ptr = self.read_pc();
                 let nxt = ptr.wrapping_add(1);
 
-                let lo = self.read(ptr as u16) as u16;
-                let hi = self.read(nxt as u16) as u16;
+                let lo = self.bus_read(ptr as u16) as u16;
+                let hi = self.bus_read(nxt as u16) as u16;
 
                 let retrieved_addr = (hi << 8) | lo; // let addr = self.read_u16(ptr as u16);
                 let op_addr = retrieved_addr.wrapping_add(self.reg_y as u16);
@@ -848,14 +1130,14 @@ impl CPU6502{
 
     fn stack_push(&mut self, data : u8) {
         let addr = self.stk_ptr as u16 + CPU6502::STACK_OFFSET;
-        self.write(addr, data);
+        self.bus_write(addr, data);
         self.stk_ptr = self.stk_ptr.wrapping_sub(1);
     }
 
     fn stack_pop(&mut self) -> u8 {
         self.stk_ptr = self.stk_ptr.wrapping_add(1);
         let addr = self.stk_ptr as u16 + CPU6502::STACK_OFFSET;
-        self.read(addr)
+        self.bus_read(addr)
     }
 
 
@@ -985,7 +1267,7 @@ impl CPU6502{
             AddressingMode::ZP0 | AddressingMode::ZPX |
             AddressingMode::ABS | AddressingMode::ABX 
             => {
-                self.write(self.addr_abs.unwrap(), tmp);
+                self.bus_write(self.addr_abs.unwrap(), tmp);
             }
 
             _ => panic!("Incorrect Addressing Mode in ASL\n")
@@ -1094,7 +1376,7 @@ impl CPU6502{
         self.status.set(Flags::Z, val == 0);
         self.status.set(Flags::N, val & 0x80 != 0);
 
-        self.write(self.addr_abs.unwrap(), val);
+        self.bus_write(self.addr_abs.unwrap(), val);
 
     }
 
@@ -1133,7 +1415,7 @@ impl CPU6502{
         self.status.set(Flags::Z, val == 0);
         self.status.set(Flags::N, val & 0x80 != 0);
 
-        self.write(self.addr_abs.unwrap(), val);
+        self.bus_write(self.addr_abs.unwrap(), val);
     }
 
     fn inx(&mut self) {
@@ -1233,7 +1515,7 @@ impl CPU6502{
             AddressingMode::ZP0 | AddressingMode::ZPX |
             AddressingMode::ABS | AddressingMode::ABX 
             => {
-                self.write(self.addr_abs.unwrap(), tmp);
+                self.bus_write(self.addr_abs.unwrap(), tmp);
             }
 
             _ => panic!("Incorrect Addressing Mode in LSR\n")
@@ -1299,7 +1581,7 @@ impl CPU6502{
             AddressingMode::ZP0 | AddressingMode::ZPX |
             AddressingMode::ABS | AddressingMode::ABX 
             => {
-                self.write(self.addr_abs.unwrap(), tmp);
+                self.bus_write(self.addr_abs.unwrap(), tmp);
             }
 
             _ => panic!("Incorrect Addressing Mode in ROL\n")
@@ -1335,7 +1617,7 @@ impl CPU6502{
             AddressingMode::ZP0 | AddressingMode::ZPX |
             AddressingMode::ABS | AddressingMode::ABX 
             => {
-                self.write(self.addr_abs.unwrap(), tmp);
+                self.bus_write(self.addr_abs.unwrap(), tmp);
             }
 
             _ => panic!("Incorrect Addressing Mode in ROR\n")
@@ -1411,15 +1693,15 @@ impl CPU6502{
     }
 
     fn sta(&mut self) {
-        self.write(self.addr_abs.unwrap(), self.reg_a);
+        self.bus_write(self.addr_abs.unwrap(), self.reg_a);
     }
 
     fn stx(&mut self) {
-        self.write(self.addr_abs.unwrap(), self.reg_x);
+        self.bus_write(self.addr_abs.unwrap(), self.reg_x);
     }
 
     fn sty(&mut self) {
-        self.write(self.addr_abs.unwrap(), self.reg_y);
+        self.bus_write(self.addr_abs.unwrap(), self.reg_y);
     }
 
     fn tax(&mut self) {
@@ -1463,6 +1745,3 @@ impl CPU6502{
 
 
 }
-
-
-
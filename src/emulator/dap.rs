@@ -0,0 +1,770 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::emulator::cpu::{Access, CPU6502, Flags, Limit, StopReason};
+use crate::emulator::debug_info::{self, DebugInfo};
+use crate::emulator::disassembler::{decode, FormatOptions};
+use crate::emulator::formats::{self, StartAction};
+use crate::emulator::label_files::{self, LabelFormat};
+use crate::emulator::symbols::SymbolTable;
+
+/*
+*   Debug Adapter Protocol server, for VS Code and other DAP clients
+*
+*   Messages are `Content-Length: n\r\n\r\n{json}` over stdio or TCP.
+*   There's one thread, the CPU. Launch arguments:
+*       program         file to load (anything Load File takes)
+*       loadAddress     where raw binaries go, "$8000" if not given
+*       pc              start here instead of the program's start
+*       stopOnEntry     stop before the first instruction
+*       debugInfo       cc65 .dbg file, for source breakpoints and line stepping
+*       labels          VICE/Mesen/FCEUX label file, or a list of them
+*       machine         { resetVector, irqVector, nmiVector, reset }
+*                       reset starts from the reset vector like a power on
+*   Addresses are numbers or hex strings ("$c000", "0xc000").
+*/
+
+/// Cycles run between checks for requests (pause) while running
+const RUN_CHUNK : u64 = 100_000;
+
+/// Instructions a step over/out runs before giving up
+const STEP_LIMIT : u64 = 10_000_000;
+
+/// Furthest readMemory/disassemble offsets and counts go, the whole
+/// address space. Keeps client numbers from overflowing or looping forever
+const MAX_RANGE : i64 = 0x10000;
+
+// variablesReference of the two scopes
+const REGISTERS : u64 = 1;
+const FLAGS : u64 = 2;
+
+const FLAG_NAMES : [(&str, Flags); 7] = [("N", Flags::N), ("V", Flags::V), ("B", Flags::B),
+    ("D", Flags::D), ("I", Flags::I), ("Z", Flags::Z), ("C", Flags::C)];
+
+pub struct DapServer<W: Write> {
+    pub cpu: CPU6502,
+    symbols: SymbolTable,
+    debug: Option<DebugInfo>,
+    // debug info's source paths are relative to this
+    source_dir: PathBuf,
+
+    out: W,
+    seq: u64,
+
+    running: bool,
+    stop_on_entry: bool,
+
+    // cpu.breakpoints is all of these
+    source_breakpoints: BTreeMap<String, Vec<u16>>,
+    instruction_breakpoints: Vec<u16>,
+    function_breakpoints: Vec<u16>,
+}
+
+/// Next message from a client, None once it's gone
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if let Some(n) = line.strip_prefix("Content-Length:") {
+            len = n.trim().parse().ok();
+        }
+        if line.is_empty() && len.is_some() {
+            break;
+        }
+    }
+
+    let mut body = vec![0; len.unwrap()];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Reads messages on their own thread, so requests (pause) get
+/// through while the CPU is running
+pub fn spawn_reader(mut input: impl BufRead + Send + 'static) -> Receiver<Value> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(Some(msg)) = read_message(&mut input) {
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn hex_ref(addr: u16) -> String {
+    format!("0x{:04x}", addr)
+}
+
+/// Number, or hex string with an optional `$`/`0x`
+fn parse_addr(val: &Value) -> Result<Option<u16>, String> {
+    let addr = match val {
+        Value::Null => return Ok(None),
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => {
+            let hex = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+            u64::from_str_radix(hex, 16).ok()
+        }
+        _ => None,
+    };
+    match addr {
+        Some(x) if x <= 0xFFFF => Ok(Some(x as u16)),
+        _ => Err(format!("{} isn't an address", val)),
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const CHARS : &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut s = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, x)| n | (*x as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => s.push(CHARS[(n >> (18 - 6 * i)) as usize & 63] as char),
+                false => s.push('='),
+            }
+        }
+    }
+    s
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(out: W) -> Self {
+        DapServer {
+            cpu: CPU6502::create_cpu_and_bus(0x8000),
+            symbols: SymbolTable::new(),
+            debug: None,
+            source_dir: PathBuf::new(),
+            out,
+            seq: 0,
+            running: false,
+            stop_on_entry: false,
+            source_breakpoints: BTreeMap::new(),
+            instruction_breakpoints: Vec::new(),
+            function_breakpoints: Vec::new(),
+        }
+    }
+
+    /// Handles messages until the client disconnects
+    pub fn serve(&mut self, messages: Receiver<Value>) -> io::Result<()> {
+        loop {
+            let msg = match self.running {
+                true => match messages.try_recv() {
+                    Ok(msg) => Some(msg),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                },
+                false => match messages.recv() {
+                    Ok(msg) => Some(msg),
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            match msg {
+                Some(msg) => {
+                    if !self.handle(&msg)? {
+                        return Ok(());
+                    }
+                }
+                None => self.run_chunk()?,
+            }
+        }
+    }
+
+    /// Handles one request, false after disconnect
+    fn handle(&mut self, msg: &Value) -> io::Result<bool> {
+        let command = msg["command"].as_str().unwrap_or("");
+        let args = &msg["arguments"];
+        let was_running = self.running;
+
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsSetVariable": true,
+                "supportsReadMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsSteppingGranularity": true,
+                "supportsEvaluateForHovers": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_source_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => Ok(Value::Null),
+            "threads" => Ok(json!({ "threads": [{ "id": 1, "name": "6502" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Flags", "variablesReference": FLAGS, "expensive": false },
+            ]})),
+            "variables" => Ok(self.variables(args["variablesReference"].as_u64().unwrap_or(0))),
+            "setVariable" => self.set_variable(args),
+            "continue" => {
+                self.running = true;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" | "stepOut" | "disconnect" | "terminate" => Ok(Value::Null),
+            "pause" => {
+                self.running = false;
+                Ok(Value::Null)
+            }
+            "readMemory" => self.read_memory(args),
+            "disassemble" => self.disassemble(args),
+            "evaluate" => self.evaluate(args),
+            _ => Err(format!("{} isn't supported", command)),
+        };
+        let ok = result.is_ok();
+        self.respond(msg, result)?;
+
+        // things the client expects after the response
+        match command {
+            "launch" if ok => self.event("initialized", Value::Null)?,
+            "configurationDone" => match self.stop_on_entry {
+                true => self.stopped("entry", None)?,
+                false => self.running = true,
+            },
+            "next" | "stepIn" | "stepOut" => {
+                let granularity = args["granularity"].as_str().unwrap_or("statement");
+                let stop = self.step(command, granularity != "instruction");
+                self.report(stop)?;
+            }
+            "pause" if was_running => self.stopped("pause", None)?,
+            "terminate" => self.event("terminated", Value::Null)?,
+            "disconnect" => return Ok(false),
+            _ => (),
+        }
+        Ok(true)
+    }
+
+    fn send(&mut self, mut msg: Value) -> io::Result<()> {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+        let body = msg.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut msg = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => (),
+            Ok(body) => msg["body"] = body,
+            Err(e) => msg["message"] = json!(e),
+        }
+        self.send(msg)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut msg = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            msg["body"] = body;
+        }
+        self.send(msg)
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        let mut body = json!({ "reason": reason, "threadId": 1, "allThreadsStopped": true });
+        if let Some(text) = text {
+            body["description"] = json!(text);
+            body["text"] = json!(text);
+        }
+        self.event("stopped", body)
+    }
+
+    fn report(&mut self, stop: StopReason) -> io::Result<()> {
+        let describe = |addr: u16, symbols: &SymbolTable| match symbols.name_of(addr) {
+            Some(name) => format!("${:04x} ({})", addr, name),
+            None => format!("${:04x}", addr),
+        };
+        match stop {
+            StopReason::Done | StopReason::BudgetExhausted => self.stopped("step", None),
+            StopReason::Brk(_) => self.stopped("exception", Some("BRK".to_string())),
+            StopReason::Breakpoint(_) => self.stopped("breakpoint", None),
+            StopReason::Watchpoint(addr, Access::Read) => {
+                let text = format!("read from {}", describe(addr, &self.symbols));
+                self.stopped("data breakpoint", Some(text))
+            }
+            StopReason::Watchpoint(addr, Access::Write) => {
+                let text = format!("write to {}", describe(addr, &self.symbols));
+                self.stopped("data breakpoint", Some(text))
+            }
+            StopReason::Trap(_) => self.stopped("exception", Some("jumps to itself".to_string())),
+            StopReason::Jam(_) => self.stopped("exception", Some("JAM".to_string())),
+            StopReason::IllegalOpcode(_, op) => self.stopped("exception", Some(format!("illegal opcode ${:02x}", op))),
+        }
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"].as_str().ok_or("launch needs a program")?;
+        let load_addr = parse_addr(&args["loadAddress"])?.unwrap_or(0x8000);
+        let bytes = std::fs::read(program).map_err(|e| format!("{}: {}", program, e))?;
+
+        let mut cpu = CPU6502::create_cpu_and_bus(load_addr);
+        let loaded = formats::load_file(program, &bytes, &mut cpu, load_addr, StartAction::SetPc)
+            .map_err(|e| format!("{}: {}", program, e))?;
+        let mut symbols = loaded.symbols;
+
+        let labels = match &args["labels"] {
+            Value::String(path) => vec![path.as_str()],
+            Value::Array(paths) => paths.iter().filter_map(|x| x.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        for path in labels {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            let format = LabelFormat::from_extension(path).ok_or_else(|| format!("{}: unknown label file type", path))?;
+            let prg_size = cpu.cartridge().map(|x| x.prg().len());
+            symbols.merge(&label_files::parse(format, &text, prg_size).map_err(|e| format!("{}: {}", path, e))?);
+        }
+
+        self.debug = None;
+        if let Some(path) = args["debugInfo"].as_str() {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            let debug = debug_info::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+            symbols.merge(&debug.symbols);
+            self.source_dir = Path::new(path).parent().map(|x| x.to_path_buf()).unwrap_or_default();
+            self.debug = Some(debug);
+        }
+
+        // cartridges ignore these since their ROM is mapped there
+        let machine = &args["machine"];
+        for (vector, name) in [(0xFFFA, "nmiVector"), (0xFFFC, "resetVector"), (0xFFFE, "irqVector")] {
+            if let Some(addr) = parse_addr(&machine[name])? {
+                cpu.write(vector, addr as u8);
+                cpu.write(vector + 1, (addr >> 8) as u8);
+            }
+        }
+        if machine["reset"].as_bool().unwrap_or(false) {
+            cpu.reset();
+        }
+        if let Some(pc) = parse_addr(&args["pc"])? {
+            cpu.pc = pc;
+        }
+
+        // breakpoints can come before launch
+        cpu.breakpoints = std::mem::take(&mut self.cpu.breakpoints);
+        self.cpu = cpu;
+        self.symbols = symbols;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(Value::Null)
+    }
+
+    fn update_breakpoints(&mut self) {
+        self.cpu.breakpoints = self.source_breakpoints.values().flatten()
+            .chain(&self.instruction_breakpoints)
+            .chain(&self.function_breakpoints)
+            .copied()
+            .collect();
+    }
+
+    fn set_source_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"].as_str().or_else(|| args["source"]["name"].as_str()).unwrap_or("");
+        let lines: Vec<u64> = args["breakpoints"].as_array().map(|x| x.iter().filter_map(|x| x["line"].as_u64()).collect())
+            .unwrap_or_default();
+
+        let mut addrs = Vec::new();
+        let mut results = Vec::new();
+        for line in lines {
+            let found = self.debug.as_ref().map(|x| x.addrs_of(path, line as u32)).unwrap_or_default();
+            results.push(match (found.first(), &self.debug) {
+                (Some(addr), _) => json!({ "verified": true, "line": line, "instructionReference": hex_ref(*addr) }),
+                (None, Some(_)) => json!({ "verified": false, "line": line, "message": "no code on this line" }),
+                (None, None) => json!({ "verified": false, "line": line, "message": "launch with debugInfo for source breakpoints" }),
+            });
+            addrs.extend(found);
+        }
+
+        self.source_breakpoints.insert(path.to_string(), addrs);
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let mut results = Vec::new();
+        self.instruction_breakpoints.clear();
+
+        for bp in args["breakpoints"].as_array().into_iter().flatten() {
+            let addr = parse_addr(&bp["instructionReference"]).ok().flatten()
+                .map(|x| x as i64 + bp["offset"].as_i64().unwrap_or(0));
+            results.push(match addr {
+                Some(addr @ 0..=0xFFFF) => {
+                    self.instruction_breakpoints.push(addr as u16);
+                    json!({ "verified": true, "instructionReference": hex_ref(addr as u16) })
+                }
+                _ => json!({ "verified": false, "message": "not an address" }),
+            });
+        }
+
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn set_function_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let mut results = Vec::new();
+        self.function_breakpoints.clear();
+
+        for bp in args["breakpoints"].as_array().into_iter().flatten() {
+            let name = bp["name"].as_str().unwrap_or("");
+            results.push(match debug_info::resolve_location(name, &self.symbols, self.debug.as_ref()) {
+                Ok(addrs) => {
+                    self.function_breakpoints.extend(&addrs);
+                    json!({ "verified": true, "instructionReference": hex_ref(addrs[0]) })
+                }
+                Err(e) => json!({ "verified": false, "message": e }),
+            });
+        }
+
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn name_at(&self, addr: u16) -> String {
+        match self.symbols.name_of(addr) {
+            Some(name) => name.to_string(),
+            None => format!("${:04x}", addr),
+        }
+    }
+
+    fn stack_trace(&self) -> Value {
+        let pc = self.cpu.pc;
+        let mut frame = json!({
+            "id": 0,
+            "name": self.name_at(pc),
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": hex_ref(pc),
+        });
+
+        if let Some((file, line)) = self.debug.as_ref().and_then(|x| x.line_at(pc)) {
+            let name = file.rsplit(['/', '\\']).next().unwrap_or(file);
+            frame["source"] = json!({ "name": name, "path": self.source_dir.join(file).to_string_lossy() });
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+        }
+        json!({ "stackFrames": [frame], "totalFrames": 1 })
+    }
+
+    fn variables(&self, reference: u64) -> Value {
+        let var = |name: &str, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let cpu = &self.cpu;
+
+        let vars = match reference {
+            REGISTERS => vec![
+                var("A", format!("${:02x}", cpu.reg_a)),
+                var("X", format!("${:02x}", cpu.reg_x)),
+                var("Y", format!("${:02x}", cpu.reg_y)),
+                var("SP", format!("${:02x}", cpu.stk_ptr)),
+                var("P", format!("${:02x}", cpu.status.bits())),
+                json!({ "name": "PC", "value": format!("${:04x}", cpu.pc), "variablesReference": 0,
+                    "memoryReference": hex_ref(cpu.pc) }),
+            ],
+            FLAGS => FLAG_NAMES.iter().map(|(name, flag)| var(name, (cpu.status.contains(*flag) as u8).to_string())).collect(),
+            _ => Vec::new(),
+        };
+        json!({ "variables": vars })
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Result<Value, String> {
+        let name = args["name"].as_str().unwrap_or("");
+        let value = args["value"].as_str().unwrap_or("").trim();
+
+        if args["variablesReference"].as_u64() == Some(FLAGS) {
+            let flag = FLAG_NAMES.iter().find(|x| x.0 == name).ok_or("unknown flag")?.1;
+            let on = match value {
+                "1" | "true" => true,
+                "0" | "false" => false,
+                _ => return Err("flags are 0 or 1".to_string()),
+            };
+            self.cpu.status.set(flag, on);
+            return Ok(json!({ "value": (on as u8).to_string() }));
+        }
+
+        let val = parse_addr(&json!(value))?.ok_or("no value")?;
+        let byte = || match val <= 0xFF {
+            true => Ok(val as u8),
+            false => Err(format!("{} doesn't fit in a byte", value)),
+        };
+        match name {
+            "A" => self.cpu.reg_a = byte()?,
+            "X" => self.cpu.reg_x = byte()?,
+            "Y" => self.cpu.reg_y = byte()?,
+            "SP" => self.cpu.stk_ptr = byte()?,
+            "P" => self.cpu.status = Flags::from_bits_truncate(byte()?) | Flags::U,
+            "PC" => self.cpu.pc = val,
+            _ => return Err(format!("unknown register {}", name)),
+        }
+        match name {
+            "PC" => Ok(json!({ "value": format!("${:04x}", val) })),
+            _ => Ok(json!({ "value": format!("${:02x}", val) })),
+        }
+    }
+
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let base = parse_addr(&args["memoryReference"])?.ok_or("no memoryReference")?;
+        let start = base as i64 + args["offset"].as_i64().unwrap_or(0).clamp(-MAX_RANGE, MAX_RANGE);
+        let count = args["count"].as_u64().unwrap_or(0).min(MAX_RANGE as u64) as i64;
+
+        let bytes: Vec<u8> = (start.max(0)..(start + count).min(0x10000)).map(|x| self.cpu.read(x as u16)).collect();
+        Ok(json!({
+            "address": hex_ref(start.clamp(0, 0xFFFF) as u16),
+            "data": base64(&bytes),
+            "unreadableBytes": count - bytes.len() as i64,
+        }))
+    }
+
+    fn disassemble(&self, args: &Value) -> Result<Value, String> {
+        let base = parse_addr(&args["memoryReference"])?.ok_or("no memoryReference")? as i64
+            + args["offset"].as_i64().unwrap_or(0).clamp(-MAX_RANGE, MAX_RANGE);
+        let skip = args["instructionOffset"].as_i64().unwrap_or(0).clamp(-MAX_RANGE, MAX_RANGE);
+        let count = args["instructionCount"].as_u64().unwrap_or(0).min(MAX_RANGE as u64) as usize;
+        let symbols = match args["resolveSymbols"].as_bool().unwrap_or(true) {
+            true => Some(&self.symbols),
+            false => None,
+        };
+
+        let len_at = |addr: i64| match addr {
+            0..=0xFFFF => decode(&self.cpu, addr as u16).len as i64,
+            _ => 1,
+        };
+
+        // going backwards can't be exact, decode from far enough back that
+        // it has usually lined up with the real instructions by base
+        let mut addr = base;
+        if skip < 0 {
+            let mut before = Vec::new();
+            let mut a = base + 3 * skip;
+            while a < base {
+                before.push(a);
+                a += len_at(a);
+            }
+            addr = before.get(before.len().saturating_sub(-skip as usize)).copied().unwrap_or(base);
+        }
+        for _ in 0..skip.max(0) {
+            addr += len_at(addr);
+        }
+
+        let mut instrs = Vec::new();
+        for _ in 0..count {
+            if !(0..=0xFFFF).contains(&addr) {
+                instrs.push(json!({ "address": format!("0x{:04x}", addr.max(0)), "instruction": "??", "presentationHint": "invalid" }));
+                addr += 1;
+                continue;
+            }
+
+            let instr = decode(&self.cpu, addr as u16);
+            let bytes: Vec<String> = instr.raw().iter().map(|x| format!("{:02x}", x)).collect();
+            let mut entry = json!({
+                "address": hex_ref(instr.addr),
+                "instructionBytes": bytes.join(" "),
+                "instruction": instr.format_with(&FormatOptions::default(), symbols),
+            });
+            if let Some(name) = self.symbols.name_of(instr.addr) {
+                entry["symbol"] = json!(name);
+            }
+            if let Some((file, line)) = self.debug.as_ref().and_then(|x| x.line_at(instr.addr)) {
+                entry["location"] = json!({ "path": self.source_dir.join(file).to_string_lossy() });
+                entry["line"] = json!(line);
+            }
+            instrs.push(entry);
+            addr += instr.len as i64;
+        }
+        Ok(json!({ "instructions": instrs }))
+    }
+
+    /// Register names, or an address/symbol (shows the byte there)
+    fn evaluate(&self, args: &Value) -> Result<Value, String> {
+        let expr = args["expression"].as_str().unwrap_or("").trim();
+        let cpu = &self.cpu;
+
+        let reg = match &expr.to_ascii_lowercase()[..] {
+            "a" => Some(cpu.reg_a as u16),
+            "x" => Some(cpu.reg_x as u16),
+            "y" => Some(cpu.reg_y as u16),
+            "sp" => Some(cpu.stk_ptr as u16),
+            "p" => Some(cpu.status.bits() as u16),
+            "pc" => Some(cpu.pc),
+            _ => None,
+        };
+        if let Some(val) = reg {
+            return Ok(json!({ "result": format!("${:02x}", val), "variablesReference": 0 }));
+        }
+
+        let spec = match expr.starts_with("0x") || self.symbols.addr_of(expr).is_some() || expr.contains(':') {
+            true => expr.to_string(),
+            false => format!("${}", expr.trim_start_matches('$')),
+        };
+        let addr = debug_info::resolve_location(&spec, &self.symbols, self.debug.as_ref())?[0];
+        Ok(json!({
+            "result": format!("${:04x}: ${:02x}", addr, cpu.read(addr)),
+            "memoryReference": hex_ref(addr),
+            "variablesReference": 0,
+        }))
+    }
+
+    fn run_chunk(&mut self) -> io::Result<()> {
+        match self.cpu.run(Limit::Cycles(RUN_CHUNK)) {
+            StopReason::BudgetExhausted => Ok(()),
+            stop => {
+                self.running = false;
+                self.report(stop)
+            }
+        }
+    }
+
+    fn line_at(&self, addr: u16) -> Option<(String, u32)> {
+        self.debug.as_ref()?.line_at(addr).map(|(file, line)| (file.to_string(), line))
+    }
+
+    /// next, stepIn and stepOut. By line needs debug info, otherwise
+    /// it's by instruction
+    fn step(&mut self, command: &str, by_line: bool) -> StopReason {
+        let on_line = self.line_at(self.cpu.pc).is_some();
+        let result = match (command, &self.debug) {
+            ("stepOut", _) => self.cpu.step_out(STEP_LIMIT),
+            (_, Some(debug)) if by_line && on_line => return debug.next_line(&mut self.cpu, STEP_LIMIT, command == "next").1,
+            ("next", _) => self.cpu.step_over(STEP_LIMIT),
+            _ => self.cpu.step_instruction(),
+        };
+        result.err().unwrap_or(StopReason::Done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every message written so far
+    fn sent(server: &DapServer<Vec<u8>>) -> Vec<Value> {
+        let mut input = &server.out[..];
+        let mut msgs = Vec::new();
+        while let Some(msg) = read_message(&mut input).unwrap() {
+            msgs.push(msg);
+        }
+        msgs
+    }
+
+    fn request(server: &mut DapServer<Vec<u8>>, command: &str, args: Value) -> Value {
+        server.out.clear();
+        server.handle(&json!({ "seq": 1, "type": "request", "command": command, "arguments": args })).unwrap();
+        while server.running {
+            server.run_chunk().unwrap();
+        }
+        let msgs = sent(server);
+        assert_eq!(msgs[0]["success"], json!(true), "{}", msgs[0]);
+        msgs.last().unwrap().clone()
+    }
+
+    #[test]
+    fn session() {
+        /*
+            c000  LDX #$00
+            c002  JSR $c009
+            c005  INX
+            c006  JMP $c006
+            c009  INX
+            c00a  RTS
+        */
+        let path = std::env::temp_dir().join("simple_6502rs_dap_test.bin");
+        std::fs::write(&path, [0xa2, 0x00, 0x20, 0x09, 0xc0, 0xe8, 0x4c, 0x06, 0xc0, 0xe8, 0x60]).unwrap();
+        let mut server = DapServer::new(Vec::new());
+
+        let caps = request(&mut server, "initialize", json!({ "adapterID": "6502" }));
+        assert_eq!(caps["body"]["supportsDisassembleRequest"], json!(true));
+        let event = request(&mut server, "launch", json!({
+            "program": path.to_str().unwrap(),
+            "loadAddress": "$c000",
+            "stopOnEntry": true,
+        }));
+        assert_eq!(event["event"], json!("initialized"));
+
+        request(&mut server, "setInstructionBreakpoints", json!({ "breakpoints": [{ "instructionReference": "0xc005" }] }));
+        let event = request(&mut server, "configurationDone", Value::Null);
+        assert_eq!(event["body"]["reason"], json!("entry"));
+
+        let regs = request(&mut server, "variables", json!({ "variablesReference": REGISTERS }));
+        assert_eq!(regs["body"]["variables"][5]["value"], json!("$c000"));
+
+        let event = request(&mut server, "continue", json!({ "threadId": 1 }));
+        assert_eq!(event["body"]["reason"], json!("breakpoint"));
+        assert_eq!((server.cpu.pc, server.cpu.reg_x), (0xc005, 1));
+
+        let event = request(&mut server, "continue", json!({ "threadId": 1 }));
+        assert_eq!(event["body"]["text"], json!("jumps to itself"));
+
+        // step over runs the whole subroutine
+        request(&mut server, "setVariable", json!({ "variablesReference": REGISTERS, "name": "PC", "value": "c002" }));
+        request(&mut server, "next", json!({ "threadId": 1 }));
+        assert_eq!((server.cpu.pc, server.cpu.reg_x), (0xc005, 3));
+
+        let mem = request(&mut server, "readMemory", json!({ "memoryReference": "0xc000", "count": 3 }));
+        assert_eq!(mem["body"]["data"], json!("ogAg"));
+
+        let dis = request(&mut server, "disassemble", json!({
+            "memoryReference": "0xc002", "instructionOffset": -1, "instructionCount": 3,
+        }));
+        let lines: Vec<&str> = dis["body"]["instructions"].as_array().unwrap().iter()
+            .map(|x| x["instruction"].as_str().unwrap()).collect();
+        assert_eq!(lines, vec!["LDX #$00", "JSR $c009", "INX"]);
+
+        // nonsense sizes are cut down to the address space
+        let mem = request(&mut server, "readMemory", json!({ "memoryReference": "0xc000", "offset": i64::MAX, "count": u64::MAX }));
+        assert_eq!(mem["body"]["unreadableBytes"], json!(MAX_RANGE));
+        let dis = request(&mut server, "disassemble", json!({
+            "memoryReference": "0xc002", "instructionOffset": i64::MIN, "instructionCount": 1,
+        }));
+        assert_eq!(dis["body"]["instructions"].as_array().unwrap().len(), 1);
+
+        server.out.clear();
+        assert!(!server.handle(&json!({ "seq": 2, "command": "disconnect" })).unwrap());
+    }
+
+    #[test]
+    fn step_out_past_pla() {
+        /*
+            c000  JSR $c004
+            c003  INX
+            c004  PHA
+            c005  PLA
+            c006  INY
+            c007  RTS
+        */
+        let path = std::env::temp_dir().join("simple_6502rs_dap_step_out.bin");
+        std::fs::write(&path, [0x20, 0x04, 0xc0, 0xe8, 0x48, 0x68, 0xc8, 0x60]).unwrap();
+        let mut server = DapServer::new(Vec::new());
+
+        request(&mut server, "initialize", json!({ "adapterID": "6502" }));
+        request(&mut server, "launch", json!({
+            "program": path.to_str().unwrap(),
+            "loadAddress": "$c000",
+            "stopOnEntry": true,
+        }));
+        request(&mut server, "configurationDone", Value::Null);
+
+        request(&mut server, "stepIn", json!({ "threadId": 1 }));
+        request(&mut server, "stepIn", json!({ "threadId": 1 }));
+        assert_eq!(server.cpu.pc, 0xc005);
+
+        // PLA pops above where it started, but only the RTS returns
+        let event = request(&mut server, "stepOut", json!({ "threadId": 1 }));
+        assert_eq!(event["body"]["reason"], json!("step"));
+        assert_eq!((server.cpu.pc, server.cpu.reg_y), (0xc003, 1));
+    }
+}
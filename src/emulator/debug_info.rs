@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::emulator::cpu::{CPU6502, Limit, StopReason};
+use crate::emulator::symbols::SymbolTable;
+
+/*
+*   cc65 debug info (ld65 --dbgfile)
+*
+*   One record per line, `kind<TAB>key=value,key=value`. Used here:
+*       file    id, name
+*       seg     id, start
+*       span    id, seg, start, size        (start is inside the segment)
+*       line    id, file, line, span, type  (span is a '+' separated list)
+*       sym     id, name, val, type         (type=lab for labels)
+*
+*   Line types are 0 for assembly, 1 for C (or other external) source
+*   and 2 for macros. When several lines cover the same byte C wins,
+*   then assembly, then macros.
+*/
+
+/// Lines and labels from a cc65 .dbg file
+#[derive(Clone, Debug, Default)]
+pub struct DebugInfo {
+    files: Vec<String>,
+    // every byte covered by a line -> (file index, line, type priority)
+    lines: BTreeMap<u16, (usize, u32, u8)>,
+    pub symbols: SymbolTable,
+}
+
+/// Splits `a=1,b="x,y"` into pairs, commas inside quotes are kept
+fn fields(s: &str) -> HashMap<&str, &str> {
+    let mut ret = HashMap::new();
+    let mut in_quote = false;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices().chain(std::iter::once((s.len(), ','))) {
+        match c {
+            '"' => in_quote = !in_quote,
+            ',' if !in_quote => {
+                if let Some((k, v)) = s[start..i].split_once('=') {
+                    ret.insert(k.trim(), v.trim().trim_matches('"'));
+                }
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    ret
+}
+
+fn number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Higher wins
+fn line_priority(kind: u32) -> u8 {
+    match kind {
+        1 => 2,
+        0 => 1,
+        _ => 0,
+    }
+}
+
+pub fn parse(text: &str) -> Result<DebugInfo, String> {
+    let mut info = DebugInfo::default();
+
+    let mut files = HashMap::new();
+    let mut segs = HashMap::new();
+    // span id -> (address, size)
+    let mut spans = HashMap::new();
+    let mut lines = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let (kind, rest) = match line.split_once(char::is_whitespace) {
+            Some(x) => x,
+            None => continue,
+        };
+        let f = fields(rest.trim());
+        let get = |key: &str| f.get(key).and_then(|x| number(x));
+        let bad = || format!("line {}: bad {} record", n + 1, kind);
+
+        match kind {
+            "file" => {
+                files.insert(get("id").ok_or_else(bad)?, f.get("name").ok_or_else(bad)?.to_string());
+            }
+            "seg" => {
+                segs.insert(get("id").ok_or_else(bad)?, get("start").ok_or_else(bad)?);
+            }
+            "span" => {
+                let seg = get("seg").ok_or_else(bad)?;
+                spans.insert(get("id").ok_or_else(bad)?, (seg, get("start").ok_or_else(bad)?, get("size").ok_or_else(bad)?));
+            }
+            "line" => {
+                // lines without code (comments, .include...) have no span
+                if let Some(span_list) = f.get("span") {
+                    let ids: Vec<u32> = span_list.split('+').filter_map(number).collect();
+                    let file = get("file").ok_or_else(bad)?;
+                    let line_no = get("line").ok_or_else(bad)?;
+                    lines.push((file, line_no, get("type").unwrap_or(0), ids));
+                }
+            }
+            "sym" if f.get("type") == Some(&"lab") => {
+                if let (Some(name), Some(val)) = (f.get("name"), get("val")) {
+                    if val <= 0xFFFF {
+                        info.symbols.insert(name, val as u16);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // file ids aren't always dense
+    let mut file_index = HashMap::new();
+    for (id, name) in files {
+        file_index.insert(id, info.files.len());
+        info.files.push(name);
+    }
+
+    for (file, line_no, kind, ids) in lines {
+        let file = *file_index.get(&file).ok_or_else(|| format!("line info for unknown file {}", file))?;
+        let priority = line_priority(kind);
+
+        for id in ids {
+            let (seg, start, size) = match spans.get(&id) {
+                Some(x) => *x,
+                None => continue,
+            };
+            let base = segs.get(&seg).copied().unwrap_or(0);
+            let (from, to) = match base.checked_add(start).and_then(|x| Some((x, x.checked_add(size)?))) {
+                Some(x) => x,
+                None => return Err(format!("span {} is past $ffff", id)),
+            };
+
+            for addr in from..to {
+                if addr > 0xFFFF {
+                    break;
+                }
+                let entry = info.lines.entry(addr as u16).or_insert((file, line_no, priority));
+                if priority > entry.2 {
+                    *entry = (file, line_no, priority);
+                }
+            }
+        }
+    }
+
+    Ok(info)
+}
+
+impl DebugInfo {
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// (file, line) of the code at addr
+    pub fn line_at(&self, addr: u16) -> Option<(&str, u32)> {
+        self.lines.get(&addr).map(|(file, line, _)| (&self.files[*file][..], *line))
+    }
+
+    /// (address, file, line) for every byte that has a line, by address
+    pub fn lines(&self) -> impl Iterator<Item = (u16, &str, u32)> {
+        self.lines.iter().map(move |(addr, (file, line, _))| (*addr, &self.files[*file][..], *line))
+    }
+
+    /// File matching name, either exactly or by its last path component
+    fn find_file(&self, name: &str) -> Option<usize> {
+        let base = |x: &str| x.rsplit(['/', '\\']).next().unwrap_or(x).to_string();
+
+        self.files.iter().position(|x| x == name)
+            .or_else(|| self.files.iter().position(|x| base(x) == base(name)))
+    }
+
+    /// Start of each piece of code generated by a line
+    pub fn addrs_of(&self, file: &str, line: u32) -> Vec<u16> {
+        let file = match self.find_file(file) {
+            Some(x) => x,
+            None => return Vec::new(),
+        };
+
+        let mut ret = Vec::new();
+        for (addr, (f, l, _)) in &self.lines {
+            let prev = addr.checked_sub(1).and_then(|x| self.lines.get(&x));
+            let starts_here = prev.map(|(pf, pl, _)| (pf, pl) != (f, l)).unwrap_or(true);
+            if *f == file && *l == line && starts_here {
+                ret.push(*addr);
+            }
+        }
+        ret
+    }
+
+    /// Runs instructions until PC is on a different source line than it
+    /// started on, skipping code without line info (library routines).
+    /// Stops early wherever cpu.run() would. Returns instructions executed
+    pub fn step_line(&self, cpu: &mut CPU6502, max_steps: u64) -> u64 {
+        self.next_line(cpu, max_steps, false).0
+    }
+
+    /// step_line() that can step over calls, lines in a subroutine called
+    /// from this one don't count. Returns instructions executed and why
+    /// it stopped, Done for reaching a new line
+    pub fn next_line(&self, cpu: &mut CPU6502, max_steps: u64, over: bool) -> (u64, StopReason) {
+        let start = self.line_at(cpu.pc);
+        let depth = cpu.call_stack.frames().len();
+        let mut steps = 0;
+
+        let stop = cpu.run_until(Limit::Instructions(max_steps), |cpu| {
+            let now = self.line_at(cpu.pc);
+            let called = over && cpu.call_stack.frames().len() > depth;
+            if steps > 0 && !called && now.is_some() && now != start {
+                return true;
+            }
+            steps += 1;
+            false
+        });
+        (steps, stop)
+    }
+}
+
+/// Addresses for a breakpoint given as `$8000`/`0x8000`, a symbol name
+/// or `file:line` (needs debug info)
+pub fn resolve_location(spec: &str, symbols: &SymbolTable, debug: Option<&DebugInfo>) -> Result<Vec<u16>, String> {
+    let spec = spec.trim();
+
+    let hex = spec.strip_prefix('$').or_else(|| spec.strip_prefix("0x"));
+    if let Some(hex) = hex {
+        return u16::from_str_radix(hex, 16).map(|x| vec![x]).map_err(|_| format!("'{}' isn't an address", spec));
+    }
+
+    if let Some((file, line)) = spec.rsplit_once(':') {
+        if let Ok(line) = line.parse::<u32>() {
+            let debug = debug.ok_or("file:line needs debug info to be loaded")?;
+            let addrs = debug.addrs_of(file, line);
+            return match addrs.is_empty() {
+                true => Err(format!("no code for {}", spec)),
+                false => Ok(addrs),
+            };
+        }
+    }
+
+    symbols.addr_of(spec)
+        .or_else(|| debug.and_then(|x| x.symbols.addr_of(spec)))
+        .map(|x| vec![x])
+        .ok_or_else(|| format!("unknown symbol '{}'", spec))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DBG : &str = "version\tmajor=2,minor=0
+file\tid=0,name=\"src/main.c\",size=100,mtime=0x00000000,mod=0
+file\tid=1,name=\"crt0.s\",size=50,mtime=0x00000000,mod=0
+seg\tid=0,name=\"CODE\",start=0x008000,size=0x000A,addrsize=absolute,type=ro
+span\tid=0,seg=0,start=0,size=2
+span\tid=1,seg=0,start=2,size=3
+span\tid=2,seg=0,start=5,size=5
+line\tid=0,file=1,line=4,span=0+1
+line\tid=1,file=0,line=7,type=1,span=1
+line\tid=2,file=0,line=8,type=1,span=2
+line\tid=3,file=0,line=1,type=1
+sym\tid=0,name=\"_main\",addrsize=absolute,scope=0,def=0,val=0x8002,seg=0,type=lab
+sym\tid=1,name=\"SIZE\",addrsize=zeropage,scope=0,def=0,val=0x10,type=equ
+";
+
+    #[test]
+    fn lines_and_symbols() {
+        let info = parse(DBG).unwrap();
+
+        assert_eq!(info.line_at(0x8000), Some(("crt0.s", 4)));
+        // C line wins over the assembly line
+        assert_eq!(info.line_at(0x8003), Some(("src/main.c", 7)));
+        assert_eq!(info.line_at(0x8009), Some(("src/main.c", 8)));
+        assert_eq!(info.line_at(0x800a), None);
+
+        assert_eq!(info.addrs_of("main.c", 8), vec![0x8005]);
+        assert_eq!(info.symbols.addr_of("_main"), Some(0x8002));
+        assert_eq!(info.symbols.addr_of("SIZE"), None);
+
+        let symbols = SymbolTable::new();
+        assert_eq!(resolve_location("main.c:7", &symbols, Some(&info)), Ok(vec![0x8002]));
+        assert_eq!(resolve_location("_main", &symbols, Some(&info)), Ok(vec![0x8002]));
+        assert_eq!(resolve_location("$c000", &symbols, None), Ok(vec![0xc000]));
+        assert!(resolve_location("main.c:7", &symbols, None).is_err());
+        assert!(resolve_location("nope", &symbols, Some(&info)).is_err());
+
+        let huge = DBG.replace("span\tid=2,seg=0,start=5,size=5", "span\tid=2,seg=0,start=5,size=0xFFFFFFFF");
+        assert!(parse(&huge).unwrap_err().contains("span 2"));
+    }
+
+    #[test]
+    fn step_by_line() {
+        let info = parse(DBG).unwrap();
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+
+        // crt0: NOP NOP / main.c:7: NOP NOP NOP / main.c:8: NOPs / no line info
+        for addr in 0x8000..0x800c {
+            cpu.write(addr, 0xEA);
+        }
+
+        assert_eq!(info.step_line(&mut cpu, 100), 2);
+        assert_eq!(cpu.pc, 0x8002);
+        assert_eq!(info.step_line(&mut cpu, 100), 3);
+        assert_eq!(info.line_at(cpu.pc), Some(("src/main.c", 8)));
+
+        // runs off the end of the line info and stops at BRK
+        cpu.write(0x800c, 0x00);
+        assert_eq!(info.step_line(&mut cpu, 100), 7);
+        assert_eq!(cpu.pc, 0x800c);
+    }
+}
@@ -1,317 +1,399 @@
-use crate::hex;
-use crate::emulator::instruction::{OPCODE_MAP, AddressingMode::*};
+use std::fmt;
+
+use crate::emulator::instruction::{OPCODE_MAP, AddressingMode::{self, *}};
 use crate::emulator::cpu::CPU6502;
+use crate::emulator::symbols::SymbolTable;
 
-/// designed for GUI use
-pub fn disassemble_next_instr(cpu : &CPU6502) -> String {
+/// Mnemonic given to bytes that aren't a known opcode
+pub const DATA_MNEMONIC : &str = ".byte";
 
-    let pc = cpu.pc;
-    let opcode = cpu.read(pc);
+/// Anything instructions can be decoded from
+pub trait ByteSource {
+    /// None if addr is outside of the source
+    fn byte_at(&self, addr: u16) -> Option<u8>;
+}
 
-    let (name, mode, bytes) = match OPCODE_MAP.get(&opcode) {
-        Some(valid) => (valid.name, valid.mode, valid.bytes),
-        None => ("???: Gonna treat as NOP", IMP, 1)
-    };
+impl ByteSource for CPU6502 {
+    fn byte_at(&self, addr: u16) -> Option<u8> {
+        Some(self.read(addr))
+    }
+}
 
-    let mut s = name.to_string();
+/// Object code that starts at origin
+pub struct ObjCode<'a> {
+    pub bytes: &'a [u8],
+    pub origin: u16,
+}
 
-    let memory = cpu.index_memory(pc, pc+2).unwrap();
+impl<'a> ObjCode<'a> {
+    pub fn new(bytes: &'a [u8], origin: u16) -> Self {
+        ObjCode { bytes, origin }
+    }
 
-    if bytes == 1{
-        match mode{
-            ACC => {
-                s.push_str(" A");
-            }
-            IMP => (),
-            _ => panic!("This opcode ({:x}) shouldn't use 1 byte", opcode)
-        };
+    /// One past the last address
+    pub fn end(&self) -> u32 {
+        self.origin as u32 + self.bytes.len() as u32
+    }
+}
 
-    } else if bytes == 2 {
-        let byte = memory[1];
-        match mode {
-            IMM => {
-                let add = format!(" #${:02x}", byte);
-                s.push_str(&add[..]);
-            }
-            REL | ZP0 => {
-                let add = format!(" ${:02x}", byte);
-                s.push_str(&add[..]);
-            }
-            ZPX => {
-                let add = format!(" ${:02x},X", byte);
-                s.push_str(&add[..]);
-            }
-            ZPY => {
-                let add = format!(" ${:02x},Y", byte);
-                s.push_str(&add[..]);
-            }
-            IDX => {
-                let add = format!(" (${:02x},X)", byte);
-                s.push_str(&add[..]);
-            }
-            IDY => {
-                let add = format!(" (${:02x}),Y", byte);
-                s.push_str(&add[..]);
-            }
+impl ByteSource for ObjCode<'_> {
+    fn byte_at(&self, addr: u16) -> Option<u8> {
+        let i = addr.wrapping_sub(self.origin) as usize;
+        match addr >= self.origin {
+            true => self.bytes.get(i).copied(),
+            false => None,
+        }
+    }
+}
 
-            _ => panic!("This opcode ({:x}) shouldn't use 2 bytes", opcode)
-        };
+/// A single decoded instruction
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedInstruction {
+    pub addr: u16,
+    pub opcode: u8,
+    /// DATA_MNEMONIC if opcode is unknown
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    /// Byte or word following the opcode
+    pub operand: Option<u16>,
+    pub len: u8,
+    pub cycles: u8,
+    /// Branch destination for REL, the address operand
+    /// (before indexing) for every other mode that has one
+    pub target: Option<u16>,
+
+    raw: [u8; 3],
+}
 
-    } else if bytes == 3 {
-        let hi = memory[2];
-        let lo = memory[1];
+impl DecodedInstruction {
+    fn data(addr: u16, byte: u8) -> Self {
+        DecodedInstruction {
+            addr,
+            opcode: byte,
+            mnemonic: DATA_MNEMONIC,
+            mode: IMP,
+            operand: None,
+            len: 1,
+            cycles: 0,
+            target: None,
+            raw: [byte, 0, 0],
+        }
+    }
 
-        match mode {
-            IND => {
-                let add = format!(" (${:02x}{:02x})", hi, lo);
-                s.push_str(&add[..]);
-            }
+    /// True if the opcode didn't match an instruction
+    pub fn is_data(&self) -> bool {
+        self.mnemonic == DATA_MNEMONIC
+    }
 
-            ABS => {
-                let add = format!(" ${:02x}{:02x}", hi, lo);
-                s.push_str(&add[..]);
-            }
+    /// Bytes making up the instruction
+    pub fn raw(&self) -> &[u8] {
+        &self.raw[..self.len as usize]
+    }
 
-            ABX => {
-                let add = format!(" ${:02x}{:02x},X", hi, lo);
-                s.push_str(&add[..]);
-            }
+    /// Address of the following instruction
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.len as u16)
+    }
 
-            ABY => {
-                let add = format!(" ${:02x}{:02x},Y", hi, lo);
-                s.push_str(&add[..]);
-            }
+    /// True if it can take more than `cycles`, a taken branch or a
+    /// read that crosses a page (stores and read-modify-writes always
+    /// take the extra cycle, so it's in their count)
+    pub fn may_add_cycles(&self) -> bool {
+        match self.mode {
+            REL => true,
+            ABX | ABY => self.cycles == 4,
+            IDY => self.cycles == 5,
+            _ => false,
+        }
+    }
+
+    pub fn format(&self, opts: &FormatOptions) -> String {
+        self.format_with(opts, None)
+    }
 
-            _=> panic!("This opcode ({:x}) shouldn't use 3 bytes", opcode)
+    /// Like format, but address operands (and branch targets)
+    /// with a name in symbols are shown by name
+    pub fn format_with(&self, opts: &FormatOptions, symbols: Option<&SymbolTable>) -> String {
+        let mut s = String::new();
+
+        if opts.show_addr {
+            s.push_str(&format!("{}: ", opts.hex_word(self.addr)));
         }
+
+        if opts.show_bytes {
+            let raw: Vec<String> = self.raw().iter().map(|b| opts.hex_digits(*b as u16, 2)).collect();
+            s.push_str(&format!("{:<10}", raw.join(" ")));
+        }
+
+        match opts.uppercase_mnemonics || self.is_data() {
+            true => s.push_str(self.mnemonic),
+            false => s.push_str(&self.mnemonic.to_ascii_lowercase()),
+        }
+
+        if self.is_data() {
+            s.push(' ');
+            s.push_str(&opts.hex_byte(self.opcode));
+            return s;
+        }
+
+        let operand = self.operand.unwrap_or(0);
+        let name = self.target.and_then(|t| symbols.and_then(|x| x.name_of(t)));
+        let named = |fallback: String| match name {
+            Some(n) => n.to_string(),
+            None => fallback,
+        };
+        let byte = || named(opts.hex_byte(operand as u8));
+        let word = || named(opts.hex_word(operand));
+
+        let add = match self.mode {
+            IMP => String::new(),
+            ACC => " A".to_string(),
+            IMM => format!(" #{}", opts.hex_byte(operand as u8)),
+            REL => format!(" {}", named(opts.hex_word(self.target.unwrap_or(0)))),
+            ZP0 => format!(" {}", byte()),
+            ZPX => format!(" {},X", byte()),
+            ZPY => format!(" {},Y", byte()),
+            IDX => format!(" ({},X)", byte()),
+            IDY => format!(" ({}),Y", byte()),
+            ABS => format!(" {}", word()),
+            ABX => format!(" {},X", word()),
+            ABY => format!(" {},Y", word()),
+            IND => format!(" ({})", word()),
+        };
+        s.push_str(&add);
+
+        s
     }
-    s
 }
 
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format(&FormatOptions::default()))
+    }
+}
 
-pub fn disassemble_to_stream(obj_code : &Vec<u8>) -> Vec<String>{
-    let mut ret : Vec<String> = Vec::new();
-
-    let mut i = 0;
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HexStyle {
+    /// $00ff
+    Dollar,
+    /// 0x00ff
+    ZeroX,
+}
 
-    while i < obj_code.len() {
-        let opcode = obj_code[i];
+/// How decoded instructions are turned into text
+#[derive(Clone, Debug)]
+pub struct FormatOptions {
+    pub uppercase_mnemonics: bool,
+    pub uppercase_hex: bool,
+    pub hex_style: HexStyle,
+    /// Prefix with the instruction's address
+    pub show_addr: bool,
+    /// Show the instruction's bytes before the mnemonic
+    pub show_bytes: bool,
+}
 
-        let instr = OPCODE_MAP.get(&opcode).unwrap_or_else
-            (|| {panic!("At {}th index, found invalid opcode: {}\n", i, opcode)}
-            );
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            uppercase_mnemonics: true,
+            uppercase_hex: false,
+            hex_style: HexStyle::Dollar,
+            show_addr: false,
+            show_bytes: false,
+        }
+    }
+}
 
-        let instr_bytes = instr.bytes;
-        let mode = instr.mode;
+impl FormatOptions {
+    fn hex_digits(&self, val: u16, width: usize) -> String {
+        match self.uppercase_hex {
+            true => format!("{:0w$X}", val, w = width),
+            false => format!("{:0w$x}", val, w = width),
+        }
+    }
 
-        let mut s = String::from(instr.name);
-        
-        if instr_bytes == 1{
-            match mode{
-                ACC => {
-                    s.push_str(" A");
-                }
-                IMP => (),
-                _ => panic!("This opcode ({:x}) shouldn't use 1 byte", opcode)
-            };
-
-        } else if instr_bytes == 2 {
-            let byte = obj_code[i+1];
-            // consumed for byte
-            i+=1;
-
-            match mode {
-                IMM => {
-                    let add = format!(" #${:02x}", byte);
-                    s.push_str(&add[..]);
-                }
-                REL | ZP0 => {
-                    let add = format!(" ${:02x}", byte);
-                    s.push_str(&add[..]);
-                }
-                ZPX => {
-                    let add = format!(" ${:02x},X", byte);
-                    s.push_str(&add[..]);
-                }
-                ZPY => {
-                    let add = format!(" ${:02x},Y", byte);
-                    s.push_str(&add[..]);
-                }
-                IDX => {
-                    let add = format!(" (${:02x},X)", byte);
-                    s.push_str(&add[..]);
-                }
-                IDY => {
-                    let add = format!(" (${:02x}),Y", byte);
-                    s.push_str(&add[..]);
-                }
-
-                _ => panic!("This opcode ({:x}) shouldn't use 2 bytes", opcode)
-            };
-
-
-        } else if instr_bytes == 3 {
-            let hi = obj_code[i+2];
-            let lo = obj_code[i+1];
-
-            // used two bytes for instr;
-            i+=2;
-
-            match mode {
-                IND => {
-                    let add = format!(" (${:02x}{:02x})", hi, lo);
-                    s.push_str(&add[..]);
-                }
-
-                ABS => {
-                    let add = format!(" ${:02x}{:02x}", hi, lo);
-                    s.push_str(&add[..]);
-                }
-
-
-                ABX => {
-                    let add = format!(" ${:02x}{:02x},X", hi, lo);
-                    s.push_str(&add[..]);
-                }
-
-
-                ABY => {
-                    let add = format!(" ${:02x}{:02x},Y", hi, lo);
-                    s.push_str(&add[..]);
-                }
-
-                _=> panic!("This opcode ({:x}) shouldn't use 3 bytes", opcode)
-            }
+    fn prefix(&self) -> &'static str {
+        match self.hex_style {
+            HexStyle::Dollar => "$",
+            HexStyle::ZeroX => "0x",
         }
-                
-        // always have read at least opcode
-        i+=1;
+    }
 
-        ret.push(s);
-        
+    pub fn hex_byte(&self, val: u8) -> String {
+        format!("{}{}", self.prefix(), self.hex_digits(val as u16, 2))
     }
 
-    ret
+    pub fn hex_word(&self, val: u16) -> String {
+        format!("{}{}", self.prefix(), self.hex_digits(val, 4))
+    }
 }
 
+/// Decodes the instruction at addr.
+///
+/// Never panics, unknown opcodes (or instructions cut off by the
+/// end of src) decode to a single `.byte`
+pub fn decode(src: &impl ByteSource, addr: u16) -> DecodedInstruction {
+    let opcode = match src.byte_at(addr) {
+        Some(x) => x,
+        None => return DecodedInstruction::data(addr, 0),
+    };
 
-pub fn disassemble_with_addr_line(obj_code : &Vec<u8>, first_addr : u16) -> Vec<String> {
-    let mut ret : Vec<String> = Vec::new();
+    let instr = match OPCODE_MAP.get(&opcode) {
+        // unofficial opcodes aren't supported yet
+        Some(x) if x.name != "IDK" => x,
+        _ => return DecodedInstruction::data(addr, opcode),
+    };
 
-    let mut i = 0;
+    let mut raw = [opcode, 0, 0];
+    for (i, byte) in raw.iter_mut().enumerate().take(instr.bytes as usize).skip(1) {
+        match src.byte_at(addr.wrapping_add(i as u16)) {
+            Some(b) => *byte = b,
+            None => return DecodedInstruction::data(addr, opcode),
+        }
+    }
 
-    while i < obj_code.len() {
-        let opcode = obj_code[i];
+    let operand = match instr.bytes {
+        2 => Some(raw[1] as u16),
+        3 => Some((raw[2] as u16) << 8 | raw[1] as u16),
+        _ => None,
+    };
 
-        let instr = OPCODE_MAP.get(&opcode).unwrap_or_else
-            (|| {panic!("At {}th index, found invalid opcode: {}\n", i, opcode)}
-            );
+    let target = match (instr.mode, operand) {
+        (REL, Some(offset)) => Some(addr.wrapping_add(2).wrapping_add(offset as u8 as i8 as u16)),
+        (IMP, _) | (ACC, _) | (IMM, _) => None,
+        (_, op) => op,
+    };
 
-        let instr_bytes = instr.bytes;
-        let mode = instr.mode;
+    DecodedInstruction {
+        addr,
+        opcode,
+        mnemonic: instr.name,
+        mode: instr.mode,
+        operand,
+        len: instr.bytes,
+        cycles: instr.min_cycles,
+        target,
+        raw,
+    }
+}
 
-        
-        let mut s = format!("${:04x}: ", first_addr + i as u16);
-        s.push_str(instr.name);
-
-        if instr_bytes == 1{
-            match mode{
-                ACC => {
-                    s.push_str(" A");
-                }
-                IMP => (),
-                _ => panic!("This opcode ({:x}) shouldn't use 1 byte", opcode)
-            };
-
-        } else if instr_bytes == 2 {
-            let byte = obj_code[i+1];
-            // consumed for byte
-            i+=1;
-
-            match mode {
-                IMM => {
-                    let add = format!(" #${:02x}", byte);
-                    s.push_str(&add[..]);
-                }
-                REL | ZP0 => {
-                    let add = format!(" ${:02x}", byte);
-                    s.push_str(&add[..]);
-                }
-                ZPX => {
-                    let add = format!(" ${:02x},X", byte);
-                    s.push_str(&add[..]);
-                }
-                ZPY => {
-                    let add = format!(" ${:02x},Y", byte);
-                    s.push_str(&add[..]);
-                }
-                IDX => {
-                    let add = format!(" (${:02x},X)", byte);
-                    s.push_str(&add[..]);
-                }
-                IDY => {
-                    let add = format!(" (${:02x}),Y", byte);
-                    s.push_str(&add[..]);
-                }
-
-                _ => panic!("This opcode ({:x}) shouldn't use 2 bytes", opcode)
-            };
-
-
-        } else if instr_bytes == 3 {
-            let hi = obj_code[i+2];
-            let lo = obj_code[i+1];
-
-            // used two bytes for instr;
-            i+=2;
-
-            match mode {
-                IND => {
-                    let add = format!(" (${:02x}{:02x})", hi, lo);
-                    s.push_str(&add[..]);
-                }
-
-                ABS => {
-                    let add = format!(" ${:02x}{:02x}", hi, lo);
-                    s.push_str(&add[..]);
-                }
-
-
-                ABX => {
-                    let add = format!(" ${:02x}{:02x},X", hi, lo);
-                    s.push_str(&add[..]);
-                }
-
-
-                ABY => {
-                    let add = format!(" ${:02x}{:02x},Y", hi, lo);
-                    s.push_str(&add[..]);
-                }
-
-                _=> panic!("This opcode ({:x}) shouldn't use 3 bytes", opcode)
+/// Decodes everything in obj_code one instruction after another
+pub fn decode_all(obj_code: &ObjCode<'_>) -> Vec<DecodedInstruction> {
+    let mut ret = Vec::new();
+    let mut addr = obj_code.origin as u32;
+
+    while addr < obj_code.end() {
+        let instr = decode(obj_code, addr as u16);
+        addr += instr.len as u32;
+        ret.push(instr);
+    }
+
+    ret
+}
+
+/// Labels for every JSR (`sub_8040`), JMP and branch (`L8012`) target
+/// that is the start of one of instrs
+pub fn auto_labels(instrs: &[DecodedInstruction]) -> SymbolTable {
+    let starts: std::collections::HashSet<u16> = instrs.iter().map(|x| x.addr).collect();
+    let mut labels = std::collections::BTreeMap::new();
+
+    for instr in instrs {
+        let target = match instr.target {
+            Some(t) if starts.contains(&t) => t,
+            _ => continue,
+        };
+
+        match (instr.mnemonic, instr.mode) {
+            ("JSR", _) => {
+                labels.insert(target, format!("sub_{:04X}", target));
             }
+            ("JMP", ABS) | (_, REL) => {
+                labels.entry(target).or_insert_with(|| format!("L{:04X}", target));
+            }
+            _ => (),
         }
-                
-        // always have read at least opcode
-        i+=1;
+    }
 
-        ret.push(s);
-        
+    labels.into_iter().map(|(addr, name)| (name, addr)).collect()
+}
+
+/// Listing of obj_code with a label line before every jump target.
+///
+/// Names from symbols are preferred over generated labels
+pub fn disassemble_listing(obj_code: &ObjCode<'_>, opts: &FormatOptions, symbols: Option<&SymbolTable>)
+-> Vec<String> {
+    let instrs = decode_all(obj_code);
+
+    let mut labels = symbols.cloned().unwrap_or_default();
+    labels.merge(&auto_labels(&instrs));
+
+    let mut ret = Vec::new();
+    for instr in &instrs {
+        if let Some(name) = labels.name_of(instr.addr) {
+            ret.push(format!("{}:", name));
+        }
+        ret.push(format!("    {}", instr.format_with(opts, Some(&labels))));
     }
 
     ret
 }
 
+/// Where to start decoding to get `count` instructions before addr.
+///
+/// Going backwards is a guess since instructions aren't all the same
+/// length. The furthest start that decodes straight into addr is used,
+/// decoding tends to fall into step with the real instructions after a few.
+/// Falls back to fewer instructions (or addr itself) if nothing lines up
+pub fn start_before(src: &impl ByteSource, addr: u16, count: usize) -> u16 {
+    for back in (1..=count * 3).rev() {
+        let mut starts = Vec::new();
+        let mut at = addr.wrapping_sub(back as u16);
+        while at != addr && starts.len() <= back {
+            starts.push(at);
+            at = decode(src, at).next_addr();
+            // jumped over addr
+            if addr.wrapping_sub(at) as usize > back {
+                break;
+            }
+        }
+        if at == addr && !starts.is_empty() {
+            return starts[starts.len().saturating_sub(count)];
+        }
+    }
+    addr
+}
+
+/// designed for GUI use
+pub fn disassemble_next_instr(cpu : &CPU6502) -> String {
+    decode(cpu, cpu.pc).to_string()
+}
+
+/// Trace log line for the instruction at PC (before it runs),
+/// prefixed by the name of PC if it has one
+pub fn trace_line(cpu: &CPU6502, symbols: Option<&SymbolTable>) -> String {
+    let opts = FormatOptions { uppercase_hex: true, show_addr: true, show_bytes: true, ..FormatOptions::default() };
+    let label = symbols.and_then(|x| x.name_of(cpu.pc)).map(|x| format!("{}:", x)).unwrap_or_default();
+
+    format!("{:<12}{:<38}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+        label, decode(cpu, cpu.pc).format_with(&opts, symbols),
+        cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.status.bits(), cpu.stk_ptr)
+}
+
+pub fn disassemble_to_stream(obj_code : &[u8]) -> Vec<String>{
+    decode_all(&ObjCode::new(obj_code, 0)).iter().map(|x| x.to_string()).collect()
+}
+
+pub fn disassemble_with_addr_line(obj_code : &[u8], first_addr : u16) -> Vec<String> {
+    let opts = FormatOptions { show_addr: true, ..FormatOptions::default() };
+
+    decode_all(&ObjCode::new(obj_code, first_addr)).iter().map(|x| x.format(&opts)).collect()
+}
+
 /// hex string to bytes. returns none if there was an error
 /// 
 pub fn htb_option(input: &str) -> Option<Vec<u8>> {
     let input : String = input.split_whitespace().collect();
-    match hex::decode(input){
-        Ok(vector) => {
-            Some(vector)
-        }    
-        Err(_) => None
-    }
+    hex::decode(input).ok()
 }
 
 /// works with proper input only
@@ -330,7 +412,7 @@ mod tests {
     #[test]
     fn test_hex_convert() {
 
-        assert_eq!(0x80 as u8, htb_option("8000").unwrap()[0]);
+        assert_eq!(0x80_u8, htb_option("8000").unwrap()[0]);
     }
 
     #[test]
@@ -346,7 +428,7 @@ mod tests {
         // push all except last
         for s in &v[0..v.len()-1] {
             result.push_str(&s[..]);
-            result.push_str(" ");            
+            result.push(' ');            
             print!("{} ",s);
 
         }
@@ -372,7 +454,7 @@ mod tests {
         // push all except last
         for s in &v[0..v.len()-1] {
             result.push_str(&s[..]);
-            result.push_str(" ");            
+            result.push(' ');            
             print!("{} ",s);
 
         }   
@@ -382,5 +464,109 @@ mod tests {
         let answer = "LDA #$01 STA $0200 LDA #$05 STA $0201 LDA #$08 STX $0202";
         assert_eq!(answer, result);
     }
-    
+
+    #[test]
+    fn test_decode_fields(){
+        let code = [0xbd, 0x34, 0x12, 0xd0, 0xfb, 0x0a];
+        let obj = ObjCode::new(&code, 0x8000);
+
+        let lda = decode(&obj, 0x8000);
+        assert_eq!(lda.mnemonic, "LDA");
+        assert_eq!(lda.opcode, 0xbd);
+        assert_eq!(lda.mode, ABX);
+        assert_eq!(lda.operand, Some(0x1234));
+        assert_eq!(lda.len, 3);
+        assert_eq!(lda.cycles, 4);
+        assert_eq!(lda.target, Some(0x1234));
+        assert_eq!(lda.raw(), &code[0..3]);
+
+        // branches back to the LDA
+        let bne = decode(&obj, 0x8003);
+        assert_eq!(bne.mode, REL);
+        assert_eq!(bne.target, Some(0x8000));
+
+        let asl = decode(&obj, 0x8005);
+        assert_eq!(asl.mode, ACC);
+        assert_eq!(asl.target, None);
+        assert_eq!(asl.next_addr(), 0x8006);
+    }
+
+    #[test]
+    fn test_unknown_and_truncated(){
+        // 0x02 isn't an opcode, 0xad needs two more bytes
+        let code = [0x02, 0xea, 0xad, 0x00];
+        let v = disassemble_to_stream(&code);
+
+        assert_eq!(v, vec![".byte $02", "NOP", ".byte $ad", "BRK"]);
+        assert!(decode(&ObjCode::new(&code, 0), 0).is_data());
+    }
+
+    #[test]
+    fn test_branch_targets_and_labels(){
+        let test = "20 08 80 d0 fb 4c 00 80 a5 10 60";
+        let code = hex_string_to_bytes(test);
+
+        let v = disassemble_with_addr_line(&code, 0x8000);
+        assert_eq!(v[1], "$8003: BNE $8000");
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("counter", 0x0010);
+
+        let listing = disassemble_listing(&ObjCode::new(&code, 0x8000), &FormatOptions::default(), Some(&symbols));
+        assert_eq!(listing, vec![
+            "L8000:",
+            "    JSR sub_8008",
+            "    BNE L8000",
+            "    JMP L8000",
+            "sub_8008:",
+            "    LDA counter",
+            "    RTS",
+        ]);
+    }
+
+    #[test]
+    fn test_format_options(){
+        let code = [0x8d, 0x00, 0x02];
+        let instr = decode(&ObjCode::new(&code, 0xc000), 0xc000);
+
+        let opts = FormatOptions {
+            uppercase_mnemonics: false,
+            uppercase_hex: true,
+            hex_style: HexStyle::ZeroX,
+            show_addr: true,
+            show_bytes: true,
+        };
+
+        assert_eq!(instr.format(&opts), "0xC000: 8D 00 02  sta 0x0200");
+        assert_eq!(instr.to_string(), "STA $0200");
+        assert_eq!(disassemble_with_addr_line(&code, 0xc000), vec!["$c000: STA $0200"]);
+    }
+
+    #[test]
+    fn test_start_before(){
+        // LDA #$01, STA $0200, INX, JMP $8000
+        let code = hex_string_to_bytes("a9 01 8d 00 02 e8 4c 00 80");
+        let src = ObjCode::new(&code, 0x8000);
+
+        assert_eq!(start_before(&src, 0x8006, 2), 0x8002);
+        assert_eq!(start_before(&src, 0x8006, 3), 0x8000);
+        assert_eq!(start_before(&src, 0x8005, 1), 0x8002);
+
+        assert!(!decode(&src, 0x8002).may_add_cycles());
+        assert!(decode(&ObjCode::new(&[0xbd, 0x00, 0x02], 0), 0).may_add_cycles());
+    }
+
+    #[test]
+    fn test_trace_line() {
+        let mut cpu = CPU6502::create_cpu_and_bus(0xc000);
+        cpu.write(0xc000, 0x8d);
+        cpu.write(0xc001, 0x00);
+        cpu.write(0xc002, 0x02);
+        cpu.reg_a = 0x42;
+
+        let symbols: SymbolTable = vec![("main", 0xc000), ("screen", 0x0200)].into_iter().collect();
+        let line = trace_line(&cpu, Some(&symbols));
+        assert!(line.starts_with("main:       $C000: 8D 00 02  STA screen"), "{}", line);
+        assert!(line.ends_with("A:42 X:00 Y:00 P:20 SP:FF"), "{}", line);
+    }
 }
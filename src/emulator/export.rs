@@ -0,0 +1,263 @@
+use crate::emulator::code_map::{CodeMap, Syntax};
+use crate::emulator::symbols::SymbolTable;
+
+/*
+*   Disassembly as source for other assemblers
+*
+*   Built on CodeMap::listing_with(), so everything that isn't reachable
+*   code is written as bytes and instructions an assembler would shrink
+*   to zero page are kept as bytes. Assembling the output as a plain
+*   binary gives back the original image.
+*/
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    Ca65,
+    Acme,
+    Tass64,
+}
+
+impl Dialect {
+    pub const ALL : [Dialect; 3] = [Dialect::Ca65, Dialect::Acme, Dialect::Tass64];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dialect::Ca65 => "ca65",
+            Dialect::Acme => "ACME",
+            Dialect::Tass64 => "64tass",
+        }
+    }
+
+    /// Usual file extension
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Dialect::Ca65 => "s",
+            Dialect::Acme => "a",
+            Dialect::Tass64 => "asm",
+        }
+    }
+
+    /// Dialect whose usual extension path has
+    pub fn from_extension(path: &str) -> Option<Dialect> {
+        let ext = path.rsplit_once('.')?.1.to_ascii_lowercase();
+        Dialect::ALL.iter().copied().find(|x| x.extension() == ext)
+    }
+
+    fn syntax(&self) -> Syntax {
+        match self {
+            Dialect::Ca65 => Syntax::default(),
+            Dialect::Acme => Syntax { org: "* =", byte: "!byte", label_colon: false, acc_operand: false },
+            Dialect::Tass64 => Syntax { org: "* =", label_colon: false, ..Syntax::default() },
+        }
+    }
+
+    fn header(&self, origin: u16) -> Vec<String> {
+        match self {
+            Dialect::Ca65 => vec![
+                // 0x, a $ would be expanded by the shell
+                format!("; ca65 prog.s && ld65 -t none -S 0x{:04x} -o prog.bin prog.o", origin),
+                "    .setcpu \"6502\"".to_string(),
+                "    .segment \"CODE\"".to_string(),
+            ],
+            Dialect::Acme => vec![
+                "; acme -f plain -o prog.bin prog.a".to_string(),
+                "    !cpu 6502".to_string(),
+            ],
+            Dialect::Tass64 => vec![
+                "; 64tass --nostart -o prog.bin prog.asm".to_string(),
+                "    .cpu \"6502\"".to_string(),
+            ],
+        }
+    }
+}
+
+/// Source for dialect which assembles back to the traced image.
+/// Symbols name addresses in and outside of the image.
+///
+/// Tests check that with this crate's assembler, the real ones are only
+/// run by `cargo test -- --ignored` when they're installed
+pub fn export_source(map: &CodeMap, dialect: Dialect, symbols: Option<&SymbolTable>) -> String {
+    let mut lines = dialect.header(map.origin());
+    lines.extend(map.listing_with(&dialect.syntax(), symbols));
+
+    let mut ret = lines.join("\n");
+    ret.push('\n');
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::assembler;
+    use crate::emulator::code_map::trace;
+    use crate::emulator::disassembler::{hex_string_to_bytes, ObjCode};
+    use std::process::Command;
+
+    #[test]
+    fn round_trip_every_dialect() {
+        // code with ASL A, an absolute zero page access,
+        // a subroutine, a table and vectors
+        let mut rom = vec![0x00; 0x1000];
+        let code = hex_string_to_bytes("
+            a2 00   bd 20 f0    0a    ad 10 00   85 11   20 17 f0
+            e8  e0 04   d0 ef   4c 16 f0
+            60  60
+        ");
+        rom[..code.len()].copy_from_slice(&code);
+        rom[0x20..0x24].copy_from_slice(&[1, 2, 3, 4]);
+        rom[0xffa..].copy_from_slice(&[0x16, 0xf0, 0x00, 0xf0, 0x16, 0xf0]);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("dest", 0x11);
+
+        let map = trace(&ObjCode::new(&rom, 0xf000), &[]);
+
+        for dialect in Dialect::ALL.iter() {
+            let source = export_source(&map, *dialect, Some(&symbols));
+
+            let asm = assembler::assemble(&source, 0)
+                .unwrap_or_else(|e| panic!("{}: {:?}\n{}", dialect.name(), e, source));
+            assert_eq!(asm.chunks.len(), 1);
+            assert_eq!(asm.chunks[0].0, 0xf000);
+            assert!(asm.chunks[0].1 == rom, "{} didn't round trip:\n{}", dialect.name(), source);
+        }
+
+        let acme = export_source(&map, Dialect::Acme, Some(&symbols));
+        assert!(acme.contains("\n    * = $f000\n"));
+        assert!(acme.contains("\nreset\n"));
+        assert!(acme.contains("\n    ASL\n"));
+        assert!(acme.contains("\n    STA dest\n"));
+    }
+
+    // written from each assembler's manual, real_assemblers() runs them
+    const CA65 : &str = "; ca65 prog.s && ld65 -t none -S 0x1000 -o prog.bin prog.o
+    .setcpu \"6502\"
+    .segment \"CODE\"
+dest = $0011
+    .org $1000
+    LDX #$00
+L1002:
+    LDA D1014,X
+    ASL A
+    STA dest
+    .byte $ad, $12, $00 ; LDA $0012
+    INX
+    CPX #$04
+    BNE L1002
+    JSR sub_1013
+sub_1013:
+    RTS
+D1014:
+    .byte $01, $02, $03, $04
+";
+
+    const ACME : &str = "; acme -f plain -o prog.bin prog.a
+    !cpu 6502
+dest = $0011
+    * = $1000
+    LDX #$00
+L1002
+    LDA D1014,X
+    ASL
+    STA dest
+    !byte $ad, $12, $00 ; LDA $0012
+    INX
+    CPX #$04
+    BNE L1002
+    JSR sub_1013
+sub_1013
+    RTS
+D1014
+    !byte $01, $02, $03, $04
+";
+
+    const TASS64 : &str = "; 64tass --nostart -o prog.bin prog.asm
+    .cpu \"6502\"
+dest = $0011
+    * = $1000
+    LDX #$00
+L1002
+    LDA D1014,X
+    ASL A
+    STA dest
+    .byte $ad, $12, $00 ; LDA $0012
+    INX
+    CPX #$04
+    BNE L1002
+    JSR sub_1013
+sub_1013
+    RTS
+D1014
+    .byte $01, $02, $03, $04
+";
+
+    /// A loop over a table storing to zero page, an absolute access
+    /// to zero page and a subroutine at $1000
+    fn golden_program() -> (Vec<u8>, SymbolTable) {
+        let code = hex_string_to_bytes("
+            a2 00   bd 14 10   0a   85 11   ad 12 00
+            e8   e0 04   d0 f2   20 13 10   60
+            01 02 03 04
+        ");
+        let mut symbols = SymbolTable::new();
+        symbols.insert("dest", 0x11);
+        (code, symbols)
+    }
+
+    #[test]
+    fn golden_output() {
+        let (code, symbols) = golden_program();
+        let map = trace(&ObjCode::new(&code, 0x1000), &[0x1000]);
+
+        assert_eq!(export_source(&map, Dialect::Ca65, Some(&symbols)), CA65);
+        assert_eq!(export_source(&map, Dialect::Acme, Some(&symbols)), ACME);
+        assert_eq!(export_source(&map, Dialect::Tass64, Some(&symbols)), TASS64);
+
+        assert_eq!(Dialect::from_extension("out/prog.ASM"), Some(Dialect::Tass64));
+        assert_eq!(Dialect::from_extension("prog.txt"), None);
+    }
+
+    /// Assembles golden_program() with ca65/ld65, ACME and 64tass, whichever are on PATH
+    #[test]
+    #[ignore]
+    fn real_assemblers() {
+        let (code, symbols) = golden_program();
+        let map = trace(&ObjCode::new(&code, 0x1000), &[0x1000]);
+        let dir = std::env::temp_dir().join(format!("simple_6502rs_export_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for dialect in Dialect::ALL.iter() {
+            let source = format!("prog.{}", dialect.extension());
+            std::fs::write(dir.join(&source), export_source(&map, *dialect, Some(&symbols))).unwrap();
+            let _ = std::fs::remove_file(dir.join("prog.bin"));
+
+            // the commands in each header
+            let commands: Vec<Vec<&str>> = match dialect {
+                Dialect::Ca65 => vec![vec!["ca65", &source, "-o", "prog.o"],
+                    vec!["ld65", "-t", "none", "-S", "0x1000", "-o", "prog.bin", "prog.o"]],
+                Dialect::Acme => vec![vec!["acme", "-f", "plain", "-o", "prog.bin", &source]],
+                Dialect::Tass64 => vec![vec!["64tass", "--nostart", "-o", "prog.bin", &source]],
+            };
+
+            let mut ran = true;
+            for args in &commands {
+                match Command::new(args[0]).args(&args[1..]).current_dir(&dir).status() {
+                    Ok(status) => assert!(status.success(), "{} failed on:\n{}", args[0],
+                        std::fs::read_to_string(dir.join(&source)).unwrap()),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        eprintln!("{} isn't installed, skipping {}", args[0], dialect.name());
+                        ran = false;
+                        break;
+                    }
+                    Err(e) => panic!("running {}: {}", args[0], e),
+                }
+            }
+            if ran {
+                assert_eq!(std::fs::read(dir.join("prog.bin")).unwrap(), code, "{}", dialect.name());
+            }
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
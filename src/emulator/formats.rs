@@ -0,0 +1,229 @@
+use crate::emulator::cpu::CPU6502;
+use crate::emulator::disassembler::ByteSource;
+use crate::emulator::symbols::SymbolTable;
+
+/*
+*   Object file formats
+*
+*   Every loader produces a LoadImage, which is a list of segments
+*   and maybe a start address, so loading into the CPU is the same
+*   for all of them.
+*/
+
+pub mod elf;
+pub mod ihex;
+pub mod ines;
+pub mod prg;
+pub mod srec;
+pub mod xex;
+
+/// Bytes that go at addr
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub addr: u16,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    /// One past the last address
+    pub fn end(&self) -> u32 {
+        self.addr as u32 + self.data.len() as u32
+    }
+
+    pub fn write_to(&self, cpu: &mut CPU6502) {
+        for (i, byte) in self.data.iter().enumerate() {
+            cpu.write(self.addr.wrapping_add(i as u16), *byte);
+        }
+    }
+}
+
+/// Everything an object file holds
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoadImage {
+    pub segments: Vec<Segment>,
+    /// Start address given by the file
+    pub start: Option<u16>,
+}
+
+/// What loading does with the start address
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StartAction {
+    /// Only write the segments
+    None,
+    /// Set PC to it
+    SetPc,
+    /// Write it to the reset vector
+    SetResetVector,
+}
+
+impl LoadImage {
+    /// Adds data at addr, joining it onto the previous segment if it follows on
+    pub fn push(&mut self, addr: u16, data: &[u8]) -> Result<(), String> {
+        if addr as usize + data.len() > 0x10000 {
+            return Err(format!("data at ${:04x} runs past $ffff", addr));
+        }
+
+        match self.segments.last_mut() {
+            Some(last) if last.end() == addr as u32 => last.data.extend_from_slice(data),
+            _ => self.segments.push(Segment { addr, data: data.to_vec() }),
+        }
+        Ok(())
+    }
+
+    /// Copies memory ranges, both ends included
+    pub fn from_ranges(src: &impl ByteSource, ranges: &[(u16, u16)], start: Option<u16>) -> Self {
+        let mut image = LoadImage { segments: Vec::new(), start };
+
+        for (from, to) in ranges {
+            let data = (*from..=*to).map(|addr| src.byte_at(addr).unwrap_or(0)).collect();
+            image.segments.push(Segment { addr: *from, data });
+        }
+        image
+    }
+
+    /// Total bytes in every segment
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|x| x.data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// First and last address of each segment
+    pub fn ranges(&self) -> Vec<(u16, u16)> {
+        self.segments.iter()
+            .filter(|x| !x.data.is_empty())
+            .map(|x| (x.addr, (x.end() - 1) as u16))
+            .collect()
+    }
+
+    /// The file's start address, or where the first segment goes
+    pub fn entry(&self) -> Option<u16> {
+        self.start.or_else(|| self.segments.first().map(|x| x.addr))
+    }
+
+    /// Writes every segment into memory, then handles the start address
+    pub fn load_into(&self, cpu: &mut CPU6502, action: StartAction) {
+        for segment in &self.segments {
+            segment.write_to(cpu);
+        }
+        self.set_start(cpu, action);
+    }
+
+    /// Only the start address part of load_into()
+    pub fn set_start(&self, cpu: &mut CPU6502, action: StartAction) {
+        if let Some(entry) = self.entry() {
+            match action {
+                StartAction::None => (),
+                StartAction::SetPc => cpu.pc = entry,
+                StartAction::SetResetVector => {
+                    cpu.write(0xFFFC, entry as u8);
+                    cpu.write(0xFFFD, (entry >> 8) as u8);
+                }
+            }
+        }
+    }
+}
+
+/// What load_file() did
+pub struct Loaded {
+    /// Bytes written
+    pub len: usize,
+    /// Names the file came with (empty for most formats)
+    pub symbols: SymbolTable,
+    /// Where it went, both ends included
+    pub ranges: Vec<(u16, u16)>,
+}
+
+/// Loads a file's contents, picking the format from its magic number or
+/// name's extension. Anything unknown is raw binary put at raw_addr
+pub fn load_file(name: &str, bytes: &[u8], cpu: &mut CPU6502, raw_addr: u16, action: StartAction)
+-> Result<Loaded, String> {
+    let ext = name.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+    let text = || String::from_utf8_lossy(bytes).into_owned();
+    let mut symbols = SymbolTable::new();
+
+    let ext = match bytes {
+        x if x.starts_with(b"\x7FELF") => "elf",
+        x if x.starts_with(b"NES\x1A") => "nes",
+        _ => &ext[..],
+    };
+
+    let image = match ext {
+        "hex" | "ihx" | "ihex" => ihex::parse(&text())?,
+        "s19" | "s28" | "s37" | "srec" | "mot" => srec::parse(&text())?,
+        "prg" => prg::parse(bytes)?,
+        "elf" => {
+            let elf = elf::parse(bytes)?;
+            symbols = elf.symbols;
+            elf.image
+        }
+        "xex" | "com" | "exe" => {
+            let xex = xex::parse(bytes)?;
+            xex.load_into(cpu, action)?;
+            let image = xex.image();
+            return Ok(Loaded { len: image.len(), symbols, ranges: image.ranges() });
+        }
+        // always starts from the cartridge's reset vector
+        "nes" => {
+            let cart = ines::parse(bytes)?.into_cartridge()?;
+            let len = cart.prg().len();
+            cpu.insert_cartridge(cart);
+            return Ok(Loaded { len, symbols, ranges: vec![(0x8000, 0xFFFF)] });
+        }
+        _ => {
+            let mut image = LoadImage::default();
+            image.push(raw_addr, bytes)?;
+            image
+        }
+    };
+
+    image.load_into(cpu, action);
+    Ok(Loaded { len: image.len(), symbols, ranges: image.ranges() })
+}
+
+/// Parses a pair of hex digits
+pub(crate) fn hex_byte(s: &str, line: usize) -> Result<u8, String> {
+    u8::from_str_radix(s, 16).map_err(|_| format!("line {}: '{}' isn't hex", line + 1, s))
+}
+
+/// Every byte of a record given as hex, after the record's prefix
+pub(crate) fn hex_record(s: &str, line: usize) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(format!("line {}: odd number of hex digits", line + 1));
+    }
+    (0..s.len()).step_by(2).map(|i| hex_byte(&s[i..i + 2], line)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_joins_and_loads() {
+        let mut image = LoadImage::default();
+        image.push(0x8000, &[1, 2]).unwrap();
+        image.push(0x8002, &[3]).unwrap();
+        image.push(0x9000, &[4]).unwrap();
+        assert!(image.push(0xffff, &[5, 6]).is_err());
+
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[0].data, vec![1, 2, 3]);
+        assert_eq!(image.entry(), Some(0x8000));
+
+        let mut cpu = CPU6502::create_cpu_and_bus(0);
+        image.start = Some(0x9000);
+        image.load_into(&mut cpu, StartAction::SetResetVector);
+        assert_eq!(cpu.read(0x8002), 3);
+        assert_eq!(cpu.read_u16(0xFFFC), 0x9000);
+        assert_eq!(cpu.pc, 0);
+
+        image.load_into(&mut cpu, StartAction::SetPc);
+        assert_eq!(cpu.pc, 0x9000);
+
+        let copy = LoadImage::from_ranges(&cpu, &[(0x8000, 0x8002)], None);
+        assert_eq!(copy.segments[0].data, vec![1, 2, 3]);
+    }
+}
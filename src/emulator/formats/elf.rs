@@ -0,0 +1,218 @@
+use crate::emulator::formats::LoadImage;
+use crate::emulator::symbols::SymbolTable;
+
+/*
+*   ELF executables from llvm-mos
+*
+*   32 bit little endian, machine EM_MOS. PT_LOAD segments are loaded at
+*   their physical (load) address, like objcopy -O binary would, and the
+*   rest of a segment's memory size is zeroed. The entry point becomes
+*   the start address.
+*
+*   Functions, objects and plain labels in .symtab become symbols.
+*   llvm-mos puts banked/other address spaces above $ffff, anything
+*   up there is skipped.
+*/
+
+pub const EM_MOS : u16 = 6502;
+
+const PT_LOAD : u32 = 1;
+const SHT_SYMTAB : u32 = 2;
+
+const STT_NOTYPE : u8 = 0;
+const STT_OBJECT : u8 = 1;
+const STT_FUNC : u8 = 2;
+const SHN_UNDEF : u16 = 0;
+
+#[derive(Clone, Debug)]
+pub struct Elf {
+    pub image: LoadImage,
+    pub symbols: SymbolTable,
+    /// PT_LOAD segments that didn't fit in 64K
+    pub skipped: usize,
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn u8(&self, at: usize) -> Result<u8, String> {
+        self.0.get(at).copied().ok_or_else(|| "ELF file is cut off".to_string())
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, String> {
+        Ok((self.u8(at + 1)? as u16) << 8 | self.u8(at)? as u16)
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, String> {
+        Ok((self.u16(at + 2)? as u32) << 16 | self.u16(at)? as u32)
+    }
+
+    fn slice(&self, at: u32, len: u32) -> Result<&[u8], String> {
+        let (at, len) = (at as usize, len as usize);
+        self.0.get(at..at.saturating_add(len)).ok_or_else(|| "ELF file is cut off".to_string())
+    }
+
+    /// NUL terminated string in the string table at table
+    fn str_at(&self, table: u32, offset: u32) -> Result<&str, String> {
+        let start = table as usize + offset as usize;
+        let rest = self.0.get(start..).ok_or("ELF string table is cut off")?;
+        let end = rest.iter().position(|b| *b == 0).ok_or("ELF string isn't terminated")?;
+        std::str::from_utf8(&rest[..end]).map_err(|_| "ELF symbol name isn't UTF-8".to_string())
+    }
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Elf, String> {
+    let r = Reader(bytes);
+
+    if !bytes.starts_with(b"\x7FELF") {
+        return Err("Not an ELF file".into());
+    }
+    if r.u8(4)? != 1 || r.u8(5)? != 1 {
+        return Err("Only 32 bit little endian ELF files are supported".into());
+    }
+    let machine = r.u16(18)?;
+    if machine != EM_MOS {
+        return Err(format!("ELF file is for machine {}, not the 6502 (EM_MOS)", machine));
+    }
+
+    let entry = r.u32(24)?;
+    let (phoff, shoff) = (r.u32(28)? as usize, r.u32(32)? as usize);
+    let (phentsize, phnum) = (r.u16(42)? as usize, r.u16(44)? as usize);
+    let (shentsize, shnum) = (r.u16(46)? as usize, r.u16(48)? as usize);
+
+    let mut elf = Elf { image: LoadImage::default(), symbols: SymbolTable::new(), skipped: 0 };
+
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        if r.u32(ph)? != PT_LOAD {
+            continue;
+        }
+        let (offset, paddr, filesz, memsz) = (r.u32(ph + 4)?, r.u32(ph + 12)?, r.u32(ph + 16)?, r.u32(ph + 20)?);
+        if memsz == 0 {
+            continue;
+        }
+        if paddr as u64 + memsz as u64 > 0x10000 {
+            elf.skipped += 1;
+            continue;
+        }
+
+        let mut data = r.slice(offset, filesz)?.to_vec();
+        data.resize(memsz.max(filesz) as usize, 0);
+        elf.image.push(paddr as u16, &data)?;
+    }
+
+    if entry <= 0xFFFF {
+        elf.image.start = Some(entry as u16);
+    }
+
+    for i in 0..shnum {
+        let sh = shoff + i * shentsize;
+        if r.u32(sh + 4)? != SHT_SYMTAB {
+            continue;
+        }
+        let (offset, size, link, entsize) = (r.u32(sh + 16)?, r.u32(sh + 20)?, r.u32(sh + 24)?, r.u32(sh + 36)?);
+        let strtab = r.u32(shoff + link as usize * shentsize + 16)?;
+        let entsize = if entsize == 0 { 16 } else { entsize };
+
+        for sym in (offset..offset.saturating_add(size)).step_by(entsize as usize) {
+            let sym = sym as usize;
+            let (name, value, info, shndx) = (r.u32(sym)?, r.u32(sym + 4)?, r.u8(sym + 12)?, r.u16(sym + 14)?);
+
+            let wanted = matches!(info & 0x0F, STT_NOTYPE | STT_OBJECT | STT_FUNC);
+            if !wanted || shndx == SHN_UNDEF || value > 0xFFFF || name == 0 {
+                continue;
+            }
+            let name = r.str_at(strtab, name)?;
+            if !name.is_empty() {
+                elf.symbols.insert(name, value as u16);
+            }
+        }
+    }
+
+    Ok(elf)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put16(v: &mut Vec<u8>, x: u16) { v.extend_from_slice(&x.to_le_bytes()); }
+    fn put32(v: &mut Vec<u8>, x: u32) { v.extend_from_slice(&x.to_le_bytes()); }
+
+    /// Header, one PT_LOAD of code at $0200 (with 2 bytes of bss), a banked
+    /// segment, then .symtab and .strtab
+    fn test_file() -> Vec<u8> {
+        let code = [0xa9, 0x01, 0x60];
+        let strtab = b"\0main\0counter\0";
+
+        // header 52, 2 program headers 64, code 3, strtab 14, symtab 3*16, 3 section headers
+        let ph_at = 52;
+        let code_at = ph_at + 64;
+        let str_at = code_at + 3;
+        let sym_at = str_at + strtab.len() as u32;
+        let sh_at = sym_at + 48;
+
+        let mut v = b"\x7FELF\x01\x01\x01\0\0\0\0\0\0\0\0\0".to_vec();
+        put16(&mut v, 2);
+        put16(&mut v, EM_MOS);
+        put32(&mut v, 1);
+        put32(&mut v, 0x0200);
+        put32(&mut v, ph_at);
+        put32(&mut v, sh_at);
+        put32(&mut v, 0);
+        for x in [52, 32, 2, 40, 3, 0].iter() {
+            put16(&mut v, *x);
+        }
+
+        for (paddr, memsz) in [(0x0200, 5), (0x1_8000, 3)].iter() {
+            for x in [PT_LOAD, code_at, *paddr, *paddr, 3, *memsz, 5, 1].iter() {
+                put32(&mut v, *x);
+            }
+        }
+        v.extend_from_slice(&code);
+        v.extend_from_slice(strtab);
+
+        // null symbol, main (func), counter (object)
+        for (name, value, info) in [(0, 0, 0), (1, 0x0200, STT_FUNC), (6, 0x0203, STT_OBJECT)].iter() {
+            put32(&mut v, *name);
+            put32(&mut v, *value);
+            put32(&mut v, 0);
+            v.push(*info);
+            v.push(0);
+            put16(&mut v, if *name == 0 { 0 } else { 1 });
+        }
+
+        // null, .symtab (link 2), .strtab
+        for (kind, offset, size, link, entsize) in [(0, 0, 0, 0, 0), (SHT_SYMTAB, sym_at, 48, 2, 16), (3, str_at, 14, 0, 0)].iter() {
+            for x in [0, *kind, 0, 0, *offset, *size, *link, 0, 1, *entsize].iter() {
+                put32(&mut v, *x);
+            }
+        }
+        v
+    }
+
+    #[test]
+    fn segments_entry_and_symbols() {
+        let elf = parse(&test_file()).unwrap();
+
+        assert_eq!(elf.image.segments.len(), 1);
+        assert_eq!(elf.image.segments[0].addr, 0x0200);
+        assert_eq!(elf.image.segments[0].data, vec![0xa9, 0x01, 0x60, 0, 0]);
+        assert_eq!(elf.image.start, Some(0x0200));
+        assert_eq!(elf.skipped, 1);
+
+        assert_eq!(elf.symbols.name_of(0x0200), Some("main"));
+        assert_eq!(elf.symbols.addr_of("counter"), Some(0x0203));
+        assert_eq!(elf.symbols.len(), 2);
+
+        let mut wrong = test_file();
+        wrong[18] = 3;
+        wrong[19] = 0;
+        assert!(parse(&wrong).unwrap_err().contains("machine 3"));
+
+        let mut cut = test_file();
+        cut.truncate(30);
+        assert!(parse(&cut).unwrap_err().contains("cut off"));
+    }
+}
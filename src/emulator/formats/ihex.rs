@@ -0,0 +1,130 @@
+use crate::emulator::formats::{hex_record, LoadImage};
+
+/*
+*   Intel HEX
+*
+*   `:LLAAAATT<data>CC` where CC makes every byte of the record sum to 0.
+*   Types used: 00 data, 01 end of file, 02/04 extended (segment/linear)
+*   address, 03/05 start address. Addresses past $ffff are an error.
+*/
+
+const BYTES_PER_RECORD : usize = 16;
+
+pub fn parse(text: &str) -> Result<LoadImage, String> {
+    let mut image = LoadImage::default();
+    // from type 02/04 records
+    let mut base: u32 = 0;
+    let mut ended = false;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if ended {
+            return Err(format!("line {}: record after end of file", n + 1));
+        }
+
+        let rec = match line.strip_prefix(':') {
+            Some(x) => hex_record(x, n)?,
+            None => return Err(format!("line {}: record doesn't start with ':'", n + 1)),
+        };
+        if rec.len() < 5 || rec.len() != rec[0] as usize + 5 {
+            return Err(format!("line {}: wrong record length", n + 1));
+        }
+        if rec.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(format!("line {}: bad checksum", n + 1));
+        }
+
+        let addr = (rec[1] as u32) << 8 | rec[2] as u32;
+        let data = &rec[4..rec.len() - 1];
+        let big_endian = |d: &[u8]| d.iter().fold(0u32, |acc, b| acc << 8 | *b as u32);
+
+        match (rec[3], data.len()) {
+            (0x00, _) => {
+                let full = base + addr;
+                if full.checked_add(data.len() as u32).is_none_or(|end| end > 0x10000) {
+                    return Err(format!("line {}: address ${:x} is past $ffff", n + 1, full));
+                }
+                image.push(full as u16, data).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            }
+            (0x01, _) => ended = true,
+            (0x02, 2) => base = big_endian(data) << 4,
+            (0x04, 2) => base = big_endian(data) << 16,
+            (0x03, 4) | (0x05, 4) => {
+                let start = match rec[3] {
+                    // CS:IP
+                    0x03 => (big_endian(&data[..2]) << 4) + big_endian(&data[2..]),
+                    _ => big_endian(data),
+                };
+                if start > 0xFFFF {
+                    return Err(format!("line {}: start address ${:x} is past $ffff", n + 1, start));
+                }
+                image.start = Some(start as u16);
+            }
+            (t, _) => return Err(format!("line {}: bad record type {:02x}", n + 1, t)),
+        }
+    }
+
+    Ok(image)
+}
+
+fn record(kind: u8, addr: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes.push(sum.wrapping_neg());
+
+    format!(":{}", hex::encode_upper(bytes))
+}
+
+/// Data records for every segment, a start address record
+/// if image has one, then the end of file record
+pub fn write(image: &LoadImage) -> String {
+    let mut lines = Vec::new();
+
+    for segment in &image.segments {
+        for (i, chunk) in segment.data.chunks(BYTES_PER_RECORD).enumerate() {
+            let addr = segment.addr.wrapping_add((i * BYTES_PER_RECORD) as u16);
+            lines.push(record(0x00, addr, chunk));
+        }
+    }
+
+    if let Some(start) = image.start {
+        lines.push(record(0x05, 0, &(start as u32).to_be_bytes()));
+    }
+    lines.push(record(0x01, 0, &[]));
+
+    let mut ret = lines.join("\n");
+    ret.push('\n');
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::formats::Segment;
+
+    #[test]
+    fn parse_and_write() {
+        let text = "
+            :0380000001020377
+            :02900000AABB09
+            :040000050000800077
+            :00000001FF
+        ";
+        let image = parse(text).unwrap();
+        assert_eq!(image.segments, vec![
+            Segment { addr: 0x8000, data: vec![1, 2, 3] },
+            Segment { addr: 0x9000, data: vec![0xaa, 0xbb] },
+        ]);
+        assert_eq!(image.start, Some(0x8000));
+        assert_eq!(parse(&write(&image)).unwrap(), image);
+
+        assert!(parse(":0380000001020378").unwrap_err().contains("checksum"));
+        assert!(parse(":020000040001F9\n:0100000000FF").unwrap_err().contains("past $ffff"));
+        assert!(parse(":02000004FFFFFC\n:02FFFF000102FD").unwrap_err().contains("past $ffff"));
+        assert!(parse("0380000001020377").is_err());
+    }
+}
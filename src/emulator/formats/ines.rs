@@ -0,0 +1,189 @@
+use crate::emulator::cartridge::{Cartridge, Mirroring, CHR_BANK_SIZE, PRG_BANK_SIZE};
+
+/*
+*   iNES and NES 2.0 ROMs (.nes)
+*
+*   16 byte header: "NES\x1A", PRG size (16K units), CHR size (8K units),
+*   flags 6 (mirroring, battery, trainer, four screen, mapper low nibble),
+*   flags 7 (NES 2.0 marker, mapper high nibble). NES 2.0 adds mapper
+*   bits 8-11, a submapper and bigger sizes in bytes 8 and 9.
+*
+*   Then a 512 byte trainer if flag 6 says so, PRG ROM and CHR ROM.
+*/
+
+pub const HEADER_SIZE : usize = 16;
+pub const TRAINER_SIZE : usize = 512;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InesHeader {
+    pub nes2: bool,
+    /// In bytes
+    pub prg_size: usize,
+    /// In bytes, 0 means the board has CHR RAM
+    pub chr_size: usize,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+}
+
+/// Everything in a .nes file
+#[derive(Clone, Debug)]
+pub struct InesRom {
+    pub header: InesHeader,
+    pub trainer: Option<Vec<u8>>,
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+}
+
+/// NES 2.0 size: msb nibble and lsb byte, or exponent/multiplier if msb is $F
+fn rom_size(msb: u8, lsb: u8, unit: usize, what: &str) -> Result<usize, String> {
+    match msb {
+        0xF => {
+            let (exp, mul) = (lsb >> 2, (lsb & 3) as usize * 2 + 1);
+            1usize.checked_shl(exp as u32).and_then(|x| x.checked_mul(mul))
+                .ok_or_else(|| format!("{} size 2^{} * {} is too big", what, exp, mul))
+        }
+        _ => Ok(((msb as usize) << 8 | lsb as usize) * unit),
+    }
+}
+
+pub fn parse_header(bytes: &[u8]) -> Result<InesHeader, String> {
+    if bytes.len() < HEADER_SIZE || &bytes[..4] != b"NES\x1A" {
+        return Err("Not an iNES file (no NES<EOF> header)".into());
+    }
+
+    let (flags6, flags7) = (bytes[6], bytes[7]);
+    let nes2 = flags7 & 0x0C == 0x08;
+
+    let mirroring = match (flags6 & 0x08 != 0, flags6 & 0x01 != 0) {
+        (true, _) => Mirroring::FourScreen,
+        (false, true) => Mirroring::Vertical,
+        (false, false) => Mirroring::Horizontal,
+    };
+
+    let mut mapper = (flags6 >> 4) as u16;
+    let (mut prg_size, mut chr_size) = (bytes[4] as usize * PRG_BANK_SIZE, bytes[5] as usize * CHR_BANK_SIZE);
+    let mut submapper = 0;
+
+    if nes2 {
+        mapper |= (flags7 & 0xF0) as u16 | ((bytes[8] & 0x0F) as u16) << 8;
+        submapper = bytes[8] >> 4;
+        prg_size = rom_size(bytes[9] & 0x0F, bytes[4], PRG_BANK_SIZE, "PRG ROM")?;
+        chr_size = rom_size(bytes[9] >> 4, bytes[5], CHR_BANK_SIZE, "CHR ROM")?;
+    } else if flags7 & 0x0C == 0 && bytes[12..16].iter().all(|x| *x == 0) {
+        // older dumps have junk ("DiskDude!") where the high nibble would be
+        mapper |= (flags7 & 0xF0) as u16;
+    }
+
+    Ok(InesHeader {
+        nes2,
+        prg_size,
+        chr_size,
+        mapper,
+        submapper,
+        mirroring,
+        battery: flags6 & 0x02 != 0,
+        trainer: flags6 & 0x04 != 0,
+    })
+}
+
+pub fn parse(bytes: &[u8]) -> Result<InesRom, String> {
+    let header = parse_header(bytes)?;
+
+    let mut at = HEADER_SIZE;
+    let mut take = |len: usize, what: &str| {
+        let part = bytes.get(at..at.saturating_add(len))
+            .ok_or_else(|| format!("File is too short for its {} ({} bytes)", what, len))?;
+        at += len;
+        Ok::<_, String>(part.to_vec())
+    };
+
+    let trainer = match header.trainer {
+        true => Some(take(TRAINER_SIZE, "trainer")?),
+        false => None,
+    };
+    let prg = take(header.prg_size, "PRG ROM")?;
+    let chr = take(header.chr_size, "CHR ROM")?;
+
+    Ok(InesRom { header, trainer, prg, chr })
+}
+
+impl InesRom {
+    /// Errors if the mapper isn't supported
+    pub fn into_cartridge(self) -> Result<Cartridge, String> {
+        let h = self.header;
+        Cartridge::new(h.mapper, self.prg, self.chr, h.mirroring, h.battery)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::cpu::CPU6502;
+
+    fn rom(flags6: u8, flags7: u8, prg_banks: u8) -> Vec<u8> {
+        let mut rom = b"NES\x1A".to_vec();
+        rom.extend_from_slice(&[prg_banks, 1, flags6, flags7, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut prg = vec![0xEA; prg_banks as usize * PRG_BANK_SIZE];
+        // reset vector -> $C000
+        let len = prg.len();
+        prg[len - 4] = 0x00;
+        prg[len - 3] = 0xC0;
+        rom.extend(prg);
+        rom.extend(vec![0x55; CHR_BANK_SIZE]);
+        rom
+    }
+
+    #[test]
+    fn header_fields() {
+        let h = parse_header(&rom(0x03, 0x00, 1)).unwrap();
+        assert!(!h.nes2);
+        assert_eq!((h.prg_size, h.chr_size, h.mapper), (PRG_BANK_SIZE, CHR_BANK_SIZE, 0));
+        assert_eq!(h.mirroring, Mirroring::Vertical);
+        assert!(h.battery && !h.trainer);
+
+        // NES 2.0, mapper $104 submapper 2
+        let mut bytes = rom(0x48, 0x08, 1);
+        bytes[8] = 0x21;
+        let h = parse_header(&bytes).unwrap();
+        assert!(h.nes2);
+        assert_eq!((h.mapper, h.submapper), (0x104, 2));
+        assert_eq!(h.mirroring, Mirroring::FourScreen);
+
+        // exponent sizes, 2^2 * 3 and one too big to have
+        bytes[9] = 0xF0;
+        bytes[5] = 0x09;
+        assert_eq!(parse_header(&bytes).unwrap().chr_size, 12);
+        bytes[5] = 0xFF;
+        assert!(parse_header(&bytes).unwrap_err().contains("too big"));
+
+        // junk in the old header's padding
+        let mut bytes = rom(0x10, 0x40, 1);
+        bytes[12..16].copy_from_slice(b"Dude");
+        assert_eq!(parse_header(&bytes).unwrap().mapper, 1);
+
+        assert!(parse(&rom(0x04, 0x00, 1)).unwrap_err().contains("CHR ROM"));
+        assert!(parse_header(b"NOPE").is_err());
+    }
+
+    #[test]
+    fn nrom_boots_from_reset_vector() {
+        let cart = parse(&rom(0x00, 0x00, 1)).unwrap().into_cartridge().unwrap();
+        assert_eq!(cart.chr()[0], 0x55);
+
+        let mut cpu = CPU6502::create_cpu_and_bus(0);
+        cpu.insert_cartridge(cart);
+        assert_eq!(cpu.pc, 0xC000);
+        // 16K mirrored, writes ignored
+        assert_eq!(cpu.read(0xBFFC), 0x00);
+        cpu.write(0x8000, 0x00);
+        assert_eq!(cpu.read(0x8000), 0xEA);
+
+        let mmc1 = parse(&rom(0x10, 0x00, 1)).unwrap().into_cartridge();
+        assert!(mmc1.err().unwrap().contains("Mapper 1 (MMC1)"));
+    }
+}
@@ -0,0 +1,84 @@
+use crate::emulator::formats::LoadImage;
+
+/*
+*   Commodore .prg (C64, VIC-20, ...)
+*
+*   A little endian load address, then the bytes that go there.
+*   Programs loaded into BASIC memory usually start with a stub like
+*   `10 SYS 2064`, the address after SYS becomes the start address.
+*/
+
+const SYS_TOKEN : u8 = 0x9E;
+
+pub fn parse(bytes: &[u8]) -> Result<LoadImage, String> {
+    if bytes.len() < 2 {
+        return Err("prg file is too short to have a load address".into());
+    }
+
+    let load = (bytes[1] as u16) << 8 | bytes[0] as u16;
+    let data = &bytes[2..];
+
+    let mut image = LoadImage::default();
+    image.push(load, data)?;
+    image.start = sys_address(load, data);
+    Ok(image)
+}
+
+/// Address from the first SYS in a BASIC program at load
+pub fn sys_address(load: u16, data: &[u8]) -> Option<u16> {
+    let mut line = 0usize;
+
+    // each line: next line pointer, line number, tokens, 0
+    while line + 4 < data.len() {
+        let next = (data[line + 1] as u16) << 8 | data[line] as u16;
+        if next == 0 {
+            break;
+        }
+
+        let end = data[line + 4..].iter().position(|b| *b == 0).map(|x| x + line + 4)?;
+        let tokens = &data[line + 4..end];
+
+        if let Some(sys) = tokens.iter().position(|b| *b == SYS_TOKEN) {
+            // `SYS 2064`, `SYS(2064)` and `SYS2064` all work
+            let digits: String = tokens[sys + 1..].iter()
+                .map(|b| *b as char)
+                .skip_while(|c| *c == ' ' || *c == '(')
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            return digits.parse::<u32>().ok().filter(|x| *x <= 0xFFFF).map(|x| x as u16);
+        }
+
+        // pointers are absolute, they have to move forward
+        match next.checked_sub(load) {
+            Some(offset) if offset as usize > line => line = offset as usize,
+            _ => break,
+        }
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basic_stub() {
+        // 10 SYS 2062, then INC $D020 / RTS
+        let mut prg = vec![0x01, 0x08, 0x0c, 0x08, 0x0a, 0x00, 0x9e];
+        prg.extend_from_slice(b" 2062");
+        prg.extend_from_slice(&[0x00, 0x00, 0x00, 0xee, 0x20, 0xd0, 0x60]);
+
+        let image = parse(&prg).unwrap();
+        assert_eq!(image.segments[0].addr, 0x0801);
+        assert_eq!(image.segments[0].data[13], 0xee);
+        assert_eq!(image.start, Some(0x0801 + 13));
+
+        // machine code without a stub
+        let image = parse(&[0x00, 0xc0, 0xa9, 0x00, 0x60]).unwrap();
+        assert_eq!(image.start, None);
+        assert_eq!(image.entry(), Some(0xc000));
+
+        assert!(parse(&[0x01]).is_err());
+    }
+}
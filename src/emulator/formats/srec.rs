@@ -0,0 +1,134 @@
+use crate::emulator::formats::{hex_record, LoadImage};
+
+/*
+*   Motorola S-records (S19/S28/S37)
+*
+*   `S<type><count><address><data><checksum>`, count covers the address,
+*   data and checksum. The checksum is the ones' complement of the sum
+*   of every byte after the type.
+*
+*   S0 header, S1/S2/S3 data with 16/24/32 bit addresses,
+*   S5/S6 record counts, S7/S8/S9 start address
+*/
+
+const BYTES_PER_RECORD : usize = 16;
+
+pub fn parse(text: &str) -> Result<LoadImage, String> {
+    let mut image = LoadImage::default();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut chars = line.chars();
+        let kind = match (chars.next(), chars.next()) {
+            (Some('S'), Some(k)) | (Some('s'), Some(k)) => k,
+            _ => return Err(format!("line {}: record doesn't start with 'S'", n + 1)),
+        };
+
+        // 'S' is one byte, kind might not be
+        let rec = hex_record(&line[1 + kind.len_utf8()..], n)?;
+        if rec.len() < 2 || rec.len() != rec[0] as usize + 1 {
+            return Err(format!("line {}: wrong record length", n + 1));
+        }
+        if rec.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xFF {
+            return Err(format!("line {}: bad checksum", n + 1));
+        }
+
+        let addr_len = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(format!("line {}: bad record type S{}", n + 1, kind)),
+        };
+        if rec.len() < addr_len + 2 {
+            return Err(format!("line {}: wrong record length", n + 1));
+        }
+
+        let addr = rec[1..=addr_len].iter().fold(0u32, |acc, b| acc << 8 | *b as u32);
+        let data = &rec[addr_len + 1..rec.len() - 1];
+
+        match kind {
+            '1' | '2' | '3' => {
+                if addr.checked_add(data.len() as u32).is_none_or(|end| end > 0x10000) {
+                    return Err(format!("line {}: address ${:x} is past $ffff", n + 1, addr));
+                }
+                image.push(addr as u16, data).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            }
+            '7' | '8' | '9' => {
+                if addr > 0xFFFF {
+                    return Err(format!("line {}: start address ${:x} is past $ffff", n + 1, addr));
+                }
+                image.start = Some(addr as u16);
+            }
+            // header and counts
+            _ => (),
+        }
+    }
+
+    Ok(image)
+}
+
+fn record(kind: char, addr: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8 + 3, (addr >> 8) as u8, addr as u8];
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes.push(!sum);
+
+    format!("S{}{}", kind, hex::encode_upper(bytes))
+}
+
+/// S19 file: header, S1 data records, the count and an S9 start
+/// record (the first segment's address if image has no start)
+pub fn write(image: &LoadImage) -> String {
+    let mut lines = vec![record('0', 0, b"simple_6502rs")];
+
+    for segment in &image.segments {
+        for (i, chunk) in segment.data.chunks(BYTES_PER_RECORD).enumerate() {
+            let addr = segment.addr.wrapping_add((i * BYTES_PER_RECORD) as u16);
+            lines.push(record('1', addr, chunk));
+        }
+    }
+
+    // at most 4096 data records, fits S5
+    let count = lines.len() as u16 - 1;
+    lines.push(record('5', count, &[]));
+    lines.push(record('9', image.entry().unwrap_or(0), &[]));
+
+    let mut ret = lines.join("\n");
+    ret.push('\n');
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::formats::Segment;
+
+    #[test]
+    fn parse_and_write() {
+        let text = "
+            S00600004844521B
+            S106800001020373
+            S2060090000A0B54
+            S5030002FA
+            S9038000 7C
+        ".replace(' ', "");
+        let image = parse(&text).unwrap();
+        assert_eq!(image.segments, vec![
+            Segment { addr: 0x8000, data: vec![1, 2, 3] },
+            Segment { addr: 0x9000, data: vec![0x0a, 0x0b] },
+        ]);
+        assert_eq!(image.start, Some(0x8000));
+        assert_eq!(parse(&write(&image)).unwrap(), image);
+
+        assert!(parse("S1068000010203FF").unwrap_err().contains("checksum"));
+        assert!(parse("S2060190000A0B53").unwrap_err().contains("past $ffff"));
+        let wraps = format!("S315FFFFFFF0{}FD", "00".repeat(16));
+        assert!(parse(&wraps).unwrap_err().contains("past $ffff"));
+        assert!(parse("Sé0680").is_err());
+    }
+}
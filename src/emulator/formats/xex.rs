@@ -0,0 +1,141 @@
+use crate::emulator::cpu::CPU6502;
+use crate::emulator::formats::{LoadImage, Segment, StartAction};
+
+/*
+*   Atari DOS binary load files (.xex, .com, .exe)
+*
+*   $FFFF, then segments of [start][end][bytes], both ends included.
+*   More $FFFF markers can appear before any segment header.
+*
+*   A segment writing INITAD makes DOS call that address as soon as
+*   the segment is loaded, RUNAD is jumped to once everything is loaded.
+*/
+
+pub const RUNAD : u16 = 0x02E0;
+pub const INITAD : u16 = 0x02E2;
+
+/// Instructions an INIT routine gets before loading gives up
+pub const INIT_STEP_LIMIT : u32 = 1_000_000;
+
+#[derive(Clone, Debug)]
+pub struct XexSegment {
+    pub segment: Segment,
+    /// INITAD, if this segment wrote it
+    pub init: Option<u16>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Xex {
+    pub segments: Vec<XexSegment>,
+    /// Last value written to RUNAD
+    pub run: Option<u16>,
+}
+
+fn word(bytes: &[u8], at: usize) -> Option<u16> {
+    Some((*bytes.get(at + 1)? as u16) << 8 | *bytes.get(at)? as u16)
+}
+
+/// Value written to addr and addr + 1 by segment, both bytes have to be
+fn written_word(segment: &Segment, addr: u16) -> Option<u16> {
+    let lo = addr.checked_sub(segment.addr)? as usize;
+    word(&segment.data, lo)
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Xex, String> {
+    if word(bytes, 0) != Some(0xFFFF) {
+        return Err("binary load file doesn't start with $FFFF".into());
+    }
+
+    let mut xex = Xex { segments: Vec::new(), run: None };
+    let mut at = 2;
+
+    while at < bytes.len() {
+        let mut start = word(bytes, at).ok_or("truncated segment header")?;
+        at += 2;
+        if start == 0xFFFF {
+            start = word(bytes, at).ok_or("truncated segment header")?;
+            at += 2;
+        }
+        let end = word(bytes, at).ok_or("truncated segment header")?;
+        at += 2;
+
+        if end < start {
+            return Err(format!("segment ${:04x}-${:04x} ends before it starts", start, end));
+        }
+        let len = (end - start) as usize + 1;
+        let data = bytes.get(at..at + len)
+            .ok_or_else(|| format!("segment ${:04x}-${:04x} is cut off", start, end))?;
+        at += len;
+
+        let segment = Segment { addr: start, data: data.to_vec() };
+        if let Some(run) = written_word(&segment, RUNAD) {
+            xex.run = Some(run);
+        }
+        let init = written_word(&segment, INITAD);
+        xex.segments.push(XexSegment { segment, init });
+    }
+
+    Ok(xex)
+}
+
+impl Xex {
+    /// Every segment, starting at RUNAD (INIT routines can't run this way)
+    pub fn image(&self) -> LoadImage {
+        LoadImage {
+            segments: self.segments.iter().map(|x| x.segment.clone()).collect(),
+            start: self.run,
+        }
+    }
+
+    /// Loads segments one at a time like DOS does, calling INIT
+    /// routines in between
+    pub fn load_into(&self, cpu: &mut CPU6502, action: StartAction) -> Result<(), String> {
+        for seg in &self.segments {
+            seg.segment.write_to(cpu);
+
+            if let Some(init) = seg.init {
+                cpu.call_subroutine(init, INIT_STEP_LIMIT)
+                    .map_err(|e| format!("INIT routine at ${:04x}: {}", init, e))?;
+            }
+        }
+
+        self.image().set_start(cpu, action);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_and_init() {
+        let xex = [
+            0xff, 0xff,
+            // init routine: LDA #$01, STA $0600, RTS
+            0x00, 0x20, 0x05, 0x20, 0xa9, 0x01, 0x8d, 0x00, 0x06, 0x60,
+            // INITAD
+            0xe2, 0x02, 0xe3, 0x02, 0x00, 0x20,
+            // main, after another $FFFF: LDA $0600, BRK
+            0xff, 0xff, 0x00, 0x30, 0x03, 0x30, 0xad, 0x00, 0x06, 0x00,
+            // RUNAD
+            0xe0, 0x02, 0xe1, 0x02, 0x00, 0x30,
+        ];
+
+        let parsed = parse(&xex).unwrap();
+        assert_eq!(parsed.segments.len(), 4);
+        assert_eq!(parsed.segments[1].init, Some(0x2000));
+        assert_eq!(parsed.segments[2].segment.addr, 0x3000);
+        assert_eq!(parsed.run, Some(0x3000));
+
+        let mut cpu = CPU6502::create_cpu_and_bus(0);
+        cpu.stk_ptr = 0xff;
+        parsed.load_into(&mut cpu, StartAction::SetPc).unwrap();
+        assert_eq!(cpu.read(0x0600), 1);
+        assert_eq!(cpu.pc, 0x3000);
+
+        assert!(parse(&xex[2..]).is_err());
+        assert!(parse(&xex[..9]).unwrap_err().contains("cut off"));
+    }
+}
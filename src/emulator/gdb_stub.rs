@@ -0,0 +1,460 @@
+use std::collections::BTreeSet;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+use crate::emulator::cpu::{Access, CPU6502, Flags, Limit, StopReason};
+
+/*
+*   GDB remote serial protocol stub
+*
+*   Registers are a, x, y, p, sp (8 bit) and pc (16 bit), the same order
+*   MAME's stub uses, and gdb is told about them with target.xml.
+*   Memory is the CPU's view of it (so cartridge ROM can't be written).
+*   Continuing stops at BRK, breakpoints (Z0/Z1), watchpoints (Z2-Z4),
+*   an instruction that jumps to itself, or Ctrl-C in gdb.
+*
+*   gdb-multiarch -ex "target remote :3333"
+*/
+
+const TARGET_XML : &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.mos6502.core">
+    <flags id="status" size="1">
+      <field name="C" start="0" end="0"/>
+      <field name="Z" start="1" end="1"/>
+      <field name="I" start="2" end="2"/>
+      <field name="D" start="3" end="3"/>
+      <field name="B" start="4" end="4"/>
+      <field name="V" start="6" end="6"/>
+      <field name="N" start="7" end="7"/>
+    </flags>
+    <reg name="a" bitsize="8" type="uint8"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="status"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// Instructions run between checks for Ctrl-C while continuing
+const POLL_STEPS : u64 = 10_000;
+
+const SIGINT : u8 = 2;
+const SIGILL : u8 = 4;
+const SIGTRAP : u8 = 5;
+
+pub struct GdbStub {
+    pub cpu: CPU6502,
+
+    // after QStartNoAckMode
+    no_ack: bool,
+
+    // read from the connection but not used yet
+    pending: Vec<u8>,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
+
+fn parse_u16(s: &str) -> Option<u16> {
+    u16::from_str_radix(s, 16).ok()
+}
+
+fn update(set: &mut BTreeSet<u16>, addr: u16, insert: bool) {
+    match insert {
+        true => set.insert(addr),
+        false => set.remove(&addr),
+    };
+}
+
+/// `addr,len` as used by m, M and Z
+fn addr_len(s: &str) -> Option<(u16, u16)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((parse_u16(addr)?, parse_u16(len)?))
+}
+
+impl GdbStub {
+    pub fn new(cpu: CPU6502) -> Self {
+        GdbStub {
+            cpu,
+            no_ack: false,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Talks to one gdb until it detaches, kills or disconnects
+    pub fn serve(&mut self, stream: &mut TcpStream) -> io::Result<()> {
+        self.no_ack = false;
+        self.pending.clear();
+        // acks and replies are tiny, don't wait to batch them
+        stream.set_nodelay(true)?;
+
+        while let Some(packet) = self.read_packet(stream)? {
+            let reply = match packet.as_bytes().first() {
+                Some(b'c') => self.resume(stream, &packet[1..], false)?,
+                Some(b's') => self.resume(stream, &packet[1..], true)?,
+                Some(b'k') => return Ok(()),
+                Some(b'D') => {
+                    self.send(stream, "OK")?;
+                    return Ok(());
+                }
+                // only one thread, so the first action is the one
+                _ if packet.starts_with("vCont;") => match packet.as_bytes().get(6) {
+                    Some(b'c') | Some(b'C') => self.resume(stream, "", false)?,
+                    Some(b's') | Some(b'S') => self.resume(stream, "", true)?,
+                    _ => String::new(),
+                },
+                _ => self.reply(&packet),
+            };
+            self.send(stream, &reply)?;
+        }
+        Ok(())
+    }
+
+    /// Reply to any packet that doesn't run the CPU, empty if unsupported
+    pub fn reply(&mut self, packet: &str) -> String {
+        let error = || "E01".to_string();
+        // packets are read lossily, anything else can't be one of ours
+        if !packet.is_ascii() {
+            return error();
+        }
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+
+        match cmd {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => to_hex(&self.registers()),
+            "G" => match from_hex(args) {
+                Some(regs) if regs.len() == 7 => {
+                    for (i, val) in regs.iter().enumerate().take(5) {
+                        self.set_register(i, *val as u16);
+                    }
+                    self.set_register(5, u16::from_le_bytes([regs[5], regs[6]]));
+                    "OK".to_string()
+                }
+                _ => error(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(5) => to_hex(&self.cpu.pc.to_le_bytes()),
+                Ok(n) if n < 5 => to_hex(&self.registers()[n..n + 1]),
+                _ => error(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, val)| {
+                    Some((usize::from_str_radix(n, 16).ok()?, from_hex(val)?))
+                });
+                match parsed {
+                    Some((n, val)) if n < 5 && val.len() == 1 => self.set_register(n, val[0] as u16),
+                    Some((5, val)) if val.len() == 2 => self.set_register(5, u16::from_le_bytes([val[0], val[1]])),
+                    _ => return error(),
+                }
+                "OK".to_string()
+            }
+            "m" => match addr_len(args) {
+                Some((addr, len)) => {
+                    let bytes: Vec<u8> = (0..len).map(|i| self.cpu.read(addr.wrapping_add(i))).collect();
+                    to_hex(&bytes)
+                }
+                None => error(),
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| Some((addr_len(range)?, from_hex(data)?)));
+                match parsed {
+                    Some(((addr, len), data)) if data.len() == len as usize => {
+                        for (i, byte) in data.iter().enumerate() {
+                            self.cpu.write(addr.wrapping_add(i as u16), *byte);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => error(),
+                }
+            }
+            "Z" | "z" => self.set_point(cmd == "Z", args),
+            // one thread, nothing to switch
+            "H" => "OK".to_string(),
+            _ => self.query(packet),
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (off, len) = match range.split_once(',') {
+                Some((off, len)) => (usize::from_str_radix(off, 16).unwrap_or(0), usize::from_str_radix(len, 16).unwrap_or(0)),
+                None => return "E01".to_string(),
+            };
+            let start = off.min(TARGET_XML.len());
+            let end = start.saturating_add(len).min(TARGET_XML.len());
+            let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+            return format!("{}{}", more, &TARGET_XML[start..end]);
+        }
+
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "vCont?" => "vCont;c;C;s;S".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// a x y p sp pc(lo hi)
+    fn registers(&self) -> [u8; 7] {
+        let pc = self.cpu.pc.to_le_bytes();
+        [self.cpu.reg_a, self.cpu.reg_x, self.cpu.reg_y, self.cpu.status.bits(), self.cpu.stk_ptr, pc[0], pc[1]]
+    }
+
+    fn set_register(&mut self, n: usize, val: u16) {
+        match n {
+            0 => self.cpu.reg_a = val as u8,
+            1 => self.cpu.reg_x = val as u8,
+            2 => self.cpu.reg_y = val as u8,
+            3 => self.cpu.status = Flags::from_bits_truncate(val as u8) | Flags::U,
+            4 => self.cpu.stk_ptr = val as u8,
+            _ => self.cpu.pc = val,
+        }
+    }
+
+    /// Z/z type,addr,kind, kind is the length for watchpoints
+    fn set_point(&mut self, insert: bool, args: &str) -> String {
+        let mut parts = args.splitn(3, ',');
+        let kind = parts.next();
+        let (addr, len) = match (parts.next().and_then(parse_u16), parts.next().and_then(parse_u16)) {
+            (Some(addr), Some(len)) => (addr, len.max(1)),
+            _ => return "E01".to_string(),
+        };
+
+        let cpu = &mut self.cpu;
+        match kind {
+            Some("0") | Some("1") => update(&mut cpu.breakpoints, addr, insert),
+            Some(kind @ ("2" | "3" | "4")) => {
+                for addr in (0..len).map(|i| addr.wrapping_add(i)) {
+                    if kind != "3" {
+                        update(&mut cpu.write_watchpoints, addr, insert);
+                    }
+                    if kind != "2" {
+                        update(&mut cpu.read_watchpoints, addr, insert);
+                    }
+                }
+            }
+            _ => return String::new(),
+        }
+        "OK".to_string()
+    }
+
+    /// c and s, with an optional address to resume from
+    fn resume(&mut self, stream: &mut TcpStream, addr: &str, step: bool) -> io::Result<String> {
+        if let Some(addr) = parse_u16(addr) {
+            self.cpu.pc = addr;
+        }
+        let trap = format!("S{:02x}", SIGTRAP);
+
+        if step {
+            self.cpu.execute_step();
+            return Ok(self.watch_reply().unwrap_or(trap));
+        }
+
+        // a chunk never stops short at a breakpoint, so the next one
+        // starting there is only ever continuing from it
+        loop {
+            match self.cpu.run(Limit::Instructions(POLL_STEPS)) {
+                StopReason::BudgetExhausted => (),
+                StopReason::Breakpoint(_) => return Ok(format!("T{:02x}swbreak:;", SIGTRAP)),
+                StopReason::Watchpoint(..) => return Ok(self.watch_reply().unwrap_or(trap)),
+                StopReason::Jam(_) | StopReason::IllegalOpcode(..) => return Ok(format!("S{:02x}", SIGILL)),
+                _ => return Ok(trap),
+            }
+
+            if self.interrupted(stream)? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+        }
+    }
+
+    fn watch_reply(&self) -> Option<String> {
+        let (addr, access) = self.cpu.watch_hit()?;
+        let kind = match access {
+            Access::Write => "watch",
+            // Z4 sets both
+            Access::Read if self.cpu.write_watchpoints.contains(&addr) => "awatch",
+            Access::Read => "rwatch",
+        };
+        Some(format!("T{:02x}{}:{:04x};", SIGTRAP, kind, addr))
+    }
+
+    /// Checks for Ctrl-C (0x03) without waiting
+    fn interrupted(&mut self, stream: &mut TcpStream) -> io::Result<bool> {
+        let mut buf = [0; 64];
+        stream.set_nonblocking(true)?;
+        let read = stream.read(&mut buf);
+        stream.set_nonblocking(false)?;
+
+        match read {
+            Ok(0) => Err(io::Error::new(ErrorKind::ConnectionAborted, "gdb disconnected")),
+            Ok(n) => {
+                self.pending.extend(buf[..n].iter().filter(|x| **x != 0x03));
+                Ok(buf[..n].contains(&0x03))
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn next_byte(&mut self, stream: &mut TcpStream) -> io::Result<Option<u8>> {
+        if self.pending.is_empty() {
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf)?;
+            self.pending.extend_from_slice(&buf[..n]);
+        }
+        match self.pending.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(self.pending.remove(0))),
+        }
+    }
+
+    /// Next `$data#cs` packet, None once gdb disconnects.
+    /// Acks, and Ctrl-C while stopped, are skipped
+    fn read_packet(&mut self, stream: &mut TcpStream) -> io::Result<Option<String>> {
+        let eof = || io::Error::new(ErrorKind::UnexpectedEof, "packet cut off");
+
+        while let Some(byte) = self.next_byte(stream)? {
+            if byte != b'$' {
+                continue;
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.next_byte(stream)?.ok_or_else(eof)? {
+                    b'#' => break,
+                    b => data.push(b),
+                }
+            }
+            let checksum = [self.next_byte(stream)?.ok_or_else(eof)?, self.next_byte(stream)?.ok_or_else(eof)?];
+            let valid = std::str::from_utf8(&checksum).ok().and_then(|x| u8::from_str_radix(x, 16).ok())
+                == Some(data.iter().fold(0u8, |sum, x| sum.wrapping_add(*x)));
+
+            if !self.no_ack {
+                stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn send(&mut self, stream: &mut TcpStream, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, x| sum.wrapping_add(x));
+        let packet = format!("${}#{:02x}", data, checksum);
+
+        loop {
+            stream.write_all(packet.as_bytes())?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match self.next_byte(stream)? {
+                Some(b'-') => continue,
+                Some(b'+') | None => return Ok(()),
+                // gdb didn't ack, keep whatever it sent
+                Some(byte) => {
+                    self.pending.insert(0, byte);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Sends a packet and returns the reply, like gdb would
+    fn request(stream: &mut TcpStream, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, x| sum.wrapping_add(x));
+        stream.write_all(format!("${}#{:02x}", data, checksum).as_bytes()).unwrap();
+
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'+' if reply.is_empty() => (),
+                b'#' => break,
+                b => reply.push(b),
+            }
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        stream.write_all(b"+").unwrap();
+
+        String::from_utf8(reply[1..].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn scripted_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = thread::spawn(move || {
+            let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+            // INX, INX, STA $10, JMP $8000
+            for (i, byte) in [0xe8, 0xe8, 0x85, 0x10, 0x4c, 0x00, 0x80].iter().enumerate() {
+                cpu.write(0x8000 + i as u16, *byte);
+            }
+            let mut stub = GdbStub::new(cpu);
+            let (mut stream, _) = listener.accept().unwrap();
+            stub.serve(&mut stream).unwrap();
+            stub.cpu.reg_x
+        });
+
+        let mut gdb = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        gdb.set_nodelay(true).unwrap();
+        assert!(request(&mut gdb, "qSupported:swbreak+").contains("qXfer:features:read+"));
+        assert!(request(&mut gdb, "qXfer:features:read:target.xml:0,fff").starts_with("l<?xml"));
+        assert!(request(&mut gdb, "qXfer:features:read:target.xml:10,ffffffffffffffff").starts_with('l'));
+        assert_eq!(request(&mut gdb, "\u{ff}"), "E01");
+        assert_eq!(request(&mut gdb, "?"), "S05");
+        assert_eq!(request(&mut gdb, "g"), "00000020ff0080");
+
+        assert_eq!(request(&mut gdb, "P0=42"), "OK");
+        assert_eq!(request(&mut gdb, "p0"), "42");
+        assert_eq!(request(&mut gdb, "M0200,3:010203"), "OK");
+        assert_eq!(request(&mut gdb, "m0200,3"), "010203");
+
+        assert_eq!(request(&mut gdb, "s"), "S05");
+        assert_eq!(request(&mut gdb, "p5"), "0180");
+
+        assert_eq!(request(&mut gdb, "Z0,8004,1"), "OK");
+        assert_eq!(request(&mut gdb, "c"), "T05swbreak:;");
+        assert_eq!(request(&mut gdb, "p5"), "0480");
+        assert_eq!(request(&mut gdb, "z0,8004,1"), "OK");
+
+        assert_eq!(request(&mut gdb, "Z2,0010,1"), "OK");
+        assert_eq!(request(&mut gdb, "vCont;c:1"), "T05watch:0010;");
+        assert_eq!(request(&mut gdb, "m0010,1"), "42");
+
+        gdb.write_all(b"$k#6b").unwrap();
+        assert_eq!(server.join().unwrap(), 4);
+    }
+}
@@ -0,0 +1,86 @@
+/*
+*   Recent memory activity, set cpu.heatmap to Some(Heatmap::default())
+*
+*   Every address has a read, write and execute level that an access sets
+*   to full and decay() brings back down, so callers pick how fast it fades
+*   (the GUI decays once a frame).
+*/
+
+pub struct Heatmap {
+    reads: Vec<u8>,
+    writes: Vec<u8>,
+    executes: Vec<u8>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap {
+            reads: vec![0; 0x10000],
+            writes: vec![0; 0x10000],
+            executes: vec![0; 0x10000],
+        }
+    }
+}
+
+impl Heatmap {
+    pub fn read(&mut self, addr: u16) {
+        self.reads[addr as usize] = u8::MAX;
+    }
+
+    pub fn write(&mut self, addr: u16) {
+        self.writes[addr as usize] = u8::MAX;
+    }
+
+    pub fn execute(&mut self, addr: u16) {
+        self.executes[addr as usize] = u8::MAX;
+    }
+
+    /// Fades everything by amount
+    pub fn decay(&mut self, amount: u8) {
+        for levels in [&mut self.reads, &mut self.writes, &mut self.executes] {
+            for level in levels.iter_mut() {
+                *level = level.saturating_sub(amount);
+            }
+        }
+    }
+
+    /// (read, write, execute) levels
+    pub fn levels(&self, addr: u16) -> (u8, u8, u8) {
+        let i = addr as usize;
+        (self.reads[i], self.writes[i], self.executes[i])
+    }
+
+    /// RGB per address, writes red, reads green and executes blue
+    pub fn rgb(&self) -> impl Iterator<Item = [u8; 3]> + '_ {
+        (0..0x10000).map(move |i| [self.writes[i], self.reads[i], self.executes[i]])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::emulator::cpu::{CPU6502, Limit, StopReason};
+    use super::*;
+
+    #[test]
+    fn accesses_and_decay() {
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+
+        // LDA $10, STA $0200, BRK
+        for (i, byte) in [0xa5, 0x10, 0x8d, 0x00, 0x02, 0x00].iter().enumerate() {
+            cpu.write(0x8000 + i as u16, *byte);
+        }
+        cpu.heatmap = Some(Heatmap::default());
+        assert_eq!(cpu.run(Limit::Instructions(100)), StopReason::Brk(0x8005));
+
+        let mut heat = cpu.heatmap.take().unwrap();
+        assert_eq!(heat.levels(0x10), (255, 0, 0));
+        assert_eq!(heat.levels(0x0200), (0, 255, 0));
+        assert_eq!(heat.levels(0x8004), (0, 0, 255));
+        assert_eq!(heat.levels(0x8005), (0, 0, 0));
+
+        heat.decay(200);
+        heat.decay(100);
+        assert_eq!(heat.levels(0x10), (0, 0, 0));
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 // use crate::cpu;
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum AddressingMode {
     IMM,    // Immediate
     REL,    // Relative
@@ -144,8 +144,8 @@ lazy_static!{
         Instruction::new("INC", 0xEE, AddressingMode::ABS, 3, 6),
         Instruction::new("INC", 0xFE, AddressingMode::ABX, 3, 7),
         
-        Instruction::new("IDX", 0xE8, AddressingMode::IMP, 1, 2),
-        Instruction::new("IDY", 0xC8, AddressingMode::IMP, 1, 2),
+        Instruction::new("INX", 0xE8, AddressingMode::IMP, 1, 2),
+        Instruction::new("INY", 0xC8, AddressingMode::IMP, 1, 2),
 
         Instruction::new("JMP", 0x4C, AddressingMode::ABS, 3, 3),
         Instruction::new("JMP", 0x6C, AddressingMode::IND, 3, 5),
@@ -269,3 +269,10 @@ lazy_static!{
     
 
 }
+
+/// Finds an official instruction by name and addressing mode
+///
+/// name must be all caps
+pub fn find_instruction(name : &str, mode : AddressingMode) -> Option<&'static Instruction> {
+    CPU_INSTRUCTIONS.iter().find(|x| x.name == name && x.mode == mode && x.name != "IDK")
+}
@@ -0,0 +1,193 @@
+use crate::emulator::symbols::SymbolTable;
+
+/*
+*   Label files from other emulators
+*
+*   VICE        `al C:080d .start` (monitor `ll`/`sl` commands)
+*   Mesen       `.mlb`, `R:0010:ptr`, `P:0123:reset` with a memory type
+*               prefix, R = internal RAM, W/S = work/save RAM at $6000,
+*               P = PRG ROM offset (NROM, so the ROM ends at $FFFF and
+*               16K is at $C000, it's mirrored at $8000 too),
+*               G = register (CPU address). Mesen 2's long names work too.
+*   FCEUX       `.nl`, `$C000#reset#comment`, `$0300/10#buffer#`
+*/
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LabelFormat {
+    Vice,
+    Mesen,
+    Fceux,
+}
+
+impl LabelFormat {
+    pub const ALL : [LabelFormat; 3] = [LabelFormat::Vice, LabelFormat::Mesen, LabelFormat::Fceux];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LabelFormat::Vice => "VICE",
+            LabelFormat::Mesen => "Mesen",
+            LabelFormat::Fceux => "FCEUX",
+        }
+    }
+
+    /// From a file's extension (.lbl/.vs, .mlb, .nl)
+    pub fn from_extension(name: &str) -> Option<LabelFormat> {
+        match &name.rsplit('.').next()?.to_ascii_lowercase()[..] {
+            "lbl" | "vs" | "labels" => Some(LabelFormat::Vice),
+            "mlb" => Some(LabelFormat::Mesen),
+            "nl" => Some(LabelFormat::Fceux),
+            _ => None,
+        }
+    }
+}
+
+fn hex(s: &str, n: usize) -> Result<u32, String> {
+    u32::from_str_radix(s.trim().trim_start_matches('$'), 16)
+        .map_err(|_| format!("line {}: '{}' isn't a hex address", n + 1, s))
+}
+
+fn cpu_addr(addr: u32, n: usize) -> Result<u16, String> {
+    match addr <= 0xFFFF {
+        true => Ok(addr as u16),
+        false => Err(format!("line {}: ${:x} is outside the CPU's address space", n + 1, addr)),
+    }
+}
+
+// PRG ROM size without a cartridge
+const DEFAULT_PRG_SIZE : usize = 0x8000;
+
+/// Mesen memory type + offset -> CPU address, None for types without one (CHR...)
+fn mesen_addr(kind: &str, offset: u32, prg_size: usize) -> Option<u32> {
+    match kind {
+        "R" | "NesInternalRam" => Some(offset),
+        "G" | "NesMemory" | "Register" => Some(offset),
+        "W" | "S" | "NesWorkRam" | "NesSaveRam" => Some(0x6000 + offset),
+        "P" | "NesPrgRom" => Some(0x10000 - prg_size as u32 + offset),
+        _ => None,
+    }
+}
+
+/// Labels in a file, prg_size is the loaded cartridge's PRG ROM size
+/// (Mesen's PRG offsets depend on it, 32K is assumed without one)
+pub fn parse(format: LabelFormat, text: &str, prg_size: Option<usize>) -> Result<SymbolTable, String> {
+    let prg_size = prg_size.unwrap_or(DEFAULT_PRG_SIZE);
+
+    let mut table = SymbolTable::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        match format {
+            LabelFormat::Vice => {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                match parts[..] {
+                    ["al", addr, name, ..] => {
+                        // memspace prefix is optional
+                        let addr = addr.rsplit(':').next().unwrap_or(addr);
+                        table.insert(name.trim_start_matches('.'), cpu_addr(hex(addr, n)?, n)?);
+                    }
+                    _ => return Err(format!("line {}: expected 'al <addr> .<name>'", n + 1)),
+                }
+            }
+
+            LabelFormat::Mesen => {
+                let parts: Vec<&str> = line.splitn(4, ':').collect();
+                if parts.len() < 3 {
+                    return Err(format!("line {}: expected 'type:addr:name'", n + 1));
+                }
+                // comments only
+                if parts[2].is_empty() {
+                    continue;
+                }
+                let start = parts[1].split('-').next().unwrap_or("");
+                if let Some(addr) = mesen_addr(parts[0], hex(start, n)?, prg_size) {
+                    table.insert(parts[2], cpu_addr(addr, n)?);
+                }
+            }
+
+            LabelFormat::Fceux => {
+                let parts: Vec<&str> = line.splitn(3, '#').collect();
+                if parts.len() < 2 || !parts[0].starts_with('$') {
+                    return Err(format!("line {}: expected '$addr#name#'", n + 1));
+                }
+                let addr = parts[0].split('/').next().unwrap_or("");
+                if !parts[1].is_empty() {
+                    table.insert(parts[1], cpu_addr(hex(addr, n)?, n)?);
+                }
+            }
+        }
+    }
+
+    Ok(table)
+}
+
+/// Label file for symbols, prg_size like parse()
+pub fn write(format: LabelFormat, symbols: &SymbolTable, prg_size: Option<usize>) -> String {
+    let prg_size = prg_size.unwrap_or(DEFAULT_PRG_SIZE);
+    let mut ret = String::new();
+
+    for (name, addr) in symbols.iter() {
+        let line = match format {
+            LabelFormat::Vice => format!("al C:{:04x} .{}", addr, name),
+            LabelFormat::Mesen => match addr {
+                0x0000..=0x07FF => format!("R:{:04X}:{}", addr, name),
+                0x6000..=0x7FFF => format!("W:{:04X}:{}", addr - 0x6000, name),
+                0x8000..=0xFFFF => format!("P:{:04X}:{}", (addr as usize - 0x8000) % prg_size, name),
+                _ => format!("G:{:04X}:{}", addr, name),
+            },
+            LabelFormat::Fceux => format!("${:04X}#{}#", addr, name),
+        };
+        ret.push_str(&line);
+        ret.push('\n');
+    }
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_each_format() {
+        let vice = parse(LabelFormat::Vice, "al C:080d .start\nal 00fb .ptr\n", None).unwrap();
+        assert_eq!(vice.addr_of("start"), Some(0x080d));
+        assert_eq!(vice.addr_of("ptr"), Some(0x00fb));
+
+        let mesen_text = "R:0010-0011:ptr\nP:0000:reset:entry\nS:0000:save\nP:0123::comment only\nC:0000:tiles\n";
+        let mesen = parse(LabelFormat::Mesen, mesen_text, None).unwrap();
+        assert_eq!(mesen.addr_of("ptr"), Some(0x0010));
+        assert_eq!(mesen.addr_of("reset"), Some(0x8000));
+        assert_eq!(mesen.addr_of("save"), Some(0x6000));
+        assert_eq!(mesen.len(), 3);
+        // 16K NROM is at $C000
+        assert_eq!(parse(LabelFormat::Mesen, mesen_text, Some(0x4000)).unwrap().addr_of("reset"), Some(0xc000));
+
+        let fceux = parse(LabelFormat::Fceux, "$C000#reset#starts here\n$0300/10#buffer#\n", None).unwrap();
+        assert_eq!(fceux.addr_of("reset"), Some(0xc000));
+        assert_eq!(fceux.addr_of("buffer"), Some(0x0300));
+
+        assert!(parse(LabelFormat::Vice, "al C:1234567 .x", None).is_err());
+        assert!(parse(LabelFormat::Fceux, "C000 reset", None).is_err());
+    }
+
+    #[test]
+    fn write_round_trips() {
+        let symbols: SymbolTable = vec![("ptr", 0x10), ("sram", 0x6002), ("ppuctrl", 0x2000), ("reset", 0xc000)]
+            .into_iter().collect();
+
+        for format in LabelFormat::ALL.iter() {
+            for prg_size in [None, Some(0x4000)] {
+                let back = parse(*format, &write(*format, &symbols, prg_size), prg_size).unwrap();
+                for (name, addr) in symbols.iter() {
+                    assert_eq!(back.addr_of(name), Some(addr), "{}", format.name());
+                }
+            }
+        }
+        assert_eq!(write(LabelFormat::Vice, &symbols, None).lines().next(), Some("al C:0010 .ptr"));
+        assert!(write(LabelFormat::Mesen, &symbols, Some(0x4000)).contains("P:0000:reset\n"));
+    }
+}
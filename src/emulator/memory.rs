@@ -1,5 +1,3 @@
-use std::usize;
-
 const CPU_RAM_SIZE : usize = 64*1024; // 64 KB
 
 
@@ -77,10 +75,7 @@ impl Memory for CPU_RAM{
     fn load(&mut self, program : Vec<u8>, start_addr : Option<u16>) -> Result<(), &'static str> {
         // memory for program ROM should be btwn 0x8000 and 0xFFFF
 
-        let start_addr = match start_addr{
-            Some(x) => x,
-            None => 0x8000,
-        };
+        let start_addr = start_addr.unwrap_or(0x8000);
 
         if program.len() > 0xFFFC - start_addr as usize{
             return Err("Program len too large")
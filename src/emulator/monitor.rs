@@ -0,0 +1,643 @@
+use crate::emulator::assembler::assemble;
+use crate::emulator::cpu::{Access, CPU6502, Flags, Limit, StopReason};
+use crate::emulator::debug_info::{self, DebugInfo};
+use crate::emulator::disassembler::{decode, trace_line, FormatOptions};
+use crate::emulator::formats::{self, ihex, srec, LoadImage, StartAction};
+use crate::emulator::label_files::{self, LabelFormat};
+use crate::emulator::symbols::SymbolTable;
+
+/*
+*   VICE style machine language monitor
+*
+*   Text in, text out so it can sit behind a terminal (mon6502) or
+*   anything else. Numbers are hex, addresses can also be symbols,
+*   `.name` when the name could be read as hex (`.beef`).
+*/
+
+const HELP : &str = "m [from [to]]            memory dump, carries on from the last one
+d [from [to]]            disassemble, carries on from the last one
+a addr [instruction]     assemble, without an instruction (or after one) keeps
+                         asking for lines until an empty one
+r [reg=val ...]          show/set registers: a x y sp p pc
+g [addr]                 go until BRK, a breakpoint, a watchpoint or a trap
+z [count]                step into
+n [count]                step over JSRs
+ret                      step out, run until the current subroutine returns
+break [loc ...]          list/add breakpoints (loc can be file:line after ll of a .dbg)
+watch [load|store] from [to]
+                         stop after data is read (load) and/or written (store)
+del [loc ...]            delete breakpoints/watchpoints, all of them without loc
+f from to bytes...       fill with a repeating pattern
+h from to bytes...       hunt for bytes, xx matches anything
+t from to dest           transfer (copy) memory
+l file [addr]            load like Load File, raw binaries go to addr ($8000) and
+                         the PC is set to the program's start
+s file from to           save, .hex/.ihx Intel HEX, .s19/.srec S-records,
+                         .prg with a load address, anything else raw
+ll file                  load labels (VICE/Mesen/FCEUX or cc65 .dbg)
+al addr name             add a label
+shl                      show labels
+x                        exit
+An empty line repeats m, d, z or n. Counts are decimal, everything else hex.";
+
+/// Cycles `g` runs for (instructions for `n` and `ret`) before giving
+/// up, so a program stuck in a loop doesn't hang the monitor
+pub const RUN_LIMIT : u64 = 10_000_000;
+
+// m without an end address
+const MEM_BYTES : u16 = 0x80;
+const DIS_LINES : usize = 16;
+
+pub struct Monitor {
+    pub cpu: CPU6502,
+    pub symbols: SymbolTable,
+    debug: Option<DebugInfo>,
+
+    // where m and d carry on from
+    mem_next: u16,
+    dis_next: u16,
+
+    // Some while assembling line by line
+    asm_next: Option<u16>,
+
+    // command an empty line repeats
+    last: Option<String>,
+}
+
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if !quoted && (c.is_whitespace() || c == ',') => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if quoted {
+        return Err("missing closing \"".to_string());
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    u8::from_str_radix(s.trim_start_matches('$'), 16).map_err(|_| format!("'{}' isn't a hex byte", s))
+}
+
+fn parse_count(s: Option<&String>) -> Result<u32, String> {
+    match s {
+        Some(s) => s.parse().map_err(|_| format!("'{}' isn't a count", s)),
+        None => Ok(1),
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Monitor {
+    pub fn new(cpu: CPU6502) -> Self {
+        let pc = cpu.pc;
+        Monitor {
+            cpu,
+            symbols: SymbolTable::new(),
+            debug: None,
+            mem_next: 0,
+            dis_next: pc,
+            asm_next: None,
+            last: None,
+        }
+    }
+
+    /// `(C:$c000) `, or `.c000 ` while assembling
+    pub fn prompt(&self) -> String {
+        match self.asm_next {
+            Some(addr) => format!(".{:04x} ", addr),
+            None => format!("(C:${:04x}) ", self.cpu.pc),
+        }
+    }
+
+    /// Runs one line, returning what to print
+    pub fn command(&mut self, line: &str) -> Result<String, String> {
+        if let Some(addr) = self.asm_next {
+            if line.trim().is_empty() {
+                self.asm_next = None;
+                return Ok(String::new());
+            }
+            return self.assemble_at(addr, line);
+        }
+
+        let line = match (line.trim(), &self.last) {
+            ("", Some(last)) => last.clone(),
+            ("", None) => return Ok(String::new()),
+            (line, _) => line.to_string(),
+        };
+        let tokens = tokenize(&line)?;
+        let (cmd, args) = match tokens.split_first() {
+            Some((cmd, args)) => (cmd.to_ascii_lowercase(), args),
+            None => return Ok(String::new()),
+        };
+
+        self.last = match &cmd[..] {
+            "m" | "d" | "z" | "n" => Some(cmd.clone()),
+            _ => None,
+        };
+
+        match &cmd[..] {
+            "m" => self.memory(args),
+            "d" => self.disassemble(args),
+            "a" => {
+                let addr = self.addr(args.first().ok_or("a needs an address")?)?;
+                // rest of the line, untokenized
+                let instr = line.trim_start()[1..].trim_start()
+                    .split_once(char::is_whitespace).map(|x| x.1.trim()).unwrap_or("");
+                match instr.is_empty() {
+                    true => {
+                        self.asm_next = Some(addr);
+                        Ok(String::new())
+                    }
+                    false => self.assemble_at(addr, instr),
+                }
+            }
+            "r" => self.registers(args),
+            "g" => {
+                if let Some(addr) = args.first() {
+                    self.cpu.pc = self.addr(addr)?;
+                }
+                let stop = self.cpu.run(Limit::Cycles(RUN_LIMIT));
+                Ok(self.stopped(stop))
+            }
+            "z" => self.step(args, false),
+            "n" => self.step(args, true),
+            "ret" => {
+                let stop = self.cpu.step_out(RUN_LIMIT).err().unwrap_or(StopReason::Done);
+                self.dis_next = self.cpu.pc;
+                Ok(self.stopped(stop))
+            }
+            "break" | "bk" => self.add_breakpoints(args),
+            "watch" | "w" => self.watch(args),
+            "del" | "delete" => self.delete(args),
+            "f" => self.fill(args),
+            "h" => self.hunt(args),
+            "t" => self.transfer(args),
+            "l" => self.load(args),
+            "s" => self.save(args),
+            "ll" => self.load_labels(args),
+            "al" => {
+                match args {
+                    [addr, name] => {
+                        let addr = self.addr(addr)?;
+                        self.symbols.insert(name.trim_start_matches('.'), addr);
+                        Ok(String::new())
+                    }
+                    _ => Err("use al addr name".to_string()),
+                }
+            }
+            "shl" => {
+                let mut labels: Vec<(&str, u16)> = self.symbols.iter().collect();
+                labels.sort_by_key(|x| x.1);
+                Ok(labels.iter().map(|(name, addr)| format!("${:04x} .{}", addr, name)).collect::<Vec<_>>().join("\n"))
+            }
+            "help" | "?" => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command '{}', help lists them", cmd)),
+        }
+    }
+
+    /// Hex address, `$`/`0x` hex, or a symbol
+    fn addr(&self, s: &str) -> Result<u16, String> {
+        if let Some(name) = s.strip_prefix('.') {
+            return self.symbols.addr_of(name).ok_or_else(|| format!("unknown symbol '{}'", name));
+        }
+        let hex = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+        u16::from_str_radix(hex, 16).ok()
+            .or_else(|| self.symbols.addr_of(s))
+            .ok_or_else(|| format!("'{}' isn't an address or symbol", s))
+    }
+
+    /// Like addr, but file:line can give more than one
+    fn locations(&self, s: &str) -> Result<Vec<u16>, String> {
+        match (s.contains(':'), &self.debug) {
+            (true, Some(debug)) => debug_info::resolve_location(s, &self.symbols, Some(debug)),
+            _ => Ok(vec![self.addr(s)?]),
+        }
+    }
+
+    fn range(&self, from: &str, to: &str) -> Result<(u16, u16), String> {
+        let (from, to) = (self.addr(from)?, self.addr(to)?);
+        match from <= to {
+            true => Ok((from, to)),
+            false => Err("range ends before it starts".to_string()),
+        }
+    }
+
+    fn describe(&self, addr: u16) -> String {
+        match self.symbols.name_of(addr) {
+            Some(name) => format!("${:04x} (.{})", addr, name),
+            None => format!("${:04x}", addr),
+        }
+    }
+
+    fn registers_line(&self) -> String {
+        format!("  ADDR A  X  Y  SP NV-BDIZC CYCLES\n.;{:04x} {:02x} {:02x} {:02x} {:02x} {:08b} {}",
+            self.cpu.pc, self.cpu.reg_a, self.cpu.reg_x, self.cpu.reg_y, self.cpu.stk_ptr,
+            self.cpu.status.bits(), self.cpu.get_total_cycles())
+    }
+
+    fn memory(&mut self, args: &[String]) -> Result<String, String> {
+        let from = match args.first() {
+            Some(x) => self.addr(x)?,
+            None => self.mem_next,
+        };
+        let to = match args.get(1) {
+            Some(x) => self.range(&args[0], x)?.1,
+            None => from.saturating_add(MEM_BYTES - 1),
+        };
+
+        let mut lines = Vec::new();
+        for row in (from as u32..=to as u32).step_by(16) {
+            let bytes: Vec<u8> = (row..=(row + 15).min(to as u32)).map(|x| self.cpu.read(x as u16)).collect();
+            let hex: Vec<String> = bytes.iter().map(|x| format!("{:02x}", x)).collect();
+            let text: String = bytes.iter()
+                .map(|x| match x {
+                    0x20..=0x7e => *x as char,
+                    _ => '.',
+                })
+                .collect();
+            lines.push(format!(">C:{:04x}  {:<48} {}", row, hex.join(" "), text));
+        }
+
+        self.mem_next = to.wrapping_add(1);
+        Ok(lines.join("\n"))
+    }
+
+    /// One disassembled line, `>` marks the PC
+    fn disassembly_line(&self, addr: u16) -> (String, u16) {
+        let instr = decode(&self.cpu, addr);
+        let bytes: Vec<String> = instr.raw().iter().map(|x| format!("{:02x}", x)).collect();
+        let marker = if addr == self.cpu.pc { '>' } else { '.' };
+
+        let mut s = String::new();
+        if let Some(name) = self.symbols.name_of(addr) {
+            s.push_str(&format!(".{}:\n", name));
+        }
+        s.push_str(&format!("{}C:{:04x}  {:<9} {}", marker, addr, bytes.join(" "),
+            instr.format_with(&FormatOptions::default(), Some(&self.symbols))));
+        (s, instr.len as u16)
+    }
+
+    fn disassemble(&mut self, args: &[String]) -> Result<String, String> {
+        let from = match args.first() {
+            Some(x) => self.addr(x)?,
+            None => self.dis_next,
+        };
+        let to = match args.get(1) {
+            Some(x) => Some(self.range(&args[0], x)?.1),
+            None => None,
+        };
+
+        let mut lines = Vec::new();
+        let mut addr = from as u32;
+        while addr <= 0xFFFF && to.map(|to| addr <= to as u32).unwrap_or(lines.len() < DIS_LINES) {
+            let (line, len) = self.disassembly_line(addr as u16);
+            lines.push(line);
+            addr += len as u32;
+        }
+
+        self.dis_next = addr as u16;
+        Ok(lines.join("\n"))
+    }
+
+    /// Assembles a line at addr, staying in assembly mode for the next one
+    fn assemble_at(&mut self, addr: u16, text: &str) -> Result<String, String> {
+        // symbols the assembler can read become constants
+        let mut source = String::new();
+        for (name, value) in self.symbols.iter().filter(|x| is_identifier(x.0)) {
+            source.push_str(&format!("{} = ${:04x}\n", name, value));
+        }
+        source.push_str(text);
+
+        let asm = assemble(&source, addr).map_err(|errors| {
+            errors.into_iter().map(|x| x.message).collect::<Vec<_>>().join(", ")
+        })?;
+        for (start, bytes) in &asm.chunks {
+            for (i, byte) in bytes.iter().enumerate() {
+                self.cpu.write(start.wrapping_add(i as u16), *byte);
+            }
+        }
+
+        let next = asm.chunks.last().map(|(start, bytes)| start.wrapping_add(bytes.len() as u16)).unwrap_or(addr);
+        self.asm_next = Some(next);
+        self.dis_next = next;
+        Ok(String::new())
+    }
+
+    fn registers(&mut self, args: &[String]) -> Result<String, String> {
+        // `a = 01` and `a=01` are both fine
+        let text = args.join(" ").replace(" =", "=").replace("= ", "=");
+        for assignment in text.split_whitespace() {
+            let (reg, val) = assignment.split_once('=')
+                .ok_or_else(|| format!("'{}' should look like a=01", assignment))?;
+            match &reg.to_ascii_lowercase()[..] {
+                "a" => self.cpu.reg_a = parse_byte(val)?,
+                "x" => self.cpu.reg_x = parse_byte(val)?,
+                "y" => self.cpu.reg_y = parse_byte(val)?,
+                "sp" => self.cpu.stk_ptr = parse_byte(val)?,
+                "p" => self.cpu.status = Flags::from_bits_truncate(parse_byte(val)?) | Flags::U,
+                "pc" => self.cpu.pc = self.addr(val)?,
+                _ => return Err(format!("unknown register '{}'", reg)),
+            }
+        }
+        Ok(self.registers_line())
+    }
+
+    fn stopped(&self, stop: StopReason) -> String {
+        let pc = self.describe(self.cpu.pc);
+        let reason = match stop {
+            StopReason::Done => String::new(),
+            StopReason::Brk(_) => format!("BRK at {}\n", pc),
+            StopReason::Breakpoint(_) => format!("breakpoint at {}\n", pc),
+            StopReason::Watchpoint(addr, Access::Read) => format!("watchpoint, load from {}\n", self.describe(addr)),
+            StopReason::Watchpoint(addr, Access::Write) => format!("watchpoint, store to {}\n", self.describe(addr)),
+            StopReason::Jam(_) => format!("jammed at {}\n", pc),
+            StopReason::IllegalOpcode(_, op) => format!("illegal opcode {:02X} at {}\n", op, pc),
+            StopReason::Trap(_) => format!("trapped at {}, it jumps to itself\n", pc),
+            StopReason::BudgetExhausted => "still running, g carries on\n".to_string(),
+        };
+        format!("{}{}\n{}", reason, self.registers_line(), self.disassembly_line(self.cpu.pc).0)
+    }
+
+    /// z and n, over steps JSRs by running until they return
+    fn step(&mut self, args: &[String], over: bool) -> Result<String, String> {
+        let mut lines = Vec::new();
+
+        for _ in 0..parse_count(args.first())? {
+            let result = match over {
+                true => self.cpu.step_over(RUN_LIMIT),
+                false => self.cpu.step_instruction(),
+            };
+
+            if let Err(stop) = result {
+                lines.push(self.stopped(stop));
+                break;
+            }
+            lines.push(trace_line(&self.cpu, Some(&self.symbols)));
+        }
+
+        self.dis_next = self.cpu.pc;
+        Ok(lines.join("\n"))
+    }
+
+    fn add_breakpoints(&mut self, args: &[String]) -> Result<String, String> {
+        for spec in args {
+            let addrs = self.locations(spec)?;
+            self.cpu.breakpoints.extend(addrs);
+        }
+
+        let mut lines: Vec<String> = self.cpu.breakpoints.iter().map(|x| format!("BREAK: {}", self.describe(*x))).collect();
+        lines.extend(self.cpu.read_watchpoints.iter().map(|x| format!("WATCH load: {}", self.describe(*x))));
+        lines.extend(self.cpu.write_watchpoints.iter().map(|x| format!("WATCH store: {}", self.describe(*x))));
+        Ok(lines.join("\n"))
+    }
+
+    fn watch(&mut self, args: &[String]) -> Result<String, String> {
+        let (load, store, args) = match args.first().map(|x| x.to_ascii_lowercase()).as_deref() {
+            Some("load") => (true, false, &args[1..]),
+            Some("store") => (false, true, &args[1..]),
+            _ => (true, true, args),
+        };
+        let (from, to) = match args {
+            [from] => (self.addr(from)?, self.addr(from)?),
+            [from, to] => self.range(from, to)?,
+            _ => return Err("use watch [load|store] from [to]".to_string()),
+        };
+
+        for addr in from..=to {
+            if load {
+                self.cpu.read_watchpoints.insert(addr);
+            }
+            if store {
+                self.cpu.write_watchpoints.insert(addr);
+            }
+        }
+        self.add_breakpoints(&[])
+    }
+
+    fn delete(&mut self, args: &[String]) -> Result<String, String> {
+        if args.is_empty() {
+            self.cpu.breakpoints.clear();
+            self.cpu.read_watchpoints.clear();
+            self.cpu.write_watchpoints.clear();
+        }
+        for spec in args {
+            for addr in self.locations(spec)? {
+                self.cpu.breakpoints.remove(&addr);
+                self.cpu.read_watchpoints.remove(&addr);
+                self.cpu.write_watchpoints.remove(&addr);
+            }
+        }
+        self.add_breakpoints(&[])
+    }
+
+    fn fill(&mut self, args: &[String]) -> Result<String, String> {
+        if args.len() < 3 {
+            return Err("use f from to bytes...".to_string());
+        }
+        let (from, to) = self.range(&args[0], &args[1])?;
+        let pattern = args[2..].iter().map(|x| parse_byte(x)).collect::<Result<Vec<u8>, String>>()?;
+
+        for (i, addr) in (from..=to).enumerate() {
+            self.cpu.write(addr, pattern[i % pattern.len()]);
+        }
+        Ok(String::new())
+    }
+
+    fn hunt(&mut self, args: &[String]) -> Result<String, String> {
+        if args.len() < 3 {
+            return Err("use h from to bytes...".to_string());
+        }
+        let (from, to) = self.range(&args[0], &args[1])?;
+        let pattern = args[2..].iter()
+            .map(|x| match x.eq_ignore_ascii_case("xx") {
+                true => Ok(None),
+                false => parse_byte(x).map(Some),
+            })
+            .collect::<Result<Vec<Option<u8>>, String>>()?;
+
+        let last = (to as u32 + 1).saturating_sub(pattern.len() as u32);
+        let found: Vec<String> = (from as u32..=last)
+            .filter(|start| pattern.iter().enumerate()
+                .all(|(i, byte)| byte.map(|x| self.cpu.read((start + i as u32) as u16) == x).unwrap_or(true)))
+            .map(|x| format!("{:04x}", x))
+            .collect();
+
+        Ok(found.chunks(8).map(|x| x.join(" ")).collect::<Vec<_>>().join("\n"))
+    }
+
+    fn transfer(&mut self, args: &[String]) -> Result<String, String> {
+        let (from, to, dest) = match args {
+            [from, to, dest] => {
+                let (from, to) = self.range(from, to)?;
+                (from, to, self.addr(dest)?)
+            }
+            _ => return Err("use t from to dest".to_string()),
+        };
+
+        // copied first so overlapping ranges work
+        let data: Vec<u8> = (from..=to).map(|x| self.cpu.read(x)).collect();
+        for (i, byte) in data.iter().enumerate() {
+            self.cpu.write(dest.wrapping_add(i as u16), *byte);
+        }
+        Ok(String::new())
+    }
+
+    fn load(&mut self, args: &[String]) -> Result<String, String> {
+        let (file, addr) = match args {
+            [file] => (file, 0x8000),
+            [file, addr] => (file, self.addr(addr)?),
+            _ => return Err("use l file [addr]".to_string()),
+        };
+        let bytes = std::fs::read(file).map_err(|e| format!("{}: {}", file, e))?;
+
+        let loaded = formats::load_file(file, &bytes, &mut self.cpu, addr, StartAction::SetPc)?;
+        self.symbols.merge(&loaded.symbols);
+        self.dis_next = self.cpu.pc;
+        Ok(format!("loaded {} bytes from {}, PC is ${:04x}", loaded.len, file, self.cpu.pc))
+    }
+
+    fn save(&mut self, args: &[String]) -> Result<String, String> {
+        let (file, from, to) = match args {
+            [file, from, to] => {
+                let (from, to) = self.range(from, to)?;
+                (file, from, to)
+            }
+            _ => return Err("use s file from to".to_string()),
+        };
+
+        let image = LoadImage::from_ranges(&self.cpu, &[(from, to)], None);
+        let data: Vec<u8> = (from..=to).map(|x| self.cpu.read(x)).collect();
+        let ext = file.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+        let out = match &ext[..] {
+            "hex" | "ihx" | "ihex" => ihex::write(&image).into_bytes(),
+            "s19" | "s28" | "s37" | "srec" | "mot" => srec::write(&image).into_bytes(),
+            "prg" => [from as u8, (from >> 8) as u8].iter().chain(data.iter()).copied().collect(),
+            _ => data,
+        };
+
+        std::fs::write(file, out).map_err(|e| format!("{}: {}", file, e))?;
+        Ok(format!("saved ${:04x}-${:04x} to {}", from, to, file))
+    }
+
+    fn load_labels(&mut self, args: &[String]) -> Result<String, String> {
+        let file = match args {
+            [file] => file,
+            _ => return Err("use ll file".to_string()),
+        };
+        let text = std::fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+
+        let labels = match file.to_ascii_lowercase().ends_with(".dbg") {
+            true => {
+                let debug = debug_info::parse(&text)?;
+                let labels = debug.symbols.clone();
+                self.debug = Some(debug);
+                labels
+            }
+            false => {
+                let format = LabelFormat::from_extension(file)
+                    .ok_or_else(|| format!("{}: unknown label file type", file))?;
+                label_files::parse(format, &text, self.cpu.cartridge().map(|x| x.prg().len()))?
+            }
+        };
+
+        self.symbols.merge(&labels);
+        Ok(format!("{} labels loaded", labels.len()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor() -> Monitor {
+        Monitor::new(CPU6502::create_cpu_and_bus(0xc000))
+    }
+
+    #[test]
+    fn assemble_and_step() {
+        let mut mon = monitor();
+        mon.command("al 1000 sub").unwrap();
+
+        // a on its own line keeps assembling until an empty line
+        mon.command("a c000 ldx #$02").unwrap();
+        assert_eq!(mon.prompt(), ".c002 ");
+        mon.command("jsr sub").unwrap();
+        mon.command("").unwrap();
+        mon.command("a .sub inx").unwrap();
+        mon.command("rts").unwrap();
+        mon.command("").unwrap();
+        assert_eq!(mon.prompt(), "(C:$c000) ");
+
+        let listing = mon.command("d c000 c002").unwrap();
+        assert_eq!(listing, ">C:c000  a2 02     LDX #$02\n.C:c002  20 00 10  JSR sub");
+
+        // n runs the whole subroutine, z goes into it
+        mon.command("n 2").unwrap();
+        assert_eq!(mon.cpu.pc, 0xc005);
+        assert_eq!(mon.cpu.reg_x, 3);
+        mon.command("r pc=c002, x = 00").unwrap();
+        mon.command("z").unwrap();
+        assert_eq!(mon.cpu.pc, 0x1000);
+        mon.command("").unwrap();
+        assert_eq!(mon.cpu.reg_x, 1);
+
+        // ret runs the rest of it
+        assert!(mon.command("ret").unwrap().contains("c005"));
+        assert_eq!(mon.cpu.pc, 0xc005);
+    }
+
+    #[test]
+    fn memory_commands() {
+        let mut mon = monitor();
+        mon.command("f 2000 2007 01 02").unwrap();
+        assert_eq!(mon.command("m 2000 2007").unwrap(),
+            format!(">C:2000  {:<48} ........", "01 02 01 02 01 02 01 02"));
+
+        mon.command("t 2000 2003 2002").unwrap();
+        assert_eq!(mon.cpu.index_memory(0x2000, 0x2005).unwrap(), &[1, 2, 1, 2, 1, 2]);
+        assert_eq!(mon.command("h 2000 2007 02 xx 02").unwrap(), "2001 2003 2005");
+        assert!(mon.command("m 2007 2000").is_err());
+    }
+
+    #[test]
+    fn go_stops() {
+        let mut mon = monitor();
+        // c000 INC $10, c002 JMP $c000
+        mon.command("a c000 inc $10").unwrap();
+        mon.command("jmp $c000").unwrap();
+        mon.command("").unwrap();
+
+        mon.command("break c002").unwrap();
+        assert!(mon.command("g").unwrap().starts_with("breakpoint at $c002"));
+        mon.command("del").unwrap();
+
+        mon.command("watch store 10").unwrap();
+        assert!(mon.command("g").unwrap().starts_with("watchpoint, store to $0010"));
+        assert_eq!(mon.cpu.read(0x10), 2);
+
+        mon.command("del 10").unwrap();
+        mon.command("a c002 jmp $c002").unwrap();
+        mon.command("").unwrap();
+        assert!(mon.command("g c000").unwrap().starts_with("trapped at $c002"));
+    }
+}
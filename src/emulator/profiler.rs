@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::emulator::call_stack::Frame;
+use crate::emulator::symbols::SymbolTable;
+
+/*
+*   Cycle profiler, set cpu.profiler to Some(Profiler::new()) to turn it on
+*
+*   Counts executions and cycles per instruction address, and per subroutine
+*   (JSR target, or interrupt handler) using the CPU's call stack:
+*   inclusive is everything run while it's on the stack, exclusive only its
+*   own instructions. Code run outside any subroutine is the top level.
+*   collapsed() is the `a;b;c cycles` format flamegraph.pl and speedscope read.
+*/
+
+/// Per instruction address
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddrStats {
+    pub addr: u16,
+    pub count: u64,
+    pub cycles: u64,
+}
+
+/// Per subroutine
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SubStats {
+    pub addr: u16,
+    pub calls: u64,
+    pub inclusive: u64,
+    pub exclusive: u64,
+}
+
+pub struct Profiler {
+    counts: Vec<u64>,
+    cycles: Vec<u64>,
+    subs: BTreeMap<u16, SubStats>,
+    top_level: u64,
+
+    // cycles per call path, the current path's are added when it changes
+    stacks: HashMap<Vec<u16>, u64>,
+    path: Vec<u16>,
+    path_cycles: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler {
+            counts: vec![0; 0x10000],
+            cycles: vec![0; 0x10000],
+            subs: BTreeMap::new(),
+            top_level: 0,
+            stacks: HashMap::new(),
+            path: Vec::new(),
+            path_cycles: 0,
+        }
+    }
+}
+
+fn name(addr: u16, symbols: Option<&SymbolTable>) -> String {
+    match symbols.and_then(|x| x.name_of(addr)) {
+        Some(name) => name.to_string(),
+        None => format!("${:04X}", addr),
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The instruction at addr took cycles, frames is the call stack it ran in
+    pub fn record(&mut self, addr: u16, cycles: u32, frames: &[Frame]) {
+        let cycles = cycles as u64;
+        self.counts[addr as usize] += 1;
+        self.cycles[addr as usize] += cycles;
+
+        let same_path = self.path.len() == frames.len() && self.path.iter().zip(frames).all(|(a, f)| *a == f.target);
+        if !same_path {
+            self.flush();
+            // only a deeper call is a new one, returning to a caller isn't
+            if frames.len() > self.path.len() {
+                for frame in &frames[self.path.len()..] {
+                    self.subs.entry(frame.target).or_default().calls += 1;
+                }
+            }
+            self.path = frames.iter().map(|x| x.target).collect();
+        }
+        self.path_cycles += cycles;
+
+        match self.path.last() {
+            Some(addr) => self.subs.entry(*addr).or_default().exclusive += cycles,
+            None => self.top_level += cycles,
+        }
+        // recursion would count twice
+        for (i, addr) in self.path.iter().enumerate() {
+            if !self.path[..i].contains(addr) {
+                self.subs.get_mut(addr).unwrap().inclusive += cycles;
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.path_cycles > 0 {
+            *self.stacks.entry(self.path.clone()).or_default() += self.path_cycles;
+            self.path_cycles = 0;
+        }
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.cycles.iter().sum()
+    }
+
+    /// Cycles outside of any subroutine
+    pub fn top_level_cycles(&self) -> u64 {
+        self.top_level
+    }
+
+    /// Executed addresses, most cycles first
+    pub fn addresses(&self) -> Vec<AddrStats> {
+        let mut stats: Vec<AddrStats> = (0..0x10000)
+            .filter(|x| self.counts[*x] > 0)
+            .map(|x| AddrStats { addr: x as u16, count: self.counts[x], cycles: self.cycles[x] })
+            .collect();
+        stats.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.addr.cmp(&b.addr)));
+        stats
+    }
+
+    /// Executions and cycles at one address
+    pub fn at(&self, addr: u16) -> (u64, u64) {
+        (self.counts[addr as usize], self.cycles[addr as usize])
+    }
+
+    /// Most inclusive cycles first
+    pub fn subroutines(&self) -> Vec<SubStats> {
+        let mut stats: Vec<SubStats> = self.subs.iter().map(|(addr, x)| SubStats { addr: *addr, ..x.clone() }).collect();
+        stats.sort_by(|a, b| b.inclusive.cmp(&a.inclusive).then(a.addr.cmp(&b.addr)));
+        stats
+    }
+
+    /// Readable summary, the top `limit` addresses and every subroutine
+    pub fn report(&self, symbols: Option<&SymbolTable>, limit: usize) -> String {
+        let total = self.total_cycles().max(1);
+        let percent = |x: u64| x as f64 * 100. / total as f64;
+
+        let mut out = format!("{} cycles, {} at the top level\n\nSubroutines\n", self.total_cycles(), self.top_level);
+        out.push_str("      calls   inclusive          exclusive\n");
+        for sub in self.subroutines() {
+            out.push_str(&format!("{:>11} {:>11} {:5.1}% {:>11} {:5.1}%  {}\n", sub.calls, sub.inclusive,
+                percent(sub.inclusive), sub.exclusive, percent(sub.exclusive), name(sub.addr, symbols)));
+        }
+
+        out.push_str("\nAddresses\n      count      cycles\n");
+        for stat in self.addresses().iter().take(limit) {
+            let mut line = format!("{:>11} {:>11} {:5.1}%  ${:04X}", stat.count, stat.cycles, percent(stat.cycles), stat.addr);
+            if let Some(name) = symbols.and_then(|x| x.name_of(stat.addr)) {
+                line.push_str(&format!(" {}", name));
+            }
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Per address, most cycles first
+    pub fn csv(&self, symbols: Option<&SymbolTable>) -> String {
+        let mut out = String::from("address,symbol,count,cycles\n");
+        for stat in self.addresses() {
+            let name = symbols.and_then(|x| x.name_of(stat.addr)).unwrap_or("");
+            out.push_str(&format!("${:04X},{},{},{}\n", stat.addr, name, stat.count, stat.cycles));
+        }
+        out
+    }
+
+    /// Per subroutine, most inclusive cycles first
+    pub fn subroutine_csv(&self, symbols: Option<&SymbolTable>) -> String {
+        let mut out = String::from("address,symbol,calls,inclusive_cycles,exclusive_cycles\n");
+        for sub in self.subroutines() {
+            let name = symbols.and_then(|x| x.name_of(sub.addr)).unwrap_or("");
+            out.push_str(&format!("${:04X},{},{},{},{}\n", sub.addr, name, sub.calls, sub.inclusive, sub.exclusive));
+        }
+        out
+    }
+
+    /// One `top;outer;inner cycles` line per call path
+    pub fn collapsed(&self, symbols: Option<&SymbolTable>) -> String {
+        let mut stacks = self.stacks.clone();
+        if self.path_cycles > 0 {
+            *stacks.entry(self.path.clone()).or_default() += self.path_cycles;
+        }
+
+        let mut lines: Vec<String> = stacks.iter().map(|(path, cycles)| {
+            let names: Vec<String> = std::iter::once("top".to_string())
+                .chain(path.iter().map(|x| name(*x, symbols)))
+                .collect();
+            format!("{} {}", names.join(";"), cycles)
+        }).collect();
+        lines.sort();
+
+        let mut out = lines.join("\n");
+        out.push('\n');
+        out
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::emulator::cpu::{CPU6502, Limit, StopReason};
+    use super::*;
+
+    #[test]
+    fn subroutine_cycles() {
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+
+        /*
+            8000  JSR $8010   6
+            8003  JSR $8010   6
+            8006  BRK
+            8010  INX         2
+            8011  JSR $8020   6
+            8014  RTS         6
+            8020  RTS         6
+        */
+        for (addr, byte) in [(0x8000, 0x20), (0x8001, 0x10), (0x8002, 0x80), (0x8003, 0x20), (0x8004, 0x10),
+            (0x8005, 0x80), (0x8010, 0xe8), (0x8011, 0x20), (0x8012, 0x20), (0x8013, 0x80), (0x8014, 0x60),
+            (0x8020, 0x60)] {
+            cpu.write(addr, byte);
+        }
+        cpu.profiler = Some(Profiler::new());
+        assert_eq!(cpu.run(Limit::Instructions(100)), StopReason::Brk(0x8006));
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("outer", 0x8010);
+        let prof = cpu.profiler.as_ref().unwrap();
+
+        assert_eq!(prof.total_cycles(), 12 + 2 * (2 + 6 + 6 + 6));
+        assert_eq!(prof.top_level_cycles(), 12);
+        assert_eq!(prof.at(0x8010), (2, 4));
+
+        let subs = prof.subroutines();
+        assert_eq!(subs[0], SubStats { addr: 0x8010, calls: 2, inclusive: 40, exclusive: 28 });
+        assert_eq!(subs[1], SubStats { addr: 0x8020, calls: 2, inclusive: 12, exclusive: 12 });
+
+        assert_eq!(prof.collapsed(Some(&symbols)), "top 12\ntop;outer 28\ntop;outer;$8020 12\n");
+        let csv = prof.csv(None);
+        assert!(csv.starts_with("address,symbol,count,cycles\n$8011,,2,12\n"));
+        assert!(csv.contains("\n$8010,,2,4\n"));
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Names for addresses, used by the disassembler
+/// (and anything else showing addresses)
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    by_addr: BTreeMap<u16, String>,
+    by_name: HashMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// If an address already has a name, the old name is still
+    /// shown for it but both names can be looked up
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        if let Some(old) = self.by_name.insert(name.to_string(), addr) {
+            // renamed to a new address
+            if self.by_addr.get(&old).map(|x| x == name).unwrap_or(false) {
+                self.by_addr.remove(&old);
+            }
+        }
+        self.by_addr.entry(addr).or_insert_with(|| name.to_string());
+    }
+
+    /// Removes a name, returning its address
+    pub fn remove(&mut self, name: &str) -> Option<u16> {
+        let addr = self.by_name.remove(name)?;
+
+        if self.by_addr.get(&addr).map(|x| x == name).unwrap_or(false) {
+            self.by_addr.remove(&addr);
+
+            // fall back to another name for the same address
+            if let Some((other, _)) = self.by_name.iter().find(|(_, a)| **a == addr) {
+                self.by_addr.insert(addr, other.clone());
+            }
+        }
+        Some(addr)
+    }
+
+    /// Name shown for addr
+    pub fn name_of(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(|x| &x[..])
+    }
+
+    pub fn addr_of(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.by_addr.clear();
+        self.by_name.clear();
+    }
+
+    /// Names shown for addresses in from..=to, in address order
+    pub fn in_range(&self, from: u16, to: u16) -> impl Iterator<Item = (u16, &str)> {
+        self.by_addr.range(from..=to).map(|(a, n)| (*a, &n[..]))
+    }
+
+    /// Every (name, address), sorted by address then name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        let mut all: Vec<(&str, u16)> = self.by_name.iter().map(|(n, a)| (&n[..], *a)).collect();
+        all.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+        all.into_iter()
+    }
+
+    /// Adds every symbol of other, existing names for an address win
+    pub fn merge(&mut self, other: &SymbolTable) {
+        // names shown by other first, so they stay shown here
+        for (addr, name) in &other.by_addr {
+            self.insert(name, *addr);
+        }
+        for (name, addr) in other.iter() {
+            self.insert(name, addr);
+        }
+    }
+}
+
+impl<S: AsRef<str>> std::iter::FromIterator<(S, u16)> for SymbolTable {
+    fn from_iter<I: IntoIterator<Item = (S, u16)>>(iter: I) -> Self {
+        let mut table = SymbolTable::new();
+        for (name, addr) in iter {
+            table.insert(name.as_ref(), addr);
+        }
+        table
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_both_ways() {
+        let mut table = SymbolTable::new();
+        table.insert("reset", 0x8000);
+        table.insert("start", 0x8000);
+        table.insert("ptr", 0x0010);
+
+        assert_eq!(table.name_of(0x8000), Some("reset"));
+        assert_eq!(table.addr_of("start"), Some(0x8000));
+        assert_eq!(table.name_of(0x0010), Some("ptr"));
+        assert_eq!(table.len(), 3);
+
+        table.remove("reset");
+        assert_eq!(table.name_of(0x8000), Some("start"));
+
+        // moving a name
+        table.insert("ptr", 0x0020);
+        assert_eq!(table.name_of(0x0010), None);
+        assert_eq!(table.name_of(0x0020), Some("ptr"));
+
+        // merging keeps which alias is shown
+        let mut aliased = SymbolTable::new();
+        aliased.insert("nmi", 0xf016);
+        aliased.insert("LF016", 0xf016);
+        let mut merged = SymbolTable::new();
+        merged.merge(&aliased);
+        assert_eq!(merged.name_of(0xf016), Some("nmi"));
+    }
+}
@@ -31,6 +31,7 @@ mod tests {
         let bus = Box::new(bus::Bus::new());
         let mut cpu = CPU6502::new(bus);
 
+        #[allow(clippy::single_match)]
         match cpu.load(vec![5, 0xa9]) {
             Err(x) => panic!("Error: {}", x),
 
@@ -44,12 +45,14 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn lda_imm() {
         let bus = Box::new(bus::Bus::new());
         let mut cpu = CPU6502::new(bus);
 
         let y = cpu.load(vec![0xa9, 255]);
 
+        #[allow(clippy::single_match)]
         match y {
             Err(x) => panic!("Error: {}", x),
 
@@ -76,6 +79,7 @@ mod tests {
 
         let y = cpu.load(vec![0xad, 0x78, 0x77]);
 
+        #[allow(clippy::single_match)]
         match y {
             Err(x) => panic!("Error: {}", x),
 
@@ -106,6 +110,7 @@ mod tests {
 
         let cycles = 2 + 4;
 
+        #[allow(clippy::single_match)]
         match cpu.load(pro) {
             Err(x) => panic!("{}",x),
             Ok(()) => ()
@@ -138,6 +143,7 @@ mod tests {
         // enough cycles for first two instrs
         let cycles = 2 + (4+1);
 
+        #[allow(clippy::single_match)]
         match cpu.load(pro) {
             Err(z) => panic!("{}", z),
             Ok(()) => ()
@@ -158,11 +164,13 @@ mod tests {
         let mut cpu = CPU6502::new(bus);
 
 
+        #[allow(clippy::neg_multiply)]
         let jmp:i8 = -1*0xa;
         let byte_jmp = jmp as u8;
         // SEC called first
         let pro = vec![0x38, 0x90, 0x0, 0x18, 0x90, byte_jmp];
 
+        #[allow(clippy::single_match)]
         match cpu.load(pro) {
             Err(z) => panic!("{}", z),
             Ok(()) => ()
@@ -197,6 +205,7 @@ mod tests {
         let mut cpu = CPU6502::new(bus);
 
 
+        #[allow(clippy::neg_multiply)]
         let jmp : i8 = -1*0x7;
         let byte_jmp = jmp as u8;
         // SEC called first
@@ -204,6 +213,7 @@ mod tests {
 
         cpu.write(0x7fff, 0x38);
 
+        #[allow(clippy::single_match)]
         match cpu.load(pro) {
             Err(z) => panic!("{}", z),
             Ok(()) => ()
@@ -255,6 +265,7 @@ mod tests {
         
         let pro = vec![adc_imm, val, adc_imm,overflow_val];
 
+        #[allow(clippy::single_match)]
         match cpu.load(pro) {
             Err(z) => panic!("{}", z),
             Ok(()) => ()
@@ -298,6 +309,7 @@ mod tests {
 
         let pro = vec![sbc_imm, val, sbc_imm, val_2, sbc_imm, val_3];
 
+        #[allow(clippy::single_match)]
         match cpu.load(pro) {
             Err(z) => panic!("{}", z),
             Ok(()) => ()
@@ -337,6 +349,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::identity_op)]
     fn addrmode_sta_tst() {
 
         let bus = Box::new(bus::Bus::new());
@@ -353,6 +366,7 @@ mod tests {
         let pro = vec![sta_zp0, 0x0, sta_zpx, 0x00, sta_ab, 0x02, 0x00, sta_abx, 0x00, 0x11,
                         sta_aby, 0x00, 0x12, sta_inx, 0x00, sta_iny, 0x00];
 
+        #[allow(clippy::single_match)]
         match cpu.load(pro) {
             Err(z) => panic!("{}", z),
             Ok(()) => ()
@@ -399,6 +413,7 @@ mod tests {
 
         let pro = vec![jmp_abs, 0x05, 0x80, 0x0, 0x0, jmp_ind, 0x01, 0x80];
 
+        #[allow(clippy::single_match)]
         match cpu.load(pro) {
             Err(z) => panic!("{}", z),
             Ok(()) => ()
@@ -428,6 +443,7 @@ mod tests {
 
         let pro = vec![txs, pha, php, plp, pla];
 
+        #[allow(clippy::single_match)]
         match cpu.load(pro) {
             Err(z) => panic!("{}", z),
             Ok(()) => ()
@@ -471,6 +487,7 @@ mod tests {
 
         let pro = vec![jsr, 0x05, 0x80, 0x00, 0x00, rts];
 
+        #[allow(clippy::single_match)]
         match cpu.load(pro) {
             Err(z) => panic!("{}", z),
             Ok(()) => ()
@@ -503,6 +520,7 @@ mod tests {
 
         let pro = vec![nop, brk, 0, 0, rti];
 
+        #[allow(clippy::single_match)]
         match cpu.load(pro) {
             Err(z) => panic!("{}", z),
             Ok(()) => ()
@@ -534,4 +552,176 @@ mod tests {
 
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn call_subroutine_test(){
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+        cpu.stk_ptr = 0xff;
+
+        // LDA #$42, STA $10, RTS
+        for (i, byte) in [0xa9, 0x42, 0x85, 0x10, 0x60].iter().enumerate() {
+            cpu.write(0x9000 + i as u16, *byte);
+        }
+        assert_eq!(cpu.call_subroutine(0x9000, 100), Ok(()));
+        assert_eq!(cpu.read(0x10), 0x42);
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.stk_ptr, 0xff);
+
+        // JMP to itself never returns
+        for (i, byte) in [0x4c, 0x00, 0x90].iter().enumerate() {
+            cpu.write(0x9000 + i as u16, *byte);
+        }
+        assert!(cpu.call_subroutine(0x9000, 100).is_err());
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.stk_ptr, 0xff);
+    }
+
+    #[test]
+    fn breakpoint_test(){
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+
+        // INX x3, BRK
+        for (i, byte) in [0xe8, 0xe8, 0xe8, 0x00].iter().enumerate() {
+            cpu.write(0x8000 + i as u16, *byte);
+        }
+        cpu.breakpoints.insert(0x8002);
+
+        assert_eq!(cpu.run(Limit::Instructions(100)), StopReason::Breakpoint(0x8002));
+        assert_eq!(cpu.pc, 0x8002);
+        assert_eq!(cpu.reg_x, 2);
+
+        // continues from the breakpoint
+        assert_eq!(cpu.run(Limit::Instructions(100)), StopReason::Brk(0x8003));
+        assert_eq!(cpu.reg_x, 3);
+        assert_eq!(cpu.pc, 0x8003);
+    }
+
+    #[test]
+    fn execute_step_cycles_test(){
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+        let start = cpu.get_total_cycles();
+
+        // LDA #$01 (2), LDA $12ff,X with X=1 crosses a page (4+1), INX (2)
+        for (i, byte) in [0xa9, 0x01, 0xbd, 0xff, 0x12, 0xe8].iter().enumerate() {
+            cpu.write(0x8000 + i as u16, *byte);
+        }
+        cpu.reg_x = 1;
+        assert_eq!(cpu.execute_step(), 2);
+        assert_eq!(cpu.execute_step(), 5);
+        assert_eq!(cpu.execute_step(), 2);
+        assert_eq!(cpu.get_total_cycles().wrapping_sub(start), 9);
+    }
+
+    #[test]
+    fn watchpoint_test(){
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+
+        // LDA $10, STA $11, INC $12, BRK
+        for (i, byte) in [0xa5, 0x10, 0x85, 0x11, 0xe6, 0x12, 0x00].iter().enumerate() {
+            cpu.write(0x8000 + i as u16, *byte);
+        }
+        cpu.read_watchpoints.insert(0x11);
+        cpu.write_watchpoints.insert(0x11);
+        cpu.read_watchpoints.insert(0x12);
+
+        // STA doesn't read its operand
+        assert_eq!(cpu.run(Limit::Instructions(100)), StopReason::Watchpoint(0x11, Access::Write));
+        assert_eq!(cpu.pc, 0x8004);
+        assert_eq!(cpu.watch_hit(), Some((0x11, Access::Write)));
+
+        assert_eq!(cpu.run(Limit::Instructions(100)), StopReason::Watchpoint(0x12, Access::Read));
+        assert_eq!(cpu.pc, 0x8006);
+        assert_eq!(cpu.last_accesses(), &[(0x12, Access::Read), (0x12, Access::Write)]);
+
+        assert_eq!(cpu.run(Limit::Instructions(100)), StopReason::Brk(0x8006));
+        assert_eq!(cpu.pc, 0x8006);
+    }
+
+    #[test]
+    fn step_over_out_test(){
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+
+        /*
+            8000  JSR $8008
+            8003  INX
+            8004  BRK
+            8008  PHA
+            8009  PLA
+            800a  INY
+            800b  RTS
+        */
+        for (addr, byte) in [(0x8000, 0x20), (0x8001, 0x08), (0x8002, 0x80), (0x8003, 0xe8), (0x8004, 0x00),
+            (0x8008, 0x48), (0x8009, 0x68), (0x800a, 0xc8), (0x800b, 0x60)] {
+            cpu.write(addr, byte);
+        }
+
+        assert_eq!(cpu.step_over(100), Ok(()));
+        assert_eq!((cpu.pc, cpu.reg_y, cpu.stk_ptr), (0x8003, 1, 0xFF));
+        assert_eq!(cpu.step_over(100), Ok(()));
+        assert_eq!((cpu.pc, cpu.reg_x), (0x8004, 1));
+
+        // PLA pops above the PHA but isn't a return
+        cpu.pc = 0x8000;
+        cpu.execute_step();
+        assert_eq!(cpu.step_out(100), Ok(()));
+        assert_eq!((cpu.pc, cpu.reg_y), (0x8003, 2));
+
+        cpu.pc = 0x8000;
+        assert_eq!(cpu.run_to(0x800a, 100), Ok(()));
+        assert_eq!(cpu.pc, 0x800a);
+
+        cpu.breakpoints.insert(0x800b);
+        assert_eq!(cpu.run_to(0x8004, 100), Err(StopReason::Breakpoint(0x800b)));
+        assert_eq!(cpu.run_to(0x8008, 100), Err(StopReason::Brk(0x8004)));
+        assert_eq!(cpu.pc, 0x8004);
+    }
+
+    #[test]
+    fn run_stop_reasons_test(){
+        let mut cpu = CPU6502::create_cpu_and_bus(0x8000);
+
+        // INX, JMP *
+        for (i, byte) in [0xe8, 0x4c, 0x01, 0x80].iter().enumerate() {
+            cpu.write(0x8000 + i as u16, *byte);
+        }
+        assert_eq!(cpu.run(Limit::Instructions(1)), StopReason::BudgetExhausted);
+        assert_eq!(cpu.pc, 0x8001);
+        assert_eq!(cpu.run(Limit::Cycles(100)), StopReason::Trap(0x8001));
+
+        // BNE to itself
+        cpu.pc = 0x8010;
+        cpu.write(0x8010, 0xd0);
+        cpu.write(0x8011, 0xfe);
+        assert_eq!(cpu.run(Limit::Cycles(100)), StopReason::Trap(0x8010));
+
+        // LDA #$01 (2) is all that fits
+        cpu.pc = 0x8020;
+        for (i, byte) in [0xa9, 0x01, 0xa9, 0x02, 0x02].iter().enumerate() {
+            cpu.write(0x8020 + i as u16, *byte);
+        }
+        assert_eq!(cpu.run(Limit::Cycles(2)), StopReason::BudgetExhausted);
+        assert_eq!(cpu.reg_a, 1);
+        assert_eq!(cpu.run(Limit::Cycles(100)), StopReason::Jam(0x8024));
+
+        cpu.pc = 0x8030;
+        cpu.write(0x8030, 0x03);
+        assert_eq!(cpu.run(Limit::Cycles(100)), StopReason::IllegalOpcode(0x8030, 0x03));
+        assert_eq!(cpu.pc, 0x8030);
+
+        // unofficial NOP abs,X isn't run as a 1 byte IDK
+        cpu.write(0x8030, 0x1c);
+        assert_eq!(cpu.run(Limit::Cycles(100)), StopReason::IllegalOpcode(0x8030, 0x1c));
+        assert_eq!(cpu.pc, 0x8030);
+
+        cpu.pc = 0x8020;
+        cpu.write(0x8024, 0x00);
+        #[allow(deprecated)]
+        let stop = cpu.run_until_brk();
+        assert_eq!(stop, StopReason::Brk(0x8024));
+
+        // the hook can stop it before an instruction
+        cpu.pc = 0x8000;
+        assert_eq!(cpu.run_until(Limit::Cycles(100), |cpu| cpu.pc == 0x8001), StopReason::Done);
+        assert_eq!(cpu.pc, 0x8001);
+    }
+
+}